@@ -1,144 +1,180 @@
 //! SyMark: Static site generator for SiYuan notes.
 //! Includes a D3.js visualization for exploring note connections.
 
+use aho_corasick::{AhoCorasick, AhoCorasickBuilder, MatchKind};
 use base64::decode;
 use chrono::Local;
+use deunicode::deunicode;
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::PngEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::imageops::FilterType;
+use image::metadata::Orientation;
+use image::{DynamicImage, ImageDecoder, ImageReader};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
-use std::collections::{HashMap, HashSet};
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
 use std::env;
 use std::fs::{self, File};
 use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::time::Instant;
-
-/// Smart truncation function that limits text to a maximum number of sentences
-/// and finds good stopping points at sentence boundaries
-fn smart_truncate_excerpt(text: &str, max_sentences: usize) -> String {
-    if text.trim().is_empty() {
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Smart truncation function that limits text to a maximum number of sentences and a
+/// maximum width in grapheme clusters, stopping at sentence boundaries where it can and
+/// otherwise at the end of a word. Used for every excerpt, tooltip and description
+fn smart_truncate_excerpt(text: &str, max_sentences: usize, max_width: usize) -> String {
+    let text = text.trim();
+    if text.is_empty() {
         return String::new();
     }
 
-    let mut result = String::new();
     let mut sentence_count = 0;
-    let mut chars = text.char_indices().peekable();
+    // Byte offset and width of the text up to the last complete sentence
     let mut last_sentence_end = 0;
-    let max_chars = 200; // Hard limit for run-on sentences
-
-    while let Some((i, ch)) = chars.next() {
-        result.push(ch);
-
-        // Hard limit to prevent extremely long previews
-        if result.len() >= max_chars {
-            // Find a good breaking point near the limit
-            let mut truncate_pos = max_chars.min(result.len());
-            while truncate_pos > max_chars / 2 && truncate_pos > 0 {
-                if let Some(ch) = result.chars().nth(truncate_pos - 1) {
-                    if matches!(ch, ' ' | ',' | ';' | ':' | '.' | '!' | '?') {
-                        break;
-                    }
-                }
-                truncate_pos -= 1;
-            }
-            result.truncate(truncate_pos);
-            if !result.ends_with('.') && !result.ends_with('!') && !result.ends_with('?') {
-                result.push_str("...");
-            }
-            break;
+    let mut last_sentence_width = 0;
+    let mut width = 0;
+    let mut graphemes = text.grapheme_indices(true).peekable();
+
+    while let Some((i, grapheme)) = graphemes.next() {
+        if width == max_width {
+            return truncate_at_width(text, i, last_sentence_end, last_sentence_width, max_width);
         }
+        width += 1;
 
-        // Check for sentence endings
-        if matches!(ch, '.' | '!' | '?') {
-            // Look ahead to see if this is truly the end of a sentence
-            let mut is_sentence_end = true;
-            let mut peek_pos = i + ch.len_utf8();
-
-            // Check for common abbreviations or decimals
-            if ch == '.' {
-                // Look back for common abbreviations
-                let before_period = text[..i].trim_end();
-                if before_period.ends_with(" Mr")
-                    || before_period.ends_with(" Mrs")
-                    || before_period.ends_with(" Dr")
-                    || before_period.ends_with(" Prof")
-                    || before_period.ends_with(" etc")
-                    || before_period.ends_with(" vs")
-                {
-                    is_sentence_end = false;
-                }
+        let ch = grapheme.chars().next().unwrap_or(' ');
+        if !is_sentence_punctuation(ch) {
+            continue;
+        }
 
-                // Check if it's a decimal number
-                if let Some((_, next_ch)) = chars.peek() {
-                    if next_ch.is_ascii_digit() {
-                        is_sentence_end = false;
-                    }
-                }
+        // The sentence ends after runs like "?!", "..." or "。」"
+        let mut end = i + grapheme.len();
+        while let Some(&(j, next)) = graphemes.peek() {
+            let next_ch = next.chars().next().unwrap_or(' ');
+            if width == max_width
+                || !(is_sentence_punctuation(next_ch) || CLOSING_PUNCTUATION.contains(&next_ch))
+            {
+                break;
             }
+            width += 1;
+            end = j + next.len();
+            graphemes.next();
+        }
 
-            // Look ahead for whitespace and capital letter (typical sentence pattern)
-            if is_sentence_end {
-                let remaining_text = &text[peek_pos..];
-                let next_chars: Vec<char> = remaining_text.chars().take(3).collect();
-
-                if !next_chars.is_empty() {
-                    // Must have whitespace after punctuation
-                    if !next_chars[0].is_whitespace() {
-                        is_sentence_end = false;
-                    } else if next_chars.len() > 1 {
-                        // Look for capital letter after whitespace
-                        let mut found_letter = false;
-                        for &nc in &next_chars[1..] {
-                            if nc.is_alphabetic() {
-                                found_letter = true;
-                                if !nc.is_uppercase() {
-                                    is_sentence_end = false;
-                                }
-                                break;
-                            } else if !nc.is_whitespace() {
-                                break;
-                            }
-                        }
-                        // If no letter found soon after, might not be sentence end
-                        if !found_letter && next_chars.len() == 3 {
-                            is_sentence_end = false;
-                        }
-                    }
-                } else {
-                    // End of text after punctuation - this is a sentence end
-                    is_sentence_end = true;
-                }
+        if is_sentence_end(text, i, ch, end) {
+            sentence_count += 1;
+            last_sentence_end = end;
+            last_sentence_width = width;
+            if sentence_count >= max_sentences {
+                return text[..end].to_string();
             }
+        }
+    }
 
-            if is_sentence_end {
-                sentence_count += 1;
-                last_sentence_end = result.len();
+    text.to_string()
+}
 
-                if sentence_count >= max_sentences {
-                    // Truncate to the end of the last complete sentence
-                    result.truncate(last_sentence_end);
-                    break;
-                }
-            }
+// Sentence-ending punctuation of Chinese and Japanese text, which needs no space after it
+const CJK_SENTENCE_ENDS: [char; 4] = ['。', '！', '？', '．'];
+
+// Closing quotes and brackets that belong to the sentence before them
+const CLOSING_PUNCTUATION: [char; 12] = [
+    '"', '\'', ')', ']', '”', '’', '」', '』', '）', '】', '》', '〉',
+];
+
+// Abbreviations whose period does not end a sentence
+const ABBREVIATIONS: [&str; 9] = ["Mr", "Mrs", "Ms", "Dr", "Prof", "etc", "vs", "e.g", "i.e"];
+
+fn is_sentence_punctuation(ch: char) -> bool {
+    matches!(ch, '.' | '!' | '?' | '…') || CJK_SENTENCE_ENDS.contains(&ch)
+}
+
+// Whether the punctuation `ch` at byte `start` ends a sentence, given the text after the
+// punctuation run that finishes at byte `end`
+fn is_sentence_end(text: &str, start: usize, ch: char, end: usize) -> bool {
+    let before = &text[..start];
+    if CJK_SENTENCE_ENDS.contains(&ch)
+        || (ch == '…' && before.chars().next_back().is_some_and(is_cjk))
+    {
+        // Except inside a quote the sentence carries on after, as in 「行こう！」と言った。
+        let closes_quote = text[..end]
+            .chars()
+            .next_back()
+            .is_some_and(|c| CLOSING_PUNCTUATION.contains(&c));
+        let continues = text[end..]
+            .chars()
+            .next()
+            .is_some_and(|c| is_cjk(c) && c.is_alphanumeric());
+        return !(closes_quote && continues);
+    }
+    if ch == '.' {
+        let word = before.rsplit(char::is_whitespace).next().unwrap_or("");
+        if ABBREVIATIONS.contains(&word) {
+            return false;
         }
     }
 
-    // If we still have no sentences and it's getting long, just cut it
-    if sentence_count == 0 && result.len() > 150 {
-        let mut truncate_pos = 150;
-        // Find a good breakpoint (space, comma, semicolon)
-        while truncate_pos > 100 && truncate_pos < result.len() {
-            if let Some(ch) = result.chars().nth(truncate_pos) {
-                if matches!(ch, ' ' | ',' | ';' | ':') {
-                    break;
-                }
-            }
-            truncate_pos -= 1;
-        }
-        result.truncate(truncate_pos);
-        result.push_str("...");
+    // Western punctuation needs a space and then anything but a lowercase letter, which
+    // also rules out decimals like 3.14 and names like example.com
+    let mut after = text[end..].chars();
+    match after.next() {
+        None => true,
+        Some(next) if is_cjk(next) => true,
+        Some(next) if next.is_whitespace() => after
+            .find(|c| !c.is_whitespace())
+            .is_none_or(|c| !c.is_lowercase()),
+        Some(_) => false,
+    }
+}
+
+// Cuts text that ran past the width limit at byte `limit`: at the last sentence end if that
+// keeps at least half the width, otherwise at the last word boundary, with an ellipsis
+fn truncate_at_width(
+    text: &str,
+    limit: usize,
+    sentence_end: usize,
+    sentence_width: usize,
+    max_width: usize,
+) -> String {
+    if sentence_end > 0 && sentence_width * 2 >= max_width {
+        return text[..sentence_end].to_string();
     }
 
-    result.trim().to_string()
+    // Word boundaries fall between CJK characters too, so this only backs up over
+    // alphabetic words, and a single word longer than the limit is cut where it is
+    let word_start = text
+        .split_word_bound_indices()
+        .map(|(i, _)| i)
+        .take_while(|&i| i <= limit)
+        .last()
+        .filter(|&i| i > 0)
+        .unwrap_or(limit);
+
+    let mut excerpt = text[..word_start]
+        .trim_end_matches(|c: char| {
+            c.is_whitespace()
+                || matches!(
+                    c,
+                    ',' | ';'
+                        | ':'
+                        | '-'
+                        | '–'
+                        | '—'
+                        | '('
+                        | '、'
+                        | '，'
+                        | '；'
+                        | '：'
+                        | '（'
+                        | '「'
+                        | '『'
+                )
+        })
+        .to_string();
+    excerpt.push('…');
+    excerpt
 }
 
 #[derive(Debug, Deserialize)]
@@ -177,6 +213,14 @@ struct Properties {
     #[serde(default)]
     #[serde(rename = "parent-style")]
     parent_style: Option<String>,
+    #[serde(default)]
+    #[serde(rename = "custom-slug")]
+    custom_slug: String,
+    #[serde(default)]
+    alias: String,
+    #[serde(default)]
+    #[serde(rename = "custom-redirect-from")]
+    custom_redirect_from: String,
 }
 
 #[derive(Debug, Deserialize, Default)]
@@ -220,6 +264,418 @@ struct Block {
     TaskListItemChecked: bool,
 }
 
+/// Build settings read from `symark.json` in the working directory.
+/// Every section is optional, so a missing or partial file keeps the defaults.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+struct Config {
+    // Directory for data kept between builds, such as resized images
+    cache_dir: String,
+    reachability: ReachabilityConfig,
+    assets: AssetsConfig,
+    images: ImagesConfig,
+    transclusion: TransclusionConfig,
+    tags: TagsConfig,
+    urls: UrlsConfig,
+    redirects: RedirectsConfig,
+    not_found: NotFoundConfig,
+    mentions: MentionsConfig,
+    virtual_refs: VirtualRefsConfig,
+    graph: GraphConfig,
+    local_graph: LocalGraphConfig,
+    reading: ReadingConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            cache_dir: ".symark-cache".to_string(),
+            reachability: ReachabilityConfig::default(),
+            assets: AssetsConfig::default(),
+            images: ImagesConfig::default(),
+            transclusion: TransclusionConfig::default(),
+            tags: TagsConfig::default(),
+            urls: UrlsConfig::default(),
+            redirects: RedirectsConfig::default(),
+            not_found: NotFoundConfig::default(),
+            mentions: MentionsConfig::default(),
+            virtual_refs: VirtualRefsConfig::default(),
+            graph: GraphConfig::default(),
+            local_graph: LocalGraphConfig::default(),
+            reading: ReadingConfig::default(),
+        }
+    }
+}
+
+/// Publish-by-reachability: only notes linked from the seeds are built
+#[derive(Debug, Deserialize, Default)]
+#[serde(default)]
+struct ReachabilityConfig {
+    // Note IDs to start the walk from
+    seed_ids: Vec<String>,
+    // Every note carrying one of these tags is also a seed (e.g. "index")
+    seed_tags: Vec<String>,
+    // Maximum number of block-ref/transclusion hops from a seed, unlimited if unset
+    max_hops: Option<usize>,
+}
+
+/// Controls which files from the notebook's assets directories are published
+#[derive(Debug, Deserialize, Default)]
+#[serde(default)]
+struct AssetsConfig {
+    // Copy every asset instead of only those referenced by published notes
+    copy_unreferenced: bool,
+}
+
+/// Processing applied to JPEG, PNG and WebP assets when they are copied
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+struct ImagesConfig {
+    // Remove EXIF, XMP and IPTC metadata (GPS position, camera, timestamps)
+    strip_metadata: bool,
+    // Metadata fields that survive stripping: "orientation" and/or "copyright"
+    keep_metadata: Vec<String>,
+    // Write width/height and generate resized variants for a srcset
+    responsive: bool,
+    // Widths of the resized variants, only those smaller than the original are generated
+    widths: Vec<u32>,
+    // Also generate WebP variants of PNG images, offered through a <picture> element
+    webp: bool,
+    // The sizes attribute describing how wide images are displayed
+    sizes: String,
+    // Add loading="lazy" and decoding="async" to images
+    lazy_loading: bool,
+}
+
+impl Default for ImagesConfig {
+    fn default() -> Self {
+        Self {
+            strip_metadata: true,
+            // Without the orientation tag, rotated phone photos would display sideways
+            keep_metadata: vec!["orientation".to_string()],
+            responsive: true,
+            widths: vec![480, 960, 1440],
+            webp: false,
+            // Matches the content column of the default theme
+            sizes: "(max-width: 700px) 100vw, 700px".to_string(),
+            lazy_loading: true,
+        }
+    }
+}
+
+/// Limits applied when rendering embedded (transcluded) blocks and notes
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+struct TransclusionConfig {
+    // Maximum number of embeds nested inside each other on one page
+    max_depth: usize,
+}
+
+impl Default for TransclusionConfig {
+    fn default() -> Self {
+        Self { max_depth: 8 }
+    }
+}
+
+/// Options for the tags.html index of every tag
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+struct TagsConfig {
+    // How tags.html groups tags: "none", "letter" (first letter) or "hierarchy" (nested tags)
+    group_by: String,
+}
+
+impl Default for TagsConfig {
+    fn default() -> Self {
+        Self {
+            group_by: "none".to_string(),
+        }
+    }
+}
+
+/// How the file names (and so the URLs) of notes and tags are built
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+struct UrlsConfig {
+    // Name note pages by "id" or by "title"; a custom-slug attribute always wins
+    note_slugs: String,
+    // Spell non-ASCII letters in ASCII ("中文" becomes "Zhong-Wen") instead of percent-encoding
+    transliterate: bool,
+    // "flat" writes slug.html files, "pretty" writes slug/index.html and links to slug/
+    style: String,
+    // Prefix for every link when the site is hosted under a sub-path, e.g. "/notes"
+    base_path: String,
+}
+
+impl Default for UrlsConfig {
+    fn default() -> Self {
+        Self {
+            note_slugs: "id".to_string(),
+            transliterate: true,
+            style: "flat".to_string(),
+            base_path: String::new(),
+        }
+    }
+}
+
+/// Redirect pages at the URLs notes were published under before
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+struct RedirectsConfig {
+    // Old path -> note ID (or URL) it should redirect to
+    map: HashMap<String, String>,
+    // Remember where every note was published and redirect when its URL changes
+    track_moves: bool,
+    // Also write a _redirects file for static hosts such as Netlify and Cloudflare Pages
+    redirects_file: bool,
+}
+
+impl Default for RedirectsConfig {
+    fn default() -> Self {
+        Self {
+            map: HashMap::new(),
+            track_moves: true,
+            redirects_file: false,
+        }
+    }
+}
+
+/// Extras listed on the 404.html page below the note tagged 404 or the default message
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+struct NotFoundConfig {
+    // Suggest notes whose title or URL shares words with the missing URL
+    suggestions: bool,
+    // Number of recently updated notes to list, 0 for none
+    recent_notes: usize,
+}
+
+impl Default for NotFoundConfig {
+    fn default() -> Self {
+        Self {
+            suggestions: true,
+            recent_notes: 5,
+        }
+    }
+}
+
+/// Plain-text mentions of a note's title or aliases in other notes, without a block reference
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+struct MentionsConfig {
+    // List the mentions of each note in an "Unlinked mentions" section
+    unlinked: bool,
+    // Deprecated: turns on virtual_refs for every occurrence
+    auto_link: bool,
+    // Shortest title or alias that counts as a mention, in characters
+    min_length: usize,
+}
+
+impl Default for MentionsConfig {
+    fn default() -> Self {
+        Self {
+            unlinked: true,
+            auto_link: false,
+            min_length: 3,
+        }
+    }
+}
+
+/// Virtual references: note titles and aliases in the text of other notes become links
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+struct VirtualRefsConfig {
+    enabled: bool,
+    // Link the "first" occurrence of each note on a page, or "every" occurrence
+    occurrences: String,
+    // Only link these titles, aliases or note IDs; every note when empty
+    include: Vec<String>,
+    // Never link these titles, aliases or note IDs
+    exclude: Vec<String>,
+    // Shortest title or alias that is linked, in characters
+    min_length: usize,
+}
+
+/// The link graph drawn on graph.html
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+struct GraphConfig {
+    // Add a node for every tag, linked from the notes that carry it
+    tag_nodes: bool,
+    // Files to write the graph to next to graph.html: "json", "graphml", "gexf" and "dot"
+    exports: Vec<String>,
+    // Where node positions are computed: "client" in the browser, "server" at build time, or
+    // "auto" to compute them at build time once the graph has `layout_threshold` nodes
+    layout: String,
+    layout_threshold: usize,
+    // Steps of the build-time force simulation
+    layout_iterations: usize,
+    // Colors given to tags, before those from the theme's colors.json
+    palette: Vec<String>,
+    // Tag -> color, overriding the theme and the palette
+    tag_colors: HashMap<String, String>,
+}
+
+impl Default for GraphConfig {
+    fn default() -> Self {
+        Self {
+            tag_nodes: false,
+            exports: ["json", "graphml", "gexf", "dot"]
+                .map(String::from)
+                .to_vec(),
+            layout: "auto".to_string(),
+            layout_threshold: 1000,
+            layout_iterations: 300,
+            palette: Vec::new(),
+            tag_colors: HashMap::new(),
+        }
+    }
+}
+
+/// The small graph of a note's neighbors placed on note pages with {{local_graph}}
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+struct LocalGraphConfig {
+    enabled: bool,
+    // How far to follow links from the note: 1 or 2
+    hops: usize,
+    // Most neighbors to show, nearest first
+    max_nodes: usize,
+    // Write the graph data to local-graph/<note-id>.json instead of inlining it in the page
+    json_files: bool,
+}
+
+impl Default for LocalGraphConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            hops: 1,
+            max_nodes: 30,
+            json_files: false,
+        }
+    }
+}
+
+/// Reading speeds used for {{reading_time}}
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+struct ReadingConfig {
+    words_per_minute: usize,
+    // Chinese and Japanese text is counted by character rather than by word
+    cjk_characters_per_minute: usize,
+}
+
+impl Default for ReadingConfig {
+    fn default() -> Self {
+        Self {
+            words_per_minute: 200,
+            cjk_characters_per_minute: 400,
+        }
+    }
+}
+
+impl Default for VirtualRefsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            occurrences: "first".to_string(),
+            include: Vec::new(),
+            exclude: Vec::new(),
+            min_length: 3,
+        }
+    }
+}
+
+impl ImagesConfig {
+    fn keeps(&self, field: &str) -> bool {
+        self.keep_metadata.iter().any(|kept| kept == field)
+    }
+
+    // EXIF tags preserved when metadata is stripped
+    fn kept_exif_tags(&self) -> Vec<u16> {
+        let mut tags = Vec::new();
+        if self.keeps("orientation") {
+            tags.push(EXIF_TAG_ORIENTATION);
+        }
+        if self.keeps("copyright") {
+            tags.push(EXIF_TAG_COPYRIGHT);
+        }
+        tags
+    }
+}
+
+impl ReachabilityConfig {
+    fn is_enabled(&self) -> bool {
+        !self.seed_ids.is_empty() || !self.seed_tags.is_empty()
+    }
+}
+
+/// Tag colors a theme can set in its colors.json
+#[derive(Debug, Deserialize, Default)]
+#[serde(default)]
+struct ThemeColors {
+    palette: Vec<String>,
+    tags: HashMap<String, String>,
+}
+
+// Colors for tags without one in the configuration or theme
+const DEFAULT_TAG_PALETTE: [&str; 10] = [
+    "#4285F4", // Blue
+    "#EA4335", // Red
+    "#FBBC05", // Yellow
+    "#34A853", // Green
+    "#9C27B0", // Purple
+    "#FF9800", // Orange
+    "#00BCD4", // Cyan
+    "#795548", // Brown
+    "#607D8B", // Blue-gray
+    "#E91E63", // Pink
+];
+
+fn load_theme_colors(theme_dir: &Path) -> ThemeColors {
+    let path = theme_dir.join("colors.json");
+    let content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(_) => return ThemeColors::default(),
+    };
+
+    match serde_json::from_str::<ThemeColors>(&content) {
+        Ok(colors) => colors,
+        Err(e) => {
+            eprintln!("Error parsing theme colors {:?}: {}", path, e);
+            ThemeColors::default()
+        }
+    }
+}
+
+fn load_config(path: &Path) -> Config {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        // No config file is fine, everything has a default
+        Err(_) => return Config::default(),
+    };
+
+    match serde_json::from_str::<Config>(&content) {
+        Ok(mut config) => {
+            println!("Loaded configuration from {:?}", path);
+            // mentions.auto_link linked every mention before virtual references replaced it
+            if config.mentions.auto_link {
+                println!("Warning: mentions.auto_link is deprecated, use virtual_refs instead");
+                if !config.virtual_refs.enabled {
+                    config.virtual_refs.enabled = true;
+                    config.virtual_refs.occurrences = "every".to_string();
+                    config.virtual_refs.min_length = config.mentions.min_length;
+                }
+            }
+            config
+        }
+        Err(e) => {
+            eprintln!("Error parsing config file {:?}: {}", path, e);
+            Config::default()
+        }
+    }
+}
+
 fn get_style_class(style: &str, is_inline: bool) -> (Option<String>, bool) {
     // First determine the base class by looking at the style characteristics
     let base_class = if style.contains("var(--b3-card-info-background)") {
@@ -288,6 +744,75 @@ fn read_template(path: &str) -> String {
     }
 }
 
+/// Build-wide data needed while rendering blocks, besides the notes themselves
+struct RenderContext<'a> {
+    config: &'a Config,
+    // Responsive image data keyed by path relative to the assets directory
+    image_catalog: &'a HashMap<String, ResponsiveImage>,
+    urls: &'a SiteUrls,
+    // Note ID of every block, for resolving references and embeds
+    block_index: &'a HashMap<String, String>,
+    // IDs of the page and the embeds currently being rendered, outermost first
+    transclusion_stack: RefCell<Vec<String>>,
+    // Embed chains already warned about, so each problem is reported once
+    reported_transclusions: RefCell<HashSet<String>>,
+    // Titles and aliases to link as virtual references, if enabled
+    virtual_refs: Option<&'a MentionMatcher>,
+    // Notes already linked by a virtual reference on the current page
+    linked_virtual_refs: RefCell<HashSet<String>>,
+    // Set while rendering a heading, whose text never gets virtual references
+    in_heading: Cell<bool>,
+    // Links between notes for the local graphs, if enabled
+    link_graph: Option<&'a LinkGraph>,
+}
+
+impl RenderContext<'_> {
+    // Returns a placeholder to render instead of embedding `content_id` when the embed
+    // would loop back into itself or nest deeper than the configured limit
+    fn check_transclusion(&self, content_id: &str) -> Option<String> {
+        let stack = self.transclusion_stack.borrow();
+        let max_depth = self.config.transclusion.max_depth;
+
+        let circular_start = stack.iter().position(|id| id == content_id);
+        if circular_start.is_none() && stack.len() <= max_depth {
+            return None;
+        }
+
+        let mut chain = stack[circular_start.unwrap_or(0)..].to_vec();
+        chain.push(content_id.to_string());
+        let (warning, placeholder) = if circular_start.is_some() {
+            (
+                format!("Circular embed {}", chain.join(" -> ")),
+                format!("Circular embed: {}", chain.join(" &rarr; ")),
+            )
+        } else {
+            (
+                format!(
+                    "Embed depth limit of {} reached at {}",
+                    max_depth,
+                    chain.join(" -> ")
+                ),
+                format!(
+                    "Embed not shown: nested more than {} levels deep",
+                    max_depth
+                ),
+            )
+        };
+
+        if self
+            .reported_transclusions
+            .borrow_mut()
+            .insert(warning.clone())
+        {
+            println!("Warning: {}", warning);
+        }
+        Some(format!(
+            "<p class=\"transclusion-error\"><em>{}</em></p>",
+            placeholder
+        ))
+    }
+}
+
 struct TocItem {
     id: String,
     text: String,
@@ -303,6 +828,11 @@ struct MarginInfoTracker {
     linked_mentions: HashMap<String, HashSet<(String, String)>>,
     // Maps note_id -> set of (source_note_id, source_note_title) that link to the entire page
     page_linked_mentions: HashMap<String, HashSet<(String, String)>>,
+    // Maps content_id -> (source_note_id, context_block_id) for every block reference, where the
+    // context block is the paragraph, heading or table containing the reference
+    reference_contexts: HashMap<String, Vec<(String, String)>>,
+    // Maps note_id -> (source_note_id, context_block_id) for plain-text mentions of its title
+    unlinked_mentions: HashMap<String, Vec<(String, String)>>,
 }
 
 impl MarginInfoTracker {
@@ -311,6 +841,30 @@ impl MarginInfoTracker {
             transclusions: HashMap::new(),
             linked_mentions: HashMap::new(),
             page_linked_mentions: HashMap::new(),
+            reference_contexts: HashMap::new(),
+            unlinked_mentions: HashMap::new(),
+        }
+    }
+
+    fn add_unlinked_mention(&mut self, note_id: &str, source_note_id: &str, context_id: &str) {
+        let contexts = self
+            .unlinked_mentions
+            .entry(note_id.to_string())
+            .or_default();
+        let context = (source_note_id.to_string(), context_id.to_string());
+        if !contexts.contains(&context) {
+            contexts.push(context);
+        }
+    }
+
+    fn add_reference_context(&mut self, content_id: &str, source_note_id: &str, context_id: &str) {
+        let contexts = self
+            .reference_contexts
+            .entry(content_id.to_string())
+            .or_default();
+        let context = (source_note_id.to_string(), context_id.to_string());
+        if !contexts.contains(&context) {
+            contexts.push(context);
         }
     }
 
@@ -400,6 +954,7 @@ fn collect_margin_info(notes_map: &HashMap<String, Note>) -> MarginInfoTracker {
             &mut tracker,
             source_note_id,
             source_note_title,
+            "",
         );
     }
 
@@ -415,24 +970,30 @@ fn collect_info_from_blocks(
     tracker: &mut MarginInfoTracker,
     source_note_id: &str,
     source_note_title: &str,
+    context_id: &str,
 ) {
     for block in blocks {
-        if block.Type == "NodeBlockQueryEmbed" {
-            // Find the NodeBlockQueryEmbedScript child that contains the query
-            if let Some(script_block) = block
-                .Children
-                .iter()
-                .find(|child| child.Type == "NodeBlockQueryEmbedScript")
-            {
-                // Extract the block ID from the query
-                if let Some(id_start) = script_block.Data.find("id='") {
-                    let id_start = id_start + 4; // Skip "id='"
-                    if let Some(id_end) = script_block.Data[id_start..].find('\'') {
-                        let content_id = &script_block.Data[id_start..id_start + id_end];
-                        tracker.add_transclusion(content_id, source_note_id, source_note_title);
-                    }
-                }
-            }
+        // The innermost paragraph, heading or table is shown as the context of a reference
+        let context_id = if !block.ID.is_empty()
+            && matches!(
+                block.Type.as_str(),
+                "NodeParagraph" | "NodeHeading" | "NodeTable"
+            ) {
+            block.ID.as_str()
+        } else {
+            context_id
+        };
+
+        if block.Type == "NodeTextMark"
+            && block.TextMarkType.split(' ').any(|t| t == "block-ref")
+            && !block.TextMarkBlockRefID.is_empty()
+            && !context_id.is_empty()
+        {
+            tracker.add_reference_context(&block.TextMarkBlockRefID, source_note_id, context_id);
+        }
+
+        if let Some(content_id) = embed_target_id(block) {
+            tracker.add_transclusion(content_id, source_note_id, source_note_title);
         }
 
         // Check for block references and page links in text marks
@@ -440,11 +1001,35 @@ fn collect_info_from_blocks(
 
         // Recursively check children
         if !block.Children.is_empty() {
-            collect_info_from_blocks(&block.Children, tracker, source_note_id, source_note_title);
+            collect_info_from_blocks(
+                &block.Children,
+                tracker,
+                source_note_id,
+                source_note_title,
+                context_id,
+            );
         }
     }
 }
 
+// The ID of the block or note an embed block shows, taken from its query
+fn embed_target_id(block: &Block) -> Option<&str> {
+    if block.Type != "NodeBlockQueryEmbed" {
+        return None;
+    }
+
+    // Find the NodeBlockQueryEmbedScript child that contains the query
+    let script_block = block
+        .Children
+        .iter()
+        .find(|child| child.Type == "NodeBlockQueryEmbedScript")?;
+
+    // Extract the block ID from the query
+    let id_start = script_block.Data.find("id='")? + 4; // Skip "id='"
+    let id_end = script_block.Data[id_start..].find('\'')?;
+    Some(&script_block.Data[id_start..id_start + id_end])
+}
+
 // Helper function to recursively collect linked mentions and page links from text marks
 fn collect_text_marks_for_mentions(
     block: &Block,
@@ -491,885 +1076,4295 @@ fn finalize_page_vs_block_links(
     }
 }
 
-const BACK_NAVIGATION_HTML: &str = r#"<a href="index.html" class="back-link">Back to home<svg xmlns="http://www.w3.org/2000/svg" width="16" height="16" viewBox="0 0 24 24" fill="none" stroke="currentColor" stroke-width="2" stroke-linecap="round" stroke-linejoin="round" class="lucide lucide-arrow-left"><path d="m12 19-7-7 7-7"></path><path d="M19 12H5"></path></svg></a>"#;
+// Whether a character is Chinese, Japanese or Korean, scripts written without spaces
+fn is_cjk(c: char) -> bool {
+    matches!(c as u32,
+        0x1100..=0x11FF       // Hangul Jamo
+        | 0x2E80..=0x2FDF     // CJK radicals
+        | 0x3000..=0x30FF     // CJK punctuation, Hiragana, Katakana
+        | 0x3100..=0x31FF     // Bopomofo, Hangul compatibility Jamo, Katakana extensions
+        | 0x3400..=0x4DBF     // CJK Extension A
+        | 0x4E00..=0x9FFF     // CJK Unified Ideographs
+        | 0xAC00..=0xD7AF     // Hangul syllables
+        | 0xF900..=0xFAFF     // CJK compatibility ideographs
+        | 0xFF00..=0xFFEF     // Fullwidth forms
+        | 0x20000..=0x2FA1F // CJK Extensions B-F and supplements
+    )
+}
 
-fn extract_toc_items(blocks: &[Block], headings: &mut Vec<TocItem>, id_counter: &mut usize) {
-    for block in blocks {
-        if block.Type == "NodeHeading" {
-            let level = block.HeadingLevel.max(1).min(6);
+// Characters of scripts written without spaces between words, which are counted one by one.
+// Korean puts spaces between words and fullwidth Latin is still Latin, so both count as words.
+fn is_unspaced_cjk(c: char) -> bool {
+    is_cjk(c)
+        && !matches!(c as u32,
+            0x1100..=0x11FF       // Hangul Jamo
+            | 0x3130..=0x318F     // Hangul compatibility Jamo
+            | 0xAC00..=0xD7AF     // Hangul syllables
+            | 0xFF01..=0xFF5E // Fullwidth ASCII
+        )
+}
 
-            let id = if !block.ID.is_empty() {
-                block.ID.clone()
-            } else {
-                format!("heading-{}", id_counter)
-            };
-            *id_counter += 1;
+// Word and character counts of a page's readable text
+#[derive(Debug, Default, Clone, Copy)]
+struct TextStats {
+    words: usize,
+    // Chinese and Japanese characters, counted apart from words because the text has no spaces
+    // between words
+    cjk_characters: usize,
+    // Every character that is not whitespace
+    characters: usize,
+}
 
-            let mut text = String::new();
-            for child in &block.Children {
-                if child.Type == "NodeText" {
-                    text.push_str(&child.Data);
-                } else if child.Type == "NodeTextMark" {
-                    text.push_str(&child.TextMarkTextContent);
+impl TextStats {
+    fn from_text(text: &str) -> Self {
+        let mut stats = TextStats::default();
+        let mut in_word = false;
+        for c in text.chars() {
+            if c.is_whitespace() {
+                in_word = false;
+                continue;
+            }
+            stats.characters += 1;
+            if is_unspaced_cjk(c) {
+                if c.is_alphanumeric() {
+                    stats.cjk_characters += 1;
+                }
+                in_word = false;
+            } else if c.is_alphanumeric() {
+                if !in_word {
+                    stats.words += 1;
+                    in_word = true;
                 }
+            } else if !(in_word && matches!(c, '\'' | '\u{2019}' | '-' | '_')) {
+                // Apostrophes and hyphens inside a word keep it whole: "don't", "well-known"
+                in_word = false;
             }
+        }
+        stats
+    }
 
-            headings.push(TocItem { id, text, level });
+    // Counts the text a reader sees in rendered HTML
+    fn from_html(html: &str) -> Self {
+        Self::from_text(&readable_text(html))
+    }
+
+    fn add(&mut self, other: TextStats) {
+        self.words += other.words;
+        self.cjk_characters += other.cjk_characters;
+        self.characters += other.characters;
+    }
+
+    // Words with each CJK character counted as one word
+    fn word_count(&self) -> usize {
+        self.words + self.cjk_characters
+    }
+
+    // Whole minutes to read, at least one for any text at all
+    fn reading_minutes(&self, config: &ReadingConfig) -> usize {
+        if self.word_count() == 0 {
+            return 0;
         }
+        let minutes = self.words as f64 / config.words_per_minute.max(1) as f64
+            + self.cjk_characters as f64 / config.cjk_characters_per_minute.max(1) as f64;
+        (minutes.ceil() as usize).max(1)
+    }
 
-        extract_toc_items(&block.Children, headings, id_counter);
+    // Meta line such as "3 min read · 540 words"
+    fn meta_html(&self, config: &ReadingConfig) -> String {
+        let words = self.word_count();
+        format!(
+            "<span class=\"meta-tag reading-tag\">{} min read &middot; {} {}</span>",
+            self.reading_minutes(config),
+            words,
+            if words == 1 { "word" } else { "words" }
+        )
     }
-}
 
-fn naturalize_date(date_str: &str) -> String {
-    if date_str.is_empty() {
-        return String::from("Unknown date");
+    // Fills {{reading_time}}, {{word_count}} and {{character_count}}
+    fn fill_template(&self, html: &str, config: &ReadingConfig) -> String {
+        html.replace(
+            "{{reading_time}}",
+            &self.reading_minutes(config).to_string(),
+        )
+        .replace("{{word_count}}", &self.word_count().to_string())
+        .replace("{{character_count}}", &self.characters.to_string())
     }
+}
 
-    if date_str.len() >= 8 && date_str.chars().take(8).all(|c| c.is_digit(10)) {
-        let year = &date_str[0..4];
-        let month_num: u32 = date_str[4..6].parse().unwrap_or(1);
-        let day: u32 = date_str[6..8].parse().unwrap_or(1);
+// Reading statistics of every generated note, for the totals on list pages
+struct ReadingStats<'a> {
+    config: &'a ReadingConfig,
+    notes: HashMap<String, TextStats>,
+}
 
-        let month_name = match month_num {
-            1 => "January",
-            2 => "February",
-            3 => "March",
-            4 => "April",
-            5 => "May",
-            6 => "June",
-            7 => "July",
-            8 => "August",
-            9 => "September",
-            10 => "October",
-            11 => "November",
-            12 => "December",
-            _ => "Unknown",
-        };
+impl ReadingStats<'_> {
+    fn total<'n>(&self, ids: impl IntoIterator<Item = &'n String>) -> TextStats {
+        let mut total = TextStats::default();
+        for id in ids {
+            if let Some(stats) = self.notes.get(id) {
+                total.add(*stats);
+            }
+        }
+        total
+    }
+}
 
-        // Add ordinal suffix to day
-        let day_with_suffix = match day {
-            1 | 21 | 31 => format!("{}st", day),
-            2 | 22 => format!("{}nd", day),
-            3 | 23 => format!("{}rd", day),
-            _ => format!("{}th", day),
-        };
+// Extracts the text of rendered HTML, leaving out scripts, graphics and the hidden
+// tooltip previews of block references
+fn readable_text(html: &str) -> String {
+    const SKIPPED: [&str; 5] = ["script", "style", "svg", "noscript", "template"];
+    const INLINE: [&str; 13] = [
+        "a", "b", "i", "u", "s", "em", "strong", "mark", "span", "sub", "sup", "code", "kbd",
+    ];
 
-        let formatted_date = format!("{} {}, {}", month_name, day_with_suffix, year);
+    let mut text = String::with_capacity(html.len() / 2);
+    // Element whose content is skipped, with how deeply it is nested in itself
+    let mut skipping: Option<(String, usize)> = None;
+    let mut rest = html;
 
-        if date_str.len() > 8 && date_str.chars().nth(8) == Some('T') {
-            if date_str.len() >= 14 {
-                // Has at least hour and minute
-                let hour: u32 = date_str[9..11].parse().unwrap_or(0);
-                let minute: u32 = date_str[11..13].parse().unwrap_or(0);
+    while let Some(start) = rest.find('<') {
+        if skipping.is_none() {
+            push_decoded_text(&mut text, &rest[..start]);
+        }
+        let Some(end) = rest[start..].find('>') else {
+            rest = "";
+            break;
+        };
+        let tag = &rest[start + 1..start + end];
+        rest = &rest[start + end + 1..];
+
+        let closing = tag.starts_with('/');
+        let self_closing = tag.ends_with('/');
+        let name = tag
+            .trim_start_matches('/')
+            .chars()
+            .take_while(|c| c.is_ascii_alphanumeric())
+            .collect::<String>()
+            .to_ascii_lowercase();
+        if name.is_empty() {
+            continue; // Comments and doctypes
+        }
 
-                let hour12 = if hour == 0 {
-                    12
-                } else if hour > 12 {
-                    hour - 12
+        if let Some((skipped_name, depth)) = skipping.as_mut() {
+            if *skipped_name == name && !self_closing {
+                if closing {
+                    *depth -= 1;
                 } else {
-                    hour
-                };
-
-                let am_pm = if hour >= 12 { "PM" } else { "AM" };
-                return format!("{} at {}:{:02} {}", formatted_date, hour12, minute, am_pm);
+                    *depth += 1;
+                }
+                if *depth == 0 {
+                    skipping = None;
+                }
             }
+            continue;
         }
 
-        return formatted_date;
+        if !closing
+            && !self_closing
+            && (SKIPPED.contains(&name.as_str()) || tag.contains("class=\"right bottom\""))
+        {
+            skipping = Some((name, 1));
+            continue;
+        }
+        if !INLINE.contains(&name.as_str()) {
+            text.push(' ');
+        }
+    }
+    if skipping.is_none() {
+        push_decoded_text(&mut text, rest);
     }
+    text
+}
 
-    // ISO format date handling (YYYY-MM-DD)
-    if date_str.len() >= 10
-        && date_str.chars().nth(4) == Some('-')
-        && date_str.chars().nth(7) == Some('-')
-    {
-        // Extract from ISO format
-        if let Ok(year) = date_str[0..4].parse::<u32>() {
-            if let Ok(month) = date_str[5..7].parse::<u32>() {
-                if let Ok(day) = date_str[8..10].parse::<u32>() {
-                    // Get month name
-                    let month_name = match month {
-                        1 => "January",
-                        2 => "February",
-                        3 => "March",
-                        4 => "April",
-                        5 => "May",
-                        6 => "June",
-                        7 => "July",
-                        8 => "August",
-                        9 => "September",
-                        10 => "October",
-                        11 => "November",
-                        12 => "December",
-                        _ => "Unknown",
-                    };
+// Appends HTML text with the common entities decoded and any other entity read as a space
+fn push_decoded_text(text: &mut String, html: &str) {
+    let mut rest = html;
+    while let Some(amp) = rest.find('&') {
+        text.push_str(&rest[..amp]);
+        let Some(end) = rest[amp..]
+            .find(';')
+            .filter(|&end| end <= 10)
+            .map(|end| amp + end + 1)
+        else {
+            text.push('&');
+            rest = &rest[amp + 1..];
+            continue;
+        };
+        text.push_str(match &rest[amp..end] {
+            "&amp;" => "&",
+            "&lt;" => "<",
+            "&gt;" => ">",
+            "&quot;" => "\"",
+            "&#39;" | "&apos;" | "&rsquo;" => "\u{2019}",
+            _ => " ",
+        });
+        rest = &rest[end..];
+    }
+    text.push_str(rest);
+}
 
-                    // Add ordinal suffix to day
-                    let day_with_suffix = match day {
-                        1 | 21 | 31 => format!("{}st", day),
-                        2 | 22 => format!("{}nd", day),
-                        3 | 23 => format!("{}rd", day),
-                        _ => format!("{}th", day),
-                    };
+// Lowercases text without changing the byte offset of any character, so matches found in the
+// folded text can be cut from the original
+fn fold_case(text: &str) -> String {
+    let mut folded = String::with_capacity(text.len());
+    for c in text.chars() {
+        let lower: String = c.to_lowercase().collect();
+        if lower.len() == c.len_utf8() {
+            folded.push_str(&lower);
+        } else {
+            folded.push(c);
+        }
+    }
+    folded
+}
 
-                    // Check if there's time information
-                    if date_str.len() >= 16 && date_str.chars().nth(10) == Some('T') {
-                        // Has time component
-                        if let Ok(hour) = date_str[11..13].parse::<u32>() {
-                            if let Ok(minute) = date_str[14..16].parse::<u32>() {
-                                // Format with AM/PM
-                                let hour12 = if hour == 0 {
-                                    12
-                                } else if hour > 12 {
-                                    hour - 12
-                                } else {
-                                    hour
-                                };
+/// Finds the titles and aliases of notes in plain text with a single pass over the text,
+/// however many notes there are
+struct MentionMatcher {
+    automaton: AhoCorasick,
+    // Pattern index -> ID of the note the title or alias belongs to
+    note_ids: Vec<String>,
+}
 
-                                let am_pm = if hour >= 12 { "PM" } else { "AM" };
-                                return format!(
-                                    "{} {}, {} at {}:{:02} {}",
-                                    month_name, day_with_suffix, year, hour12, minute, am_pm
-                                );
-                            }
-                        }
-                    }
+impl MentionMatcher {
+    // Matches titles and aliases of at least `min_length` characters. Non-empty `include` keeps
+    // only the listed names or note IDs, and `exclude` drops them.
+    fn new(
+        notes_map: &HashMap<String, Note>,
+        min_length: usize,
+        include: &[String],
+        exclude: &[String],
+    ) -> Option<Self> {
+        let listed = |list: &[String], name: &str, id: &str| {
+            list.iter()
+                .any(|entry| entry.trim() == id || fold_case(entry.trim()) == name)
+        };
 
-                    return format!("{} {}, {}", month_name, day_with_suffix, year);
+        // Lowercased name -> note, or None when several notes share the name
+        let mut names: HashMap<String, Option<&str>> = HashMap::new();
+        for note in notes_map.values() {
+            let aliases = note.Properties.alias.split(',');
+            for name in std::iter::once(note.Properties.title.as_str()).chain(aliases) {
+                let name = fold_case(name.trim());
+                if name.chars().count() < min_length.max(1)
+                    || (!include.is_empty() && !listed(include, &name, &note.ID))
+                    || listed(exclude, &name, &note.ID)
+                {
+                    continue;
+                }
+                let owner = names.entry(name).or_insert(Some(note.ID.as_str()));
+                if owner.is_some_and(|id| id != note.ID) {
+                    *owner = None;
                 }
             }
         }
+
+        let mut patterns: Vec<_> = names
+            .into_iter()
+            .filter_map(|(name, id)| id.map(|id| (name, id.to_string())))
+            .collect();
+        if patterns.is_empty() {
+            return None;
+        }
+        patterns.sort();
+
+        let automaton = AhoCorasickBuilder::new()
+            .match_kind(MatchKind::LeftmostLongest)
+            .build(patterns.iter().map(|(name, _)| name))
+            .ok()?;
+
+        Some(Self {
+            automaton,
+            note_ids: patterns.into_iter().map(|(_, id)| id).collect(),
+        })
     }
 
-    // If we can't parse it in any of our formats, return original
-    return date_str.to_string();
+    // Mentions in `text` as (start, end, note ID). Matches must start and end at a word
+    // boundary, except next to CJK characters, which aren't separated by spaces.
+    fn find<'a>(&'a self, text: &str) -> Vec<(usize, usize, &'a str)> {
+        let is_word_char = |c: char| c.is_alphanumeric() && !is_cjk(c);
+
+        self.automaton
+            .find_iter(&fold_case(text))
+            .filter(|m| {
+                let before = text[..m.start()].chars().next_back();
+                let after = text[m.end()..].chars().next();
+                let first = text[m.start()..].chars().next();
+                let last = text[..m.end()].chars().next_back();
+                let splits_word = |inner: Option<char>, outer: Option<char>| {
+                    inner.is_some_and(is_word_char) && outer.is_some_and(is_word_char)
+                };
+                !splits_word(first, before) && !splits_word(last, after)
+            })
+            .map(|m| {
+                (
+                    m.start(),
+                    m.end(),
+                    self.note_ids[m.pattern().as_usize()].as_str(),
+                )
+            })
+            .collect()
+    }
 }
 
-fn generate_toc_html(headings: &[TocItem]) -> String {
-    let mut toc_html = String::new();
+// Records where each note's title or aliases appear as plain text in other notes, leaving out
+// the mentions that virtual references turn into links
+fn collect_unlinked_mentions(
+    notes_map: &HashMap<String, Note>,
+    matcher: &MentionMatcher,
+    virtual_refs: Option<&MentionMatcher>,
+    config: &VirtualRefsConfig,
+    tracker: &mut MarginInfoTracker,
+) {
+    for (source_note_id, note) in notes_map {
+        let mut mentions = Vec::new();
+        let mut scan = virtual_refs.map(|matcher| VirtualRefScan {
+            matcher,
+            every: config.occurrences == "every",
+            linked: HashSet::new(),
+            links: HashSet::new(),
+        });
+        collect_mentions_from_blocks(
+            &note.Children,
+            matcher,
+            scan.as_mut(),
+            source_note_id,
+            "",
+            false,
+            &mut mentions,
+        );
 
-    for heading in headings {
-        // Only include h2 and h3 in the TOC
-        if heading.level >= 2 && heading.level <= 3 {
-            let class = if heading.level == 3 {
-                " toc-subitem"
-            } else {
-                ""
-            };
-            toc_html.push_str(&format!(
-                "<li class=\"toc-item{}\"><a class=\"toc-link\" href=\"#{}\">{}",
-                class, heading.id, heading.text
-            ));
-            toc_html.push_str("</a></li>\n");
+        for (note_id, context_id) in mentions {
+            let linked = scan.as_ref().is_some_and(|scan| {
+                scan.links
+                    .contains(&(note_id.to_string(), context_id.to_string()))
+            });
+            if !linked {
+                tracker.add_unlinked_mention(note_id, source_note_id, context_id);
+            }
         }
     }
+}
 
-    if toc_html.is_empty() {
-        toc_html = "<li class=\"toc-item\"><em>No headings found</em></li>\n".to_string();
-    }
+// The virtual references rendering will add to a page, found the same way virtual_ref_text
+// finds them so they can be told apart from unlinked mentions before any page is rendered
+struct VirtualRefScan<'a> {
+    matcher: &'a MentionMatcher,
+    every: bool,
+    // Notes already linked on the page
+    linked: HashSet<String>,
+    // (linked note ID, context block ID) of every virtual reference on the page
+    links: HashSet<(String, String)>,
+}
 
-    toc_html
+impl VirtualRefScan<'_> {
+    fn scan(&mut self, text: &str, page_id: &str, context_id: &str) {
+        let matcher = self.matcher;
+        for (_, _, note_id) in matcher.find(text) {
+            if note_id == page_id || (!self.every && self.linked.contains(note_id)) {
+                continue;
+            }
+            self.linked.insert(note_id.to_string());
+            self.links
+                .insert((note_id.to_string(), context_id.to_string()));
+        }
+    }
 }
 
-fn filter_index_tag(tags_str: &str) -> String {
-    tags_str
-        .split(',')
-        .map(|t| t.trim())
-        .filter(|t| *t != "index")
-        .collect::<Vec<_>>()
-        .join(", ")
+// Text that rendering passes through virtual_ref_text
+fn virtual_ref_source_text(block: &Block) -> Option<&str> {
+    match block.Type.as_str() {
+        "NodeText" => Some(&block.Data),
+        "NodeTextMark" => match block.TextMarkType.as_str() {
+            "strong" | "strong text" | "u" | "s" | "mark" | "text" | "text strong" => {
+                Some(&block.TextMarkTextContent)
+            }
+            "em" if block.TextMarkAHref.is_empty() => Some(&block.TextMarkTextContent),
+            _ => None,
+        },
+        _ => None,
+    }
 }
 
-fn main() -> std::io::Result<()> {
-    let start_time = Instant::now();
-    let mut page_count = 0;
+fn collect_mentions_from_blocks<'a>(
+    blocks: &'a [Block],
+    matcher: &'a MentionMatcher,
+    mut scan: Option<&mut VirtualRefScan>,
+    source_note_id: &str,
+    context_id: &'a str,
+    in_heading: bool,
+    mentions: &mut Vec<(&'a str, &'a str)>,
+) {
+    for block in blocks {
+        let context_id = if !block.ID.is_empty()
+            && matches!(
+                block.Type.as_str(),
+                "NodeParagraph" | "NodeHeading" | "NodeTable"
+            ) {
+            block.ID.as_str()
+        } else {
+            context_id
+        };
+        let in_heading = in_heading || block.Type == "NodeHeading";
 
-    println!("Starting SyMark generator...");
+        let scanned_text = virtual_ref_source_text(block).filter(|_| !in_heading);
+        if let (Some(scan), Some(text)) = (scan.as_deref_mut(), scanned_text) {
+            scan.scan(text, source_note_id, context_id);
+        }
 
-    // Get theme from command line arguments or use default
-    let args: Vec<String> = env::args().collect();
-    let theme_name = if args.len() > 1 {
-        args[1].clone()
-    } else {
-        "default".to_string()
+        if block.Type == "NodeText" && !context_id.is_empty() {
+            for (_, _, note_id) in matcher.find(&block.Data) {
+                if note_id != source_note_id {
+                    mentions.push((note_id, context_id));
+                }
+            }
+        }
+
+        collect_mentions_from_blocks(
+            &block.Children,
+            matcher,
+            scan.as_deref_mut(),
+            source_note_id,
+            context_id,
+            in_heading,
+            mentions,
+        );
+    }
+}
+
+// Escapes plain text for HTML, turning the note titles and aliases in it into virtual
+// references when they are enabled. Heading text is left alone.
+fn virtual_ref_text(text: &str, render_context: &RenderContext) -> String {
+    let matcher = match render_context.virtual_refs {
+        Some(matcher) if !render_context.in_heading.get() => matcher,
+        _ => return escape_html(text),
     };
 
-    println!("Using theme: {}", theme_name);
+    // The page being rendered doesn't link to itself
+    let page_id = render_context.transclusion_stack.borrow().first().cloned();
+    let every = render_context.config.virtual_refs.occurrences == "every";
+    let mut linked = render_context.linked_virtual_refs.borrow_mut();
 
-    let themes_dir = PathBuf::from("themes");
-    let theme_dir = themes_dir.join(&theme_name);
+    let mut html = String::new();
+    let mut last = 0;
+    for (start, end, note_id) in matcher.find(text) {
+        if page_id.as_deref() == Some(note_id) || (!every && linked.contains(note_id)) {
+            continue;
+        }
+        linked.insert(note_id.to_string());
 
-    // Create themes directory if it doesn't exist
-    if !themes_dir.exists() {
-        println!("Creating themes directory...");
-        fs::create_dir_all(&themes_dir)?;
+        html.push_str(&escape_html(&text[last..start]));
+        html.push_str(&format!(
+            "<a href=\"{}\" class=\"virtual-ref\">{}</a>",
+            render_context.urls.note_url(note_id),
+            escape_html(&text[start..end])
+        ));
+        last = end;
     }
+    html.push_str(&escape_html(&text[last..]));
+    html
+}
 
-    // If the selected theme doesn't exist, create it
-    if !theme_dir.exists() {
-        println!("Creating theme directory: {:?}", theme_dir);
-        fs::create_dir_all(&theme_dir)?;
+// Page names that generated pages already use, so notes can't take them
+const RESERVED_SLUGS: [&str; 8] = ["index", "all", "tags", "graph", "404", "tag", "assets", "b"];
+
+/// Output file names and links for every page, note, tag and asset, decided once so that each
+/// generator and link agrees on them and no two pages share a file
+struct SiteUrls {
+    // Note ID -> slug, without the .html extension
+    note_slugs: HashMap<String, String>,
+    // Tag -> slug, without the tag_ prefix and .html extension
+    tag_slugs: HashMap<String, String>,
+    // Slugs keep non-ASCII letters, so links to them are percent-encoded
+    encode_links: bool,
+    // Pages are written as name/index.html and linked as name/
+    pretty: bool,
+    // Prefix of every link: "" for relative links, otherwise the base path ending in "/"
+    root: String,
+}
 
-        // Check if default theme exists to copy from
-        let default_theme_dir = themes_dir.join("default");
-        if default_theme_dir.exists() && fs::read_dir(&default_theme_dir)?.next().is_some() {
-            println!("Copying files from default theme to new theme directory...");
+impl SiteUrls {
+    // File of a generated page such as "all" or "graph", relative to the output directory
+    fn page_file(&self, name: &str) -> String {
+        if self.pretty && name != "index" && name != "404" {
+            format!("{}/index.html", name)
+        } else {
+            format!("{}.html", name)
+        }
+    }
 
-            // Copy page.html
-            if let Ok(html_template) = fs::read_to_string(default_theme_dir.join("page.html")) {
-                let cleaned_html_template = remove_zero_width_spaces(&html_template);
-                let mut html_file = File::create(theme_dir.join("page.html"))?;
-                html_file.write_all(cleaned_html_template.as_bytes())?;
-            }
+    // Path of a page relative to the site root, as it appears in links
+    fn page_path(&self, name: &str) -> String {
+        if !self.pretty {
+            format!("{}.html", name)
+        } else if name == "index" {
+            String::new()
+        } else {
+            format!("{}/", name)
+        }
+    }
 
-            // Copy styles.css
-            if let Ok(css_template) = fs::read_to_string(default_theme_dir.join("styles.css")) {
-                let cleaned_css_template = remove_zero_width_spaces(&css_template);
-                let mut css_file = File::create(theme_dir.join("styles.css"))?;
-                css_file.write_all(cleaned_css_template.as_bytes())?;
-            }
+    fn note_stem(&self, id: &str) -> String {
+        self.note_slugs
+            .get(id)
+            .map_or(id, |slug| slug.as_str())
+            .to_string()
+    }
 
-            // Copy graph.html
-            if let Ok(graph_template) = fs::read_to_string(default_theme_dir.join("graph.html")) {
-                let cleaned_graph_template = remove_zero_width_spaces(&graph_template);
-                let mut graph_file = File::create(theme_dir.join("graph.html"))?;
-                graph_file.write_all(cleaned_graph_template.as_bytes())?;
-            }
+    fn tag_stem(&self, tag: &str) -> String {
+        let slug = match self.tag_slugs.get(tag) {
+            Some(slug) => slug.clone(),
+            None => tag_slug(tag, true),
+        };
+        if self.pretty {
+            format!("tag/{}", slug)
+        } else {
+            format!("tag_{}", slug)
+        }
+    }
 
-            println!(
-                "Copied default theme files to new theme directory: {:?}",
-                theme_dir
+    fn note_file(&self, id: &str) -> String {
+        self.page_file(&self.note_stem(id))
+    }
+
+    fn tag_file(&self, tag: &str) -> String {
+        self.page_file(&self.tag_stem(tag))
+    }
+
+    fn note_path(&self, id: &str) -> String {
+        self.page_path(&self.note_stem(id))
+    }
+
+    fn note_url(&self, id: &str) -> String {
+        self.link(self.note_path(id))
+    }
+
+    fn tag_url(&self, tag: &str) -> String {
+        self.link(self.page_path(&self.tag_stem(tag)))
+    }
+
+    fn page_url(&self, name: &str) -> String {
+        self.link(self.page_path(name))
+    }
+
+    // Permalink of a block, which redirects to wherever the block currently lives
+    fn block_file(&self, block_id: &str) -> String {
+        self.page_file(&format!("b/{}", block_id))
+    }
+
+    // Makes a link usable from a page written to `file`, which only matters for relative links
+    // from a page in a subdirectory
+    fn url_from_file(&self, file: &str, url: String) -> String {
+        if self.root.is_empty() && !url.contains("://") && !url.starts_with('/') {
+            format!("{}{}", "../".repeat(file.matches('/').count()), url)
+        } else {
+            url
+        }
+    }
+
+    // Link to a file copied into the output, such as styles.css or assets/image.png; absolute
+    // URLs and anchors are left alone
+    fn asset_url(&self, path: &str) -> String {
+        if path.is_empty() || path.contains("://") || path.starts_with('/') || path.starts_with('#')
+        {
+            path.to_string()
+        } else {
+            format!("{}{}", self.root, path)
+        }
+    }
+
+    // Link targets written in a note only need the root when they point into assets/
+    fn link_href(&self, href: &str) -> String {
+        if href.starts_with("assets/") {
+            self.asset_url(href)
+        } else {
+            href.to_string()
+        }
+    }
+
+    // URL path of an output file from the domain root, as static host redirect rules need it
+    fn site_path(&self, file: &str) -> String {
+        let url = self.link(file.strip_suffix("index.html").unwrap_or(file).to_string());
+        if url.contains("://") || url.starts_with('/') {
+            url
+        } else {
+            format!("/{}", url)
+        }
+    }
+
+    // Points assets/ references inside raw HTML or CSS (title images) at the site root
+    fn rebase_assets(&self, html: &str) -> String {
+        if self.root.is_empty() {
+            return html.to_string();
+        }
+        let mut html = html.to_string();
+        for quote in ["\"", "'", "("] {
+            html = html.replace(
+                &format!("{}assets/", quote),
+                &format!("{}{}assets/", quote, self.root),
             );
+        }
+        html
+    }
+
+    // The same URLs starting from the domain root, for pages that may be served at any path
+    fn root_absolute(&self) -> SiteUrls {
+        SiteUrls {
+            note_slugs: self.note_slugs.clone(),
+            tag_slugs: self.tag_slugs.clone(),
+            encode_links: self.encode_links,
+            pretty: self.pretty,
+            root: if self.root.is_empty() {
+                "/".to_string()
+            } else {
+                self.root.clone()
+            },
+        }
+    }
+
+    fn link(&self, path: String) -> String {
+        if self.encode_links {
+            format!("{}{}", self.root, percent_encode(&path))
         } else {
-            // Create empty template files if default theme doesn't exist
-            println!("Creating empty template files in theme directory...");
-            let html_template = String::new();
-            let css_template = String::new();
-            let graph_template = String::new();
+            format!("{}{}", self.root, path)
+        }
+    }
+}
 
-            let cleaned_html_template = remove_zero_width_spaces(&html_template);
-            let mut html_file = File::create(theme_dir.join("page.html"))?;
-            html_file.write_all(cleaned_html_template.as_bytes())?;
+// Turns text into a URL-safe slug: letters and digits are kept (transliterated to ASCII if
+// requested), everything else becomes a single hyphen
+fn slugify(text: &str, transliterate: bool) -> String {
+    let text = if transliterate {
+        deunicode(text)
+    } else {
+        text.to_string()
+    };
 
-            let cleaned_css_template = remove_zero_width_spaces(&css_template);
-            let mut css_file = File::create(theme_dir.join("styles.css"))?;
-            css_file.write_all(cleaned_css_template.as_bytes())?;
+    let mut slug = String::new();
+    for c in text.chars() {
+        if c.is_alphanumeric() || c == '_' {
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
 
-            let cleaned_graph_template = remove_zero_width_spaces(&graph_template);
-            let mut graph_file = File::create(theme_dir.join("graph.html"))?;
-            graph_file.write_all(cleaned_graph_template.as_bytes())?;
+    slug.trim_end_matches('-').to_string()
+}
+
+// Slug for a tag, keeping the levels of a nested tag apart with "__"
+fn tag_slug(tag: &str, transliterate: bool) -> String {
+    tag.split('/')
+        .map(|level| slugify(level, transliterate))
+        .filter(|level| !level.is_empty())
+        .collect::<Vec<_>>()
+        .join("__")
+}
+
+// Percent-encodes everything except unreserved URL characters and path separators
+fn percent_encode(text: &str) -> String {
+    let mut encoded = String::new();
+    for byte in text.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~/#".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+// Claims `base` (or `base-2`, `base-3`, ...) as a slug; comparisons ignore case so pages
+// don't overwrite each other on case-insensitive file systems
+fn claim_slug(base: &str, used: &mut HashSet<String>) -> String {
+    let mut slug = base.to_string();
+    let mut counter = 2;
+    while !used.insert(slug.to_lowercase()) {
+        slug = format!("{}-{}", base, counter);
+        counter += 1;
+    }
+    slug
+}
+
+fn build_site_urls(
+    notes_map: &HashMap<String, Note>,
+    all_tags: &HashSet<String>,
+    urls_config: &UrlsConfig,
+) -> SiteUrls {
+    let transliterate = urls_config.transliterate;
+    let pretty = urls_config.style == "pretty";
+    let mut used: HashSet<String> = RESERVED_SLUGS.iter().map(|s| s.to_string()).collect();
+
+    // Tag slugs only need to be unique among themselves, but flat tag pages are written as
+    // tag_<slug>.html next to the notes, so those names are taken before notes pick theirs
+    let mut used_tags = HashSet::new();
+    let mut tags: Vec<_> = all_tags.iter().collect();
+    tags.sort();
+
+    let mut tag_slugs = HashMap::new();
+    for tag in tags {
+        let base = tag_slug(tag, transliterate);
+        let base = if base.is_empty() {
+            "tag".to_string()
+        } else {
+            base
+        };
+        let slug = claim_slug(&base, &mut used_tags);
+        if !pretty {
+            used.insert(format!("tag_{}", slug).to_lowercase());
+        }
+        tag_slugs.insert(tag.clone(), slug);
+    }
+
+    let mut note_slugs = HashMap::new();
+
+    // Custom slugs are chosen by hand, so they get first pick, in ID order for stable results
+    let mut notes: Vec<_> = notes_map.values().collect();
+    notes.sort_by_key(|note| (note.Properties.custom_slug.trim().is_empty(), &note.ID));
+
+    for note in notes {
+        let custom_slug = slugify(note.Properties.custom_slug.trim(), transliterate);
+        let base = if !custom_slug.is_empty() {
+            custom_slug
+        } else if urls_config.note_slugs == "title" {
+            slugify(&note.Properties.title, transliterate).to_lowercase()
+        } else {
+            String::new()
+        };
+        let base = if base.is_empty() {
+            note.ID.clone()
+        } else {
+            base
+        };
 
+        let slug = claim_slug(&base, &mut used);
+        if slug != base {
             println!(
-                "Created empty template files in theme directory: {:?}",
-                theme_dir
+                "Warning: URL \"{}.html\" is already taken, \"{}\" ({}) is published as \"{}.html\"",
+                base, note.Properties.title, note.ID, slug
             );
         }
+        note_slugs.insert(note.ID.clone(), slug);
     }
 
-    let output_dir = PathBuf::from("output");
-    println!("Output directory: {:?}", output_dir);
-    if output_dir.exists() {
-        println!("Removing existing output directory...");
-        fs::remove_dir_all(&output_dir)?;
+    let base_path = urls_config.base_path.trim().trim_end_matches('/');
+    let root = if base_path.contains("://") {
+        format!("{}/", base_path)
+    } else if !base_path.trim_start_matches('/').is_empty() {
+        format!("/{}/", base_path.trim_start_matches('/'))
+    } else if pretty {
+        // Pages sit at different depths, so links start from the domain root
+        "/".to_string()
+    } else {
+        String::new()
+    };
+
+    SiteUrls {
+        note_slugs,
+        tag_slugs,
+        encode_links: !transliterate,
+        pretty,
+        root,
     }
-    println!("Creating output directory...");
-    fs::create_dir_all(&output_dir)?;
+}
 
-    let assets_dir = output_dir.join("assets");
-    println!("Assets directory: {:?}", assets_dir);
-    fs::create_dir_all(&assets_dir)?;
+// Whether a note carries the tag itself or a tag nested below it
+fn note_has_tag(note: &Note, tag: &str) -> bool {
+    note.Properties.tags.split(',').any(|t| {
+        let t = t.trim();
+        t == tag || (t.starts_with(tag) && t[tag.len()..].starts_with('/'))
+    })
+}
 
-    println!("Finding and copying assets...");
-    find_and_copy_assets(Path::new("input"), &assets_dir)?;
+// The tags above a nested tag, outermost first: "a/b/c" gives "a" and "a/b"
+fn parent_tags(tag: &str) -> Vec<&str> {
+    tag.match_indices('/')
+        .map(|(index, _)| &tag[..index])
+        .filter(|parent| !parent.is_empty())
+        .collect()
+}
 
-    println!("Reading CSS template...");
-    let css_template_path = format!("themes/{}/styles.css", theme_name);
-    let css_template = read_template(&css_template_path);
-    let cleaned_css = remove_zero_width_spaces(&css_template);
-    let css_path = output_dir.join("styles.css");
-    println!("Writing CSS to: {:?}", css_path);
-    let mut css_file = File::create(&css_path)?;
-    css_file.write_all(cleaned_css.as_bytes())?;
+// Tag name of an inline tag mark. Tags are kept as a comma-separated list, so commas inside
+// the mark turn into spaces rather than splitting it into several tags.
+fn inline_tag_name(content: &str) -> String {
+    content
+        .split(',')
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
 
-    println!("Finding .sy files...");
-    let mut note_files = Vec::new();
-    find_sy_files(Path::new("input"), &mut note_files)?;
-    println!("Found {} .sy files", note_files.len());
+// Collects the names of inline tag marks (#tag# in the editor) in document order
+fn collect_inline_tags(blocks: &[Block], tags: &mut Vec<String>) {
+    for block in blocks {
+        if block.Type == "NodeTextMark" && block.TextMarkType.split(' ').any(|t| t == "tag") {
+            let tag = inline_tag_name(&block.TextMarkTextContent);
+            if block.TextMarkTextContent.contains(',') {
+                println!(
+                    "Warning: Inline tag #{}# contains a comma, using the tag \"{}\"",
+                    block.TextMarkTextContent.trim(),
+                    tag
+                );
+            }
+            if !tag.is_empty() && !tags.contains(&tag) {
+                tags.push(tag);
+            }
+        }
 
-    println!("Parsing notes...");
-    let mut notes_map = HashMap::new();
-    let mut id_to_path = HashMap::new();
-    let mut all_tags = HashSet::new();
-    let mut index_note_id: Option<String> = None;
+        collect_inline_tags(&block.Children, tags);
+    }
+}
 
-    for path in &note_files {
-        let content = fs::read_to_string(path)?;
-        match serde_json::from_str::<Note>(&content) {
-            Ok(mut note) => {
-                let id = note.ID.clone(); // Clone the ID before moving the note
-                id_to_path.insert(id.clone(), path.clone());
+// Maps every block ID (and every note ID) to the ID of the note that contains it
+fn build_block_index(notes_map: &HashMap<String, Note>) -> HashMap<String, String> {
+    let mut block_index = HashMap::new();
 
-                // Extract creation date from note ID if it's not set
-                if note.Properties.created.is_empty() && id.len() >= 14 {
-                    // Format is YYYYMMDDhhmmss-xxx
-                    note.Properties.created = id[0..14].to_string();
+    for (note_id, note) in notes_map {
+        block_index.insert(note_id.clone(), note_id.clone());
+        index_blocks(&note.Children, note_id, &mut block_index);
+    }
+
+    block_index
+}
+
+fn index_blocks(blocks: &[Block], note_id: &str, block_index: &mut HashMap<String, String>) {
+    for block in blocks {
+        if !block.ID.is_empty() {
+            block_index.insert(block.ID.clone(), note_id.to_string());
+        }
+        index_blocks(&block.Children, note_id, block_index);
+    }
+}
+
+// Turns the margin info (target -> sources) into note-level forward edges (source -> targets),
+// attributing block-level references and transclusions to the note containing the block
+fn build_note_adjacency(
+    tracker: &MarginInfoTracker,
+    block_index: &HashMap<String, String>,
+) -> HashMap<String, HashSet<String>> {
+    let mut adjacency: HashMap<String, HashSet<String>> = HashMap::new();
+
+    let sources_by_content = tracker
+        .transclusions
+        .iter()
+        .chain(tracker.linked_mentions.iter())
+        .chain(tracker.page_linked_mentions.iter());
+
+    for (content_id, sources) in sources_by_content {
+        if let Some(target_note_id) = block_index.get(content_id) {
+            for (source_note_id, _) in sources {
+                if source_note_id != target_note_id {
+                    adjacency
+                        .entry(source_note_id.clone())
+                        .or_default()
+                        .insert(target_note_id.clone());
                 }
+            }
+        }
+    }
 
-                if !note.Properties.tags.is_empty() {
-                    for tag in note.Properties.tags.split(',') {
-                        let tag = tag.trim().to_string();
+    adjacency
+}
 
-                        if tag != "index" {
-                            all_tags.insert(tag.clone());
-                        }
+// The links between notes: references, embeds and the document tree, plus tag nodes if enabled
+struct LinkGraph {
+    // (source, target, kind) -> number of such links; kind is "ref", "embed", "child-of" or
+    // "tag", and tag nodes have IDs of the form "tag:name"
+    edges: HashMap<(String, String, &'static str), usize>,
+    // Tags that have a node, sorted
+    tags: BTreeSet<String>,
+    // Note -> every note it references or embeds, or that references or embeds it
+    neighbors: HashMap<String, BTreeSet<String>>,
+}
 
-                        // Check if this note has the "index" tag
-                        if tag == "index" {
-                            if index_note_id.is_some() {
-                                println!(
-                                    "Warning: Multiple notes with 'index' tag found. Using the last one found."
-                                );
-                            }
-                            index_note_id = Some(id.clone());
+impl LinkGraph {
+    fn new(
+        notes_map: &HashMap<String, Note>,
+        id_to_path: &HashMap<String, PathBuf>,
+        block_index: &HashMap<String, String>,
+        tag_nodes: bool,
+    ) -> Self {
+        let mut graph = Self {
+            edges: HashMap::new(),
+            tags: BTreeSet::new(),
+            neighbors: HashMap::new(),
+        };
+
+        for (id, note) in notes_map {
+            scan_blocks_for_links(&note.Children, block_index, id, &mut graph);
+
+            // SiYuan stores a subdocument in a folder named after its parent document
+            let parent_id = id_to_path
+                .get(id)
+                .and_then(|path| path.parent())
+                .and_then(|folder| folder.file_name())
+                .and_then(|name| name.to_str());
+            if let Some(parent_id) = parent_id.filter(|parent| notes_map.contains_key(*parent)) {
+                graph.add_edge(id, parent_id, "child-of");
+            }
+
+            if tag_nodes {
+                for tag in note.Properties.tags.split(',').map(str::trim) {
+                    if tag.is_empty() || PAGE_TAGS.contains(&tag) {
+                        continue;
+                    }
+                    graph.add_edge(id, &format!("tag:{}", tag), "tag");
+                    if !graph.tags.insert(tag.to_string()) {
+                        continue;
+                    }
+
+                    // A new nested tag hangs off its parent, up to the first tag already there.
+                    // Page tags such as index in index/drafts get no node.
+                    let mut child = tag;
+                    for parent in parent_tags(tag).into_iter().rev() {
+                        if PAGE_TAGS.contains(&parent) {
+                            break;
+                        }
+                        graph.add_edge(
+                            &format!("tag:{}", child),
+                            &format!("tag:{}", parent),
+                            "child-of",
+                        );
+                        if !graph.tags.insert(parent.to_string()) {
+                            break;
                         }
+                        child = parent;
                     }
                 }
+            }
+        }
+
+        for (source, target, kind) in graph.edges.keys() {
+            if *kind == "ref" || *kind == "embed" {
+                graph
+                    .neighbors
+                    .entry(source.clone())
+                    .or_default()
+                    .insert(target.clone());
+                graph
+                    .neighbors
+                    .entry(target.clone())
+                    .or_default()
+                    .insert(source.clone());
+            }
+        }
+
+        graph
+    }
+
+    fn add_edge(&mut self, source: &str, target: &str, kind: &'static str) {
+        *self
+            .edges
+            .entry((source.to_string(), target.to_string(), kind))
+            .or_insert(0) += 1;
+    }
+
+    // How `source` links to `target` in the local graph: an embed wins over a reference
+    fn link_kind(&self, source: &str, target: &str) -> Option<&'static str> {
+        let key = |kind| (source.to_string(), target.to_string(), kind);
+        ["embed", "ref"]
+            .into_iter()
+            .find(|kind| self.edges.contains_key(&key(*kind)))
+    }
+
+    // Every edge as (source, target, kind, weight), sorted so output is stable between builds
+    fn sorted_edges(&self) -> Vec<(&str, &str, &'static str, usize)> {
+        let mut edges: Vec<_> = self
+            .edges
+            .iter()
+            .map(|((source, target, kind), weight)| {
+                (source.as_str(), target.as_str(), *kind, *weight)
+            })
+            .collect();
+        edges.sort_unstable();
+        edges
+    }
+}
+
+// Resolves the configured seeds into note IDs that exist in the notebook
+fn resolve_reachability_seeds(
+    config: &ReachabilityConfig,
+    notes_map: &HashMap<String, Note>,
+) -> Vec<String> {
+    let mut seeds = Vec::new();
+
+    for seed_id in &config.seed_ids {
+        if notes_map.contains_key(seed_id) {
+            seeds.push(seed_id.clone());
+        } else {
+            println!("Warning: Seed note {} not found", seed_id);
+        }
+    }
+
+    for (id, note) in notes_map {
+        let has_seed_tag = config
+            .seed_tags
+            .iter()
+            .any(|seed_tag| note_has_tag(note, seed_tag));
+        if has_seed_tag && !seeds.contains(id) {
+            seeds.push(id.clone());
+        }
+    }
+
+    seeds
+}
+
+// Breadth-first walk of the link graph from the seeds, stopping after max_hops
+fn collect_reachable_notes(
+    seeds: &[String],
+    adjacency: &HashMap<String, HashSet<String>>,
+    max_hops: Option<usize>,
+) -> HashSet<String> {
+    let mut reachable = HashSet::new();
+    let mut queue = VecDeque::new();
+
+    for seed in seeds {
+        if reachable.insert(seed.clone()) {
+            queue.push_back((seed.clone(), 0));
+        }
+    }
+
+    while let Some((note_id, hops)) = queue.pop_front() {
+        if max_hops.is_some_and(|max| hops >= max) {
+            continue;
+        }
+
+        if let Some(targets) = adjacency.get(&note_id) {
+            for target in targets {
+                if reachable.insert(target.clone()) {
+                    queue.push_back((target.clone(), hops + 1));
+                }
+            }
+        }
+    }
+
+    reachable
+}
+
+// Extracts the path relative to an assets directory from a reference such as
+// `assets/image.png` or `background-image:url("assets/image.png")`
+fn asset_path_from_reference(reference: &str) -> Option<String> {
+    let start = reference.find("assets/")? + "assets/".len();
+    let path: String = reference[start..]
+        .chars()
+        .take_while(|c| !matches!(c, '"' | '\'' | ')' | '?' | '#' | ' '))
+        .collect();
+
+    if path.is_empty() {
+        None
+    } else {
+        Some(percent_decode(&path))
+    }
+}
+
+// Decodes %XX escapes so encoded references match the file names on disk
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        // Anything but two hex digits after the % is kept as it is
+        let hex = bytes.get(i + 1..i + 3).unwrap_or_default();
+        if bytes[i] == b'%' && hex.len() == 2 && hex.iter().all(u8::is_ascii_hexdigit) {
+            let hex = std::str::from_utf8(hex).unwrap_or_default();
+            if let Ok(byte) = u8::from_str_radix(hex, 16) {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+// Collects every asset referenced by images, media, links and the title image of a note
+fn collect_note_asset_references(note: &Note, assets: &mut HashSet<String>) {
+    if let Some(path) = asset_path_from_reference(&note.Properties.title_img) {
+        assets.insert(path);
+    }
+    collect_asset_references(&note.Children, assets);
+}
+
+fn collect_asset_references(blocks: &[Block], assets: &mut HashSet<String>) {
+    for block in blocks {
+        match block.Type.as_str() {
+            "NodeLinkDest" => {
+                if let Some(path) = asset_path_from_reference(&block.Data) {
+                    assets.insert(path);
+                }
+            }
+            "NodeVideo" | "NodeAudio" | "NodeIFrame" | "NodeHTMLBlock" | "NodeInlineHTML" => {
+                // These blocks store raw HTML, pick up every src and href attribute
+                for part in block.Data.split("src=\"").skip(1) {
+                    if let Some(path) = asset_path_from_reference(part) {
+                        assets.insert(path);
+                    }
+                }
+                for part in block.Data.split("href=\"").skip(1) {
+                    if let Some(path) = asset_path_from_reference(part) {
+                        assets.insert(path);
+                    }
+                }
+            }
+            "NodeTextMark" => {
+                if let Some(path) = asset_path_from_reference(&block.TextMarkAHref) {
+                    assets.insert(path);
+                }
+            }
+            _ => {}
+        }
+
+        collect_asset_references(&block.Children, assets);
+    }
+}
+
+// Fills the links to the stylesheet and the generated pages that themes use in their headers
+fn fill_site_links(template: &str, urls: &SiteUrls) -> String {
+    template
+        .replace("{{css_path}}", &urls.asset_url("styles.css"))
+        .replace("{{home_url}}", &urls.page_url("index"))
+        .replace("{{all_url}}", &urls.page_url("all"))
+        .replace("{{tags_url}}", &urls.page_url("tags"))
+        .replace("{{graph_url}}", &urls.page_url("graph"))
+}
+
+// Creates a page's file, along with its directory when pages are written as name/index.html
+fn create_page_file(path: &Path) -> std::io::Result<File> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    File::create(path)
+}
+
+fn back_navigation_html(urls: &SiteUrls) -> String {
+    format!(
+        r#"<a href="{}" class="back-link">Back to home<svg xmlns="http://www.w3.org/2000/svg" width="16" height="16" viewBox="0 0 24 24" fill="none" stroke="currentColor" stroke-width="2" stroke-linecap="round" stroke-linejoin="round" class="lucide lucide-arrow-left"><path d="m12 19-7-7 7-7"></path><path d="M19 12H5"></path></svg></a>"#,
+        urls.page_url("index")
+    )
+}
+
+fn extract_toc_items(blocks: &[Block], headings: &mut Vec<TocItem>, id_counter: &mut usize) {
+    for block in blocks {
+        if block.Type == "NodeHeading" {
+            let level = block.HeadingLevel.max(1).min(6);
+
+            let id = if !block.ID.is_empty() {
+                block.ID.clone()
+            } else {
+                format!("heading-{}", id_counter)
+            };
+            *id_counter += 1;
+
+            let mut text = String::new();
+            for child in &block.Children {
+                if child.Type == "NodeText" {
+                    text.push_str(&child.Data);
+                } else if child.Type == "NodeTextMark" {
+                    text.push_str(&child.TextMarkTextContent);
+                }
+            }
+
+            headings.push(TocItem { id, text, level });
+        }
+
+        extract_toc_items(&block.Children, headings, id_counter);
+    }
+}
+
+fn naturalize_date(date_str: &str) -> String {
+    if date_str.is_empty() {
+        return String::from("Unknown date");
+    }
+
+    if date_str.len() >= 8 && date_str.chars().take(8).all(|c| c.is_digit(10)) {
+        let year = &date_str[0..4];
+        let month_num: u32 = date_str[4..6].parse().unwrap_or(1);
+        let day: u32 = date_str[6..8].parse().unwrap_or(1);
+
+        let month_name = match month_num {
+            1 => "January",
+            2 => "February",
+            3 => "March",
+            4 => "April",
+            5 => "May",
+            6 => "June",
+            7 => "July",
+            8 => "August",
+            9 => "September",
+            10 => "October",
+            11 => "November",
+            12 => "December",
+            _ => "Unknown",
+        };
+
+        // Add ordinal suffix to day
+        let day_with_suffix = match day {
+            1 | 21 | 31 => format!("{}st", day),
+            2 | 22 => format!("{}nd", day),
+            3 | 23 => format!("{}rd", day),
+            _ => format!("{}th", day),
+        };
+
+        let formatted_date = format!("{} {}, {}", month_name, day_with_suffix, year);
+
+        if date_str.len() > 8 && date_str.chars().nth(8) == Some('T') {
+            if date_str.len() >= 14 {
+                // Has at least hour and minute
+                let hour: u32 = date_str[9..11].parse().unwrap_or(0);
+                let minute: u32 = date_str[11..13].parse().unwrap_or(0);
+
+                let hour12 = if hour == 0 {
+                    12
+                } else if hour > 12 {
+                    hour - 12
+                } else {
+                    hour
+                };
+
+                let am_pm = if hour >= 12 { "PM" } else { "AM" };
+                return format!("{} at {}:{:02} {}", formatted_date, hour12, minute, am_pm);
+            }
+        }
+
+        return formatted_date;
+    }
+
+    // ISO format date handling (YYYY-MM-DD)
+    if date_str.len() >= 10
+        && date_str.chars().nth(4) == Some('-')
+        && date_str.chars().nth(7) == Some('-')
+    {
+        // Extract from ISO format
+        if let Ok(year) = date_str[0..4].parse::<u32>() {
+            if let Ok(month) = date_str[5..7].parse::<u32>() {
+                if let Ok(day) = date_str[8..10].parse::<u32>() {
+                    // Get month name
+                    let month_name = match month {
+                        1 => "January",
+                        2 => "February",
+                        3 => "March",
+                        4 => "April",
+                        5 => "May",
+                        6 => "June",
+                        7 => "July",
+                        8 => "August",
+                        9 => "September",
+                        10 => "October",
+                        11 => "November",
+                        12 => "December",
+                        _ => "Unknown",
+                    };
+
+                    // Add ordinal suffix to day
+                    let day_with_suffix = match day {
+                        1 | 21 | 31 => format!("{}st", day),
+                        2 | 22 => format!("{}nd", day),
+                        3 | 23 => format!("{}rd", day),
+                        _ => format!("{}th", day),
+                    };
+
+                    // Check if there's time information
+                    if date_str.len() >= 16 && date_str.chars().nth(10) == Some('T') {
+                        // Has time component
+                        if let Ok(hour) = date_str[11..13].parse::<u32>() {
+                            if let Ok(minute) = date_str[14..16].parse::<u32>() {
+                                // Format with AM/PM
+                                let hour12 = if hour == 0 {
+                                    12
+                                } else if hour > 12 {
+                                    hour - 12
+                                } else {
+                                    hour
+                                };
+
+                                let am_pm = if hour >= 12 { "PM" } else { "AM" };
+                                return format!(
+                                    "{} {}, {} at {}:{:02} {}",
+                                    month_name, day_with_suffix, year, hour12, minute, am_pm
+                                );
+                            }
+                        }
+                    }
+
+                    return format!("{} {}, {}", month_name, day_with_suffix, year);
+                }
+            }
+        }
+    }
+
+    // If we can't parse it in any of our formats, return original
+    return date_str.to_string();
+}
+
+fn generate_toc_html(headings: &[TocItem]) -> String {
+    let mut toc_html = String::new();
+
+    for heading in headings {
+        // Only include h2 and h3 in the TOC
+        if heading.level >= 2 && heading.level <= 3 {
+            let class = if heading.level == 3 {
+                " toc-subitem"
+            } else {
+                ""
+            };
+            toc_html.push_str(&format!(
+                "<li class=\"toc-item{}\"><a class=\"toc-link\" href=\"#{}\">{}",
+                class, heading.id, heading.text
+            ));
+            toc_html.push_str("</a></li>\n");
+        }
+    }
+
+    if toc_html.is_empty() {
+        toc_html = "<li class=\"toc-item\"><em>No headings found</em></li>\n".to_string();
+    }
+
+    toc_html
+}
+
+// Tags that pick a note for a generated page rather than group notes
+const PAGE_TAGS: [&str; 2] = ["index", "404"];
+
+fn filter_index_tag(tags_str: &str) -> String {
+    tags_str
+        .split(',')
+        .map(|t| t.trim())
+        .filter(|t| !PAGE_TAGS.contains(t))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn main() -> std::io::Result<()> {
+    let start_time = Instant::now();
+    let mut page_count = 0;
+
+    println!("Starting SyMark generator...");
+
+    // Get theme from command line arguments or use default
+    let args: Vec<String> = env::args().collect();
+    let theme_name = if args.len() > 1 {
+        args[1].clone()
+    } else {
+        "default".to_string()
+    };
+
+    println!("Using theme: {}", theme_name);
+
+    let config = load_config(Path::new("symark.json"));
+
+    let themes_dir = PathBuf::from("themes");
+    let theme_dir = themes_dir.join(&theme_name);
+
+    // Create themes directory if it doesn't exist
+    if !themes_dir.exists() {
+        println!("Creating themes directory...");
+        fs::create_dir_all(&themes_dir)?;
+    }
+
+    // If the selected theme doesn't exist, create it
+    if !theme_dir.exists() {
+        println!("Creating theme directory: {:?}", theme_dir);
+        fs::create_dir_all(&theme_dir)?;
+
+        // Check if default theme exists to copy from
+        let default_theme_dir = themes_dir.join("default");
+        if default_theme_dir.exists() && fs::read_dir(&default_theme_dir)?.next().is_some() {
+            println!("Copying files from default theme to new theme directory...");
+
+            // Copy page.html
+            if let Ok(html_template) = fs::read_to_string(default_theme_dir.join("page.html")) {
+                let cleaned_html_template = remove_zero_width_spaces(&html_template);
+                let mut html_file = File::create(theme_dir.join("page.html"))?;
+                html_file.write_all(cleaned_html_template.as_bytes())?;
+            }
+
+            // Copy styles.css
+            if let Ok(css_template) = fs::read_to_string(default_theme_dir.join("styles.css")) {
+                let cleaned_css_template = remove_zero_width_spaces(&css_template);
+                let mut css_file = File::create(theme_dir.join("styles.css"))?;
+                css_file.write_all(cleaned_css_template.as_bytes())?;
+            }
+
+            // Copy graph.html
+            if let Ok(graph_template) = fs::read_to_string(default_theme_dir.join("graph.html")) {
+                let cleaned_graph_template = remove_zero_width_spaces(&graph_template);
+                let mut graph_file = File::create(theme_dir.join("graph.html"))?;
+                graph_file.write_all(cleaned_graph_template.as_bytes())?;
+            }
+
+            println!(
+                "Copied default theme files to new theme directory: {:?}",
+                theme_dir
+            );
+        } else {
+            // Create empty template files if default theme doesn't exist
+            println!("Creating empty template files in theme directory...");
+            let html_template = String::new();
+            let css_template = String::new();
+            let graph_template = String::new();
+
+            let cleaned_html_template = remove_zero_width_spaces(&html_template);
+            let mut html_file = File::create(theme_dir.join("page.html"))?;
+            html_file.write_all(cleaned_html_template.as_bytes())?;
+
+            let cleaned_css_template = remove_zero_width_spaces(&css_template);
+            let mut css_file = File::create(theme_dir.join("styles.css"))?;
+            css_file.write_all(cleaned_css_template.as_bytes())?;
+
+            let cleaned_graph_template = remove_zero_width_spaces(&graph_template);
+            let mut graph_file = File::create(theme_dir.join("graph.html"))?;
+            graph_file.write_all(cleaned_graph_template.as_bytes())?;
+
+            println!(
+                "Created empty template files in theme directory: {:?}",
+                theme_dir
+            );
+        }
+    }
+
+    let output_dir = PathBuf::from("output");
+    println!("Output directory: {:?}", output_dir);
+    if output_dir.exists() {
+        println!("Removing existing output directory...");
+        fs::remove_dir_all(&output_dir)?;
+    }
+    println!("Creating output directory...");
+    fs::create_dir_all(&output_dir)?;
+
+    let assets_dir = output_dir.join("assets");
+    println!("Assets directory: {:?}", assets_dir);
+    fs::create_dir_all(&assets_dir)?;
+
+    println!("Reading CSS template...");
+    let css_template_path = format!("themes/{}/styles.css", theme_name);
+    let css_template = read_template(&css_template_path);
+    let cleaned_css = remove_zero_width_spaces(&css_template);
+    let css_path = output_dir.join("styles.css");
+    println!("Writing CSS to: {:?}", css_path);
+    let mut css_file = File::create(&css_path)?;
+    css_file.write_all(cleaned_css.as_bytes())?;
+
+    println!("Finding .sy files...");
+    let mut note_files = Vec::new();
+    find_sy_files(Path::new("input"), &mut note_files)?;
+    println!("Found {} .sy files", note_files.len());
+
+    println!("Parsing notes...");
+    let mut notes_map = HashMap::new();
+    let mut id_to_path = HashMap::new();
+    let mut all_tags = HashSet::new();
+    let mut index_note_id: Option<String> = None;
+    let mut not_found_note_id: Option<String> = None;
+
+    for path in &note_files {
+        let content = fs::read_to_string(path)?;
+        match serde_json::from_str::<Note>(&content) {
+            Ok(mut note) => {
+                let id = note.ID.clone(); // Clone the ID before moving the note
+                id_to_path.insert(id.clone(), path.clone());
+
+                // Extract creation date from note ID if it's not set
+                if note.Properties.created.is_empty() && id.len() >= 14 {
+                    // Format is YYYYMMDDhhmmss-xxx
+                    note.Properties.created = id[0..14].to_string();
+                }
+
+                // Inline #tags# in the content count the same as document tags
+                let mut inline_tags = Vec::new();
+                collect_inline_tags(&note.Children, &mut inline_tags);
+                for tag in inline_tags {
+                    if !note.Properties.tags.split(',').any(|t| t.trim() == tag) {
+                        if !note.Properties.tags.trim().is_empty() {
+                            note.Properties.tags.push(',');
+                        }
+                        note.Properties.tags.push_str(&tag);
+                    }
+                }
+
+                if !note.Properties.tags.is_empty() {
+                    for tag in note.Properties.tags.split(',') {
+                        let tag = tag.trim().to_string();
+                        if tag.is_empty() {
+                            continue;
+                        }
+
+                        if !PAGE_TAGS.contains(&tag.as_str()) {
+                            // Every level of a nested tag gets its own page, except levels named
+                            // like a page tag, as in index/drafts
+                            for parent in parent_tags(&tag) {
+                                if !PAGE_TAGS.contains(&parent) {
+                                    all_tags.insert(parent.to_string());
+                                }
+                            }
+                            all_tags.insert(tag.clone());
+                        }
+
+                        // Check if this note has the "index" tag
+                        if tag == "index" {
+                            if index_note_id.is_some() {
+                                println!(
+                                    "Warning: Multiple notes with 'index' tag found. Using the last one found."
+                                );
+                            }
+                            index_note_id = Some(id.clone());
+                        }
+
+                        if tag == "404" {
+                            if not_found_note_id.is_some() {
+                                println!(
+                                    "Warning: Multiple notes with '404' tag found. Using the last one found."
+                                );
+                            }
+                            not_found_note_id = Some(id.clone());
+                        }
+                    }
+                }
+
+                notes_map.insert(id, note);
+            }
+            Err(e) => {
+                eprintln!("Error parsing file {:?}: {}", path, e);
+            }
+        }
+    }
+
+    // Collect margin info (transclusions and linked mentions)
+    println!("Collecting margin info...");
+    let mut margin_info_tracker = collect_margin_info(&notes_map);
+
+    // Assets only referenced by excluded notes are not published
+    let mut excluded_assets = HashSet::new();
+
+    if config.reachability.is_enabled() {
+        println!("Walking link graph from seed notes...");
+        let seeds = resolve_reachability_seeds(&config.reachability, &notes_map);
+        if seeds.is_empty() {
+            println!("Warning: No seed notes found, every note will be excluded");
+        }
+
+        let block_index = build_block_index(&notes_map);
+        let adjacency = build_note_adjacency(&margin_info_tracker, &block_index);
+        let reachable = collect_reachable_notes(&seeds, &adjacency, config.reachability.max_hops);
+
+        let mut included_assets = HashSet::new();
+        for (id, note) in &notes_map {
+            if reachable.contains(id) {
+                collect_note_asset_references(note, &mut included_assets);
+            } else {
+                println!("Excluding unreachable note: {}", id);
+                collect_note_asset_references(note, &mut excluded_assets);
+            }
+        }
+        excluded_assets.retain(|asset| !included_assets.contains(asset));
+
+        notes_map.retain(|id, _| reachable.contains(id));
+        id_to_path.retain(|id, _| reachable.contains(id));
+        all_tags.retain(|tag| notes_map.values().any(|n| note_has_tag(n, tag)));
+        if index_note_id
+            .as_ref()
+            .is_some_and(|index_id| !notes_map.contains_key(index_id))
+        {
+            index_note_id = None;
+        }
+        if not_found_note_id
+            .as_ref()
+            .is_some_and(|not_found_id| !notes_map.contains_key(not_found_id))
+        {
+            not_found_note_id = None;
+        }
+
+        println!(
+            "Publishing {} notes reachable from {} seed notes",
+            notes_map.len(),
+            seeds.len()
+        );
+
+        // Mentions from excluded notes must not show up on published pages
+        margin_info_tracker = collect_margin_info(&notes_map);
+    }
+
+    let virtual_refs = if config.virtual_refs.enabled {
+        MentionMatcher::new(
+            &notes_map,
+            config.virtual_refs.min_length,
+            &config.virtual_refs.include,
+            &config.virtual_refs.exclude,
+        )
+    } else {
+        None
+    };
+
+    let mention_matcher = if config.mentions.unlinked {
+        MentionMatcher::new(&notes_map, config.mentions.min_length, &[], &[])
+    } else {
+        None
+    };
+    if let Some(matcher) = &mention_matcher {
+        println!("Finding unlinked mentions...");
+        collect_unlinked_mentions(
+            &notes_map,
+            matcher,
+            virtual_refs.as_ref(),
+            &config.virtual_refs,
+            &mut margin_info_tracker,
+        );
+    }
+
+    let block_index = build_block_index(&notes_map);
+    let link_graph = LinkGraph::new(
+        &notes_map,
+        &id_to_path,
+        &block_index,
+        config.graph.tag_nodes,
+    );
+
+    // Map each referenced asset to the notes that use it, for pruning and the missing report
+    let mut referenced_assets: HashMap<String, Vec<String>> = HashMap::new();
+    for (id, note) in &notes_map {
+        let mut note_assets = HashSet::new();
+        collect_note_asset_references(note, &mut note_assets);
+        for asset in note_assets {
+            referenced_assets.entry(asset).or_default().push(id.clone());
+        }
+    }
+
+    println!("Finding and copying assets...");
+    let asset_selection = AssetSelection {
+        referenced: referenced_assets.keys().cloned().collect(),
+        excluded: excluded_assets,
+        copy_unreferenced: config.assets.copy_unreferenced,
+    };
+    let mut asset_report = AssetReport::default();
+    find_and_copy_assets(
+        Path::new("input"),
+        &assets_dir,
+        &asset_selection,
+        &config.images,
+        &mut asset_report,
+    )?;
+    println!(
+        "Copied {} assets, skipped {} unpublished assets, stripped metadata from {} images",
+        asset_report.copied, asset_report.skipped, asset_report.stripped
+    );
+
+    let mut missing_assets: Vec<_> = referenced_assets
+        .iter()
+        .filter(|(asset, _)| !asset_report.found.contains(*asset))
+        .collect();
+    missing_assets.sort();
+    for (asset, note_ids) in missing_assets {
+        let mut referencing_notes: Vec<_> = note_ids
+            .iter()
+            .map(|id| format!("\"{}\" ({})", notes_map[id].Properties.title, id))
+            .collect();
+        referencing_notes.sort();
+        println!(
+            "Warning: Missing asset assets/{} referenced by {}",
+            asset,
+            referencing_notes.join(", ")
+        );
+    }
+
+    println!("Processing images...");
+    let mut published_images: Vec<String> = referenced_assets
+        .keys()
+        .filter(|asset| asset_report.found.contains(*asset) && asset_selection.should_copy(asset))
+        .cloned()
+        .collect();
+    published_images.sort();
+    let image_catalog = build_image_catalog(&published_images, &assets_dir, &config)?;
+    let site_urls = build_site_urls(&notes_map, &all_tags, &config.urls);
+    let render_context = RenderContext {
+        config: &config,
+        image_catalog: &image_catalog,
+        urls: &site_urls,
+        block_index: &block_index,
+        transclusion_stack: RefCell::new(Vec::new()),
+        reported_transclusions: RefCell::new(HashSet::new()),
+        virtual_refs: virtual_refs.as_ref(),
+        linked_virtual_refs: RefCell::new(HashSet::new()),
+        in_heading: Cell::new(false),
+        link_graph: Some(&link_graph).filter(|_| config.local_graph.enabled),
+    };
+
+    println!("Reading HTML template...");
+    let html_template_path = format!("themes/{}/page.html", theme_name);
+    let page_template = read_template(&html_template_path);
+
+    // The tag cloud is the same on every page, so it goes straight into the template
+    let tag_cloud = generate_tag_cloud(&notes_map, &all_tags, &site_urls);
+    let html_template = page_template.replace("{{tag_cloud}}", &tag_cloud);
+    let html_template = fill_site_links(&html_template, &site_urls);
+
+    // Notes come first so the list pages can show their reading totals
+    println!("Generating HTML for each note...");
+    let mut reading_stats = ReadingStats {
+        config: &config.reading,
+        notes: HashMap::new(),
+    };
+    for id in notes_map.keys() {
+        println!("Generating HTML for note: {}", id);
+        let stats = generate_html_for_note(
+            id,
+            &notes_map,
+            &id_to_path,
+            &output_dir,
+            &all_tags,
+            &html_template,
+            &margin_info_tracker,
+            &render_context,
+        )?;
+        reading_stats.notes.insert(id.clone(), stats);
+        page_count += 1;
+    }
+
+    if let Some(index_id) = &index_note_id {
+        println!("Generating custom index page with ID: {}", index_id);
+
+        let all_notes_link = format!(
+            "<div class=\"all-notes-link\"><a href=\"{}\">View All Notes</a></div>",
+            site_urls.page_url("all")
+        );
+        generate_custom_page(
+            "index",
+            index_id,
+            &all_notes_link,
+            &notes_map,
+            &id_to_path,
+            &output_dir,
+            &all_tags,
+            &html_template,
+            &margin_info_tracker,
+            &render_context,
+        )?;
+        page_count += 1;
+
+        generate_all_notes_page(
+            &notes_map,
+            &output_dir,
+            &all_tags,
+            &site_urls,
+            &html_template,
+            &reading_stats,
+        )?;
+        page_count += 1;
+    } else {
+        generate_index_page(
+            &notes_map,
+            &output_dir,
+            &all_tags,
+            &site_urls,
+            &html_template,
+            &reading_stats,
+        )?;
+        page_count += 1;
+    }
+
+    println!("Generating tag pages...");
+    for tag in &all_tags {
+        println!("Generating page for tag: {}", tag);
+        generate_tag_page(
+            tag,
+            &notes_map,
+            &output_dir,
+            &all_tags,
+            &site_urls,
+            &html_template,
+            &reading_stats,
+        )?;
+        page_count += 1;
+    }
+
+    println!("Generating tag index...");
+    generate_tags_index_page(
+        &notes_map,
+        &output_dir,
+        &all_tags,
+        &site_urls,
+        &html_template,
+        &config.tags,
+        &reading_stats,
+    )?;
+    page_count += 1;
+
+    println!("Generating graph page...");
+    let graph_template_path = format!("themes/{}/graph.html", theme_name);
+    let graph_template = read_template(&graph_template_path).replace("{{tag_cloud}}", &tag_cloud);
+    let graph_template = fill_site_links(&graph_template, &site_urls);
+    let tag_colors = assign_tag_colors(&all_tags, &config.graph, &load_theme_colors(&theme_dir));
+    generate_graph_page(
+        &notes_map,
+        &output_dir,
+        &tag_colors,
+        &link_graph,
+        &config.graph,
+        &site_urls,
+        &graph_template,
+    )?;
+    page_count += 1;
+
+    // Hosts serve 404.html at whatever path was missing, so its links start from the domain root
+    println!("Generating 404 page...");
+    let not_found_urls = site_urls.root_absolute();
+    let not_found_context = RenderContext {
+        urls: &not_found_urls,
+        transclusion_stack: RefCell::new(Vec::new()),
+        reported_transclusions: RefCell::new(HashSet::new()),
+        linked_virtual_refs: RefCell::new(HashSet::new()),
+        in_heading: Cell::new(false),
+        ..render_context
+    };
+    let not_found_tag_cloud = generate_tag_cloud(&notes_map, &all_tags, &not_found_urls);
+    let not_found_extras =
+        generate_not_found_extras(&notes_map, &not_found_urls, &config.not_found);
+    if let Some(not_found_id) = &not_found_note_id {
+        let not_found_template = fill_site_links(
+            &page_template.replace("{{tag_cloud}}", &not_found_tag_cloud),
+            &not_found_urls,
+        );
+        generate_custom_page(
+            "404",
+            not_found_id,
+            &not_found_extras,
+            &notes_map,
+            &id_to_path,
+            &output_dir,
+            &all_tags,
+            &not_found_template,
+            &margin_info_tracker,
+            &not_found_context,
+        )?;
+    } else {
+        // A theme can provide its own 404.html, otherwise the page template is used
+        let not_found_template = fs::read_to_string(format!("themes/{}/404.html", theme_name))
+            .unwrap_or_else(|_| page_template.clone());
+        let not_found_template = fill_site_links(
+            &not_found_template.replace("{{tag_cloud}}", &not_found_tag_cloud),
+            &not_found_urls,
+        );
+        generate_not_found_page(
+            &output_dir,
+            &not_found_urls,
+            &not_found_template,
+            &not_found_extras,
+        )?;
+    }
+    page_count += 1;
+
+    println!("Generating block permalinks...");
+    let block_redirects = generate_block_redirects(
+        &notes_map,
+        &output_dir,
+        &margin_info_tracker,
+        &block_index,
+        &site_urls,
+    )?;
+    println!("Wrote {} block permalinks", block_redirects);
+
+    println!("Generating redirects...");
+    let redirects = generate_redirects(&notes_map, &output_dir, &site_urls, &config)?;
+    println!("Wrote {} redirects", redirects);
+
+    let elapsed = start_time.elapsed();
+    let elapsed_ms = elapsed.as_millis();
+
+    if elapsed_ms < 1000 {
+        println!("✓ Built {} pages in {} ms", page_count, elapsed_ms);
+    } else {
+        let elapsed_sec = elapsed.as_secs_f64();
+        println!("✓ Built {} pages in {:.2} s", page_count, elapsed_sec);
+    }
+
+    println!("Checking output directory:");
+    match fs::read_dir(&output_dir) {
+        Ok(entries) => {
+            let entries: Vec<_> = entries.collect();
+            if entries.is_empty() {
+                println!("  Warning: Output directory is empty!");
+            } else {
+                for entry in entries {
+                    if let Ok(entry) = entry {
+                        println!("  - {:?}", entry.path());
+                    }
+                }
+            }
+        }
+        Err(e) => println!("  Error reading output directory: {}", e),
+    }
+
+    println!(
+        "HTML generation complete. Output written to {:?}",
+        output_dir
+    );
+    Ok(())
+}
+
+// Decides which asset files get published
+struct AssetSelection {
+    // Assets referenced by at least one published note
+    referenced: HashSet<String>,
+    // Assets only referenced by notes excluded from publishing
+    excluded: HashSet<String>,
+    copy_unreferenced: bool,
+}
+
+impl AssetSelection {
+    fn should_copy(&self, asset: &str) -> bool {
+        !self.excluded.contains(asset)
+            && (self.copy_unreferenced || self.referenced.contains(asset))
+    }
+}
+
+// Tracks what happened to the asset files found on disk
+#[derive(Debug, Default)]
+struct AssetReport {
+    // Every asset found in an assets directory, copied or not
+    found: HashSet<String>,
+    copied: usize,
+    skipped: usize,
+    // Images whose metadata was removed or reduced
+    stripped: usize,
+}
+
+fn copy_directory(
+    src: &Path,
+    dst: &Path,
+    assets_root: &Path,
+    selection: &AssetSelection,
+    images_config: &ImagesConfig,
+    report: &mut AssetReport,
+) -> std::io::Result<()> {
+    for entry in fs::read_dir(src)? {
+        let entry = entry?;
+        let entry_path = entry.path();
+        let file_name = entry.file_name();
+        let dst_path = dst.join(file_name);
+
+        if entry_path.is_dir() {
+            copy_directory(
+                &entry_path,
+                &dst_path,
+                assets_root,
+                selection,
+                images_config,
+                report,
+            )?;
+            continue;
+        }
+
+        let asset = asset_key(assets_root, &entry_path);
+        if selection.should_copy(&asset) {
+            // Only create directories that will actually contain published assets
+            if !dst.exists() {
+                fs::create_dir_all(dst)?;
+            }
+            copy_asset(&entry_path, &dst_path, images_config, report)?;
+            report.copied += 1;
+        } else {
+            report.skipped += 1;
+        }
+        report.found.insert(asset);
+    }
+
+    Ok(())
+}
+
+// Path of an asset relative to its assets directory, with forward slashes as used in notes
+fn asset_key(assets_root: &Path, path: &Path) -> String {
+    path.strip_prefix(assets_root)
+        .unwrap_or(path)
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn find_and_copy_assets(
+    dir: &Path,
+    output_assets_dir: &Path,
+    selection: &AssetSelection,
+    images_config: &ImagesConfig,
+    report: &mut AssetReport,
+) -> std::io::Result<()> {
+    if dir.is_dir() {
+        // Check if the current directory is named "assets"
+        if dir.file_name().map_or(false, |name| name == "assets") {
+            let rel_path = dir.file_name().unwrap();
+            let target_dir = output_assets_dir;
+
+            copy_directory(dir, target_dir, dir, selection, images_config, report)?;
+            println!("Copied assets from {:?} to {:?}", dir, target_dir);
+        }
+
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let path = entry.path();
+
+            if path.is_dir() {
+                find_and_copy_assets(&path, output_assets_dir, selection, images_config, report)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+const EXIF_TAG_ORIENTATION: u16 = 0x0112;
+const EXIF_TAG_COPYRIGHT: u16 = 0x8298;
+
+// Copies a single asset, stripping privacy-sensitive metadata from images on the way
+fn copy_asset(
+    src: &Path,
+    dst: &Path,
+    images_config: &ImagesConfig,
+    report: &mut AssetReport,
+) -> std::io::Result<()> {
+    let extension = src
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+
+    if !images_config.strip_metadata
+        || !matches!(extension.as_str(), "jpg" | "jpeg" | "png" | "webp")
+    {
+        fs::copy(src, dst)?;
+        return Ok(());
+    }
+
+    let data = fs::read(src)?;
+    let kept_tags = images_config.kept_exif_tags();
+    let stripped = match extension.as_str() {
+        "png" => strip_png_metadata(&data, &kept_tags, images_config.keeps("copyright")),
+        "webp" => strip_webp_metadata(&data, &kept_tags),
+        _ => strip_jpeg_metadata(&data, &kept_tags),
+    };
+
+    match stripped {
+        Some(stripped) => {
+            if stripped.len() != data.len() {
+                report.stripped += 1;
+            }
+            fs::write(dst, stripped)
+        }
+        None => {
+            println!(
+                "Warning: Could not parse {:?}, copying without stripping metadata",
+                src
+            );
+            fs::copy(src, dst).map(|_| ())
+        }
+    }
+}
+
+// Removes APP1 (EXIF/XMP), APP2 (MPF), APP13 (IPTC) and comment segments, rebuilding EXIF
+// from the kept tags. Everything after the main image's end marker is dropped too: phones
+// append MPF secondary images there, each with its own EXIF
+fn strip_jpeg_metadata(data: &[u8], kept_tags: &[u16]) -> Option<Vec<u8>> {
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+
+    let mut output = Vec::with_capacity(data.len());
+    output.extend_from_slice(&data[..2]);
+    let mut pos = 2;
+
+    while pos + 1 < data.len() {
+        if data[pos] != 0xFF {
+            return None;
+        }
+
+        let marker = data[pos + 1];
+        match marker {
+            // Fill byte before a marker
+            0xFF => {
+                pos += 1;
+                continue;
+            }
+            // End of the main image
+            0xD9 => {
+                output.extend_from_slice(&data[pos..pos + 2]);
+                return Some(output);
+            }
+            // Markers without a length field
+            0x01 | 0xD0..=0xD7 => {
+                output.extend_from_slice(&data[pos..pos + 2]);
+                pos += 2;
+                continue;
+            }
+            _ => {}
+        }
+
+        if pos + 4 > data.len() {
+            return None;
+        }
+        let segment_len = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+        let segment_end = pos + 2 + segment_len;
+        if segment_len < 2 || segment_end > data.len() {
+            return None;
+        }
+        let payload = &data[pos + 4..segment_end];
+
+        match marker {
+            0xE1 if payload.starts_with(b"Exif\0\0") => {
+                if let Some(exif) = filter_exif(&payload[6..], kept_tags) {
+                    output.extend_from_slice(&[0xFF, 0xE1]);
+                    output.extend_from_slice(&((exif.len() + 8) as u16).to_be_bytes());
+                    output.extend_from_slice(b"Exif\0\0");
+                    output.extend_from_slice(&exif);
+                }
+            }
+            // XMP, extended XMP, the MPF index of secondary images, IPTC and comments
+            0xE1 | 0xED | 0xFE => {}
+            0xE2 if payload.starts_with(b"MPF\0") => {}
+            // Start of scan: the entropy-coded data runs up to the next marker, as bytes
+            // 0xFF inside it are followed by 0x00 or a restart marker
+            0xDA => {
+                let is_marker =
+                    |i: usize| data[i] == 0xFF && !matches!(data[i + 1], 0x00 | 0xD0..=0xD7 | 0xFF);
+                let mut scan_end = segment_end;
+                while scan_end + 1 < data.len() && !is_marker(scan_end) {
+                    scan_end += 1;
+                }
+                if scan_end + 1 >= data.len() {
+                    // No end marker, keep whatever image data there is
+                    scan_end = data.len();
+                }
+                output.extend_from_slice(&data[pos..scan_end]);
+                pos = scan_end;
+                continue;
+            }
+            _ => output.extend_from_slice(&data[pos..segment_end]),
+        }
+
+        pos = segment_end;
+    }
+
+    Some(output)
+}
+
+// Drops eXIf, text and timestamp chunks, keeping a copyright text chunk if allowed
+fn strip_png_metadata(data: &[u8], kept_tags: &[u16], keep_copyright: bool) -> Option<Vec<u8>> {
+    const SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+    if !data.starts_with(SIGNATURE) {
+        return None;
+    }
+
+    let mut output = Vec::with_capacity(data.len());
+    output.extend_from_slice(SIGNATURE);
+    let mut pos = SIGNATURE.len();
+
+    while pos + 12 <= data.len() {
+        let length =
+            u32::from_be_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]]) as usize;
+        let chunk_type = &data[pos + 4..pos + 8];
+        let chunk_end = pos + 12 + length;
+        if chunk_end > data.len() {
+            return None;
+        }
+        let payload = &data[pos + 8..pos + 8 + length];
+
+        match chunk_type {
+            b"eXIf" => {
+                if let Some(exif) = filter_exif(payload, kept_tags) {
+                    write_png_chunk(&mut output, b"eXIf", &exif);
+                }
+            }
+            b"tEXt" | b"zTXt" | b"iTXt" => {
+                // XMP lives in an iTXt chunk, so only a plain copyright entry survives
+                let keyword = payload.split(|&b| b == 0).next().unwrap_or(&[]);
+                if keep_copyright && keyword == b"Copyright" {
+                    output.extend_from_slice(&data[pos..chunk_end]);
+                }
+            }
+            b"tIME" => {}
+            _ => output.extend_from_slice(&data[pos..chunk_end]),
+        }
+
+        pos = chunk_end;
+        if chunk_type == b"IEND" {
+            break;
+        }
+    }
+
+    Some(output)
+}
+
+fn write_png_chunk(output: &mut Vec<u8>, chunk_type: &[u8], payload: &[u8]) {
+    output.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    output.extend_from_slice(chunk_type);
+    output.extend_from_slice(payload);
+
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in chunk_type.iter().chain(payload) {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    output.extend_from_slice(&(!crc).to_be_bytes());
+}
+
+// Drops the EXIF and XMP chunks of a RIFF container and clears their VP8X flags
+fn strip_webp_metadata(data: &[u8], kept_tags: &[u16]) -> Option<Vec<u8>> {
+    if data.len() < 12 || &data[0..4] != b"RIFF" || &data[8..12] != b"WEBP" {
+        return None;
+    }
+
+    let mut body = Vec::with_capacity(data.len());
+    let mut has_exif = false;
+    let mut pos = 12;
+
+    while pos + 8 <= data.len() {
+        let fourcc = &data[pos..pos + 4];
+        let size = u32::from_le_bytes([data[pos + 4], data[pos + 5], data[pos + 6], data[pos + 7]])
+            as usize;
+        let chunk_end = pos + 8 + size;
+        if chunk_end > data.len() {
+            return None;
+        }
+        // Chunks are padded to an even size
+        let padded_end = (chunk_end + (size & 1)).min(data.len());
+        let payload = &data[pos + 8..chunk_end];
+
+        match fourcc {
+            b"EXIF" => {
+                let tiff = payload.strip_prefix(b"Exif\0\0").unwrap_or(payload);
+                if let Some(exif) = filter_exif(tiff, kept_tags) {
+                    body.extend_from_slice(b"EXIF");
+                    body.extend_from_slice(&(exif.len() as u32).to_le_bytes());
+                    body.extend_from_slice(&exif);
+                    if exif.len() % 2 == 1 {
+                        body.push(0);
+                    }
+                    has_exif = true;
+                }
+            }
+            b"XMP " => {}
+            _ => body.extend_from_slice(&data[pos..padded_end]),
+        }
+
+        pos = padded_end;
+    }
+
+    // VP8X flags announce which metadata chunks are present
+    if body.len() > 8 && &body[0..4] == b"VP8X" {
+        body[8] &= !0x04; // XMP
+        if !has_exif {
+            body[8] &= !0x08; // EXIF
+        }
+    }
+
+    let mut output = Vec::with_capacity(body.len() + 12);
+    output.extend_from_slice(b"RIFF");
+    output.extend_from_slice(&((body.len() + 4) as u32).to_le_bytes());
+    output.extend_from_slice(b"WEBP");
+    output.extend_from_slice(&body);
+    Some(output)
+}
+
+// Rebuilds a TIFF-structured EXIF block containing only the kept IFD0 tags.
+// Returns None when none of the kept tags are present, so the block can be dropped.
+fn filter_exif(tiff: &[u8], kept_tags: &[u16]) -> Option<Vec<u8>> {
+    if kept_tags.is_empty() || tiff.len() < 8 {
+        return None;
+    }
+
+    let little_endian = match &tiff[0..2] {
+        b"II" => true,
+        b"MM" => false,
+        _ => return None,
+    };
+    let read_u16 = |pos: usize| -> Option<u16> {
+        let bytes = [*tiff.get(pos)?, *tiff.get(pos + 1)?];
+        Some(if little_endian {
+            u16::from_le_bytes(bytes)
+        } else {
+            u16::from_be_bytes(bytes)
+        })
+    };
+    let read_u32 = |pos: usize| -> Option<u32> {
+        let bytes = [
+            *tiff.get(pos)?,
+            *tiff.get(pos + 1)?,
+            *tiff.get(pos + 2)?,
+            *tiff.get(pos + 3)?,
+        ];
+        Some(if little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        })
+    };
+    let write_u16 = |value: u16| {
+        if little_endian {
+            value.to_le_bytes()
+        } else {
+            value.to_be_bytes()
+        }
+    };
+    let write_u32 = |value: u32| {
+        if little_endian {
+            value.to_le_bytes()
+        } else {
+            value.to_be_bytes()
+        }
+    };
+
+    // Collect (tag, type, count, raw value bytes) for every kept entry of IFD0
+    let ifd_offset = read_u32(4)? as usize;
+    let entry_count = read_u16(ifd_offset)? as usize;
+    let mut kept_entries = Vec::new();
+
+    for i in 0..entry_count {
+        let entry = ifd_offset + 2 + i * 12;
+        let tag = read_u16(entry)?;
+        if !kept_tags.contains(&tag) {
+            continue;
+        }
+
+        let value_type = read_u16(entry + 2)?;
+        let count = read_u32(entry + 4)?;
+        let type_size = match value_type {
+            1 | 2 | 6 | 7 => 1,
+            3 | 8 => 2,
+            4 | 9 | 11 => 4,
+            5 | 10 | 12 => 8,
+            _ => continue,
+        };
+        let value_len = type_size * count as usize;
+        let value_start = if value_len <= 4 {
+            entry + 8
+        } else {
+            read_u32(entry + 8)? as usize
+        };
+        let value = tiff.get(value_start..value_start + value_len)?;
+
+        kept_entries.push((tag, value_type, count, value.to_vec()));
+    }
+
+    if kept_entries.is_empty() {
+        return None;
+    }
+    kept_entries.sort_by_key(|(tag, _, _, _)| *tag);
+
+    // Header, then IFD0 (count, entries, next-IFD offset), then out-of-line values
+    let mut output = Vec::new();
+    output.extend_from_slice(&tiff[0..2]);
+    output.extend_from_slice(&write_u16(42));
+    output.extend_from_slice(&write_u32(8));
+    output.extend_from_slice(&write_u16(kept_entries.len() as u16));
+
+    let mut data_offset = 8 + 2 + kept_entries.len() * 12 + 4;
+    let mut data_area = Vec::new();
+
+    for (tag, value_type, count, value) in &kept_entries {
+        output.extend_from_slice(&write_u16(*tag));
+        output.extend_from_slice(&write_u16(*value_type));
+        output.extend_from_slice(&write_u32(*count));
+
+        if value.len() <= 4 {
+            let mut inline = value.clone();
+            inline.resize(4, 0);
+            output.extend_from_slice(&inline);
+        } else {
+            output.extend_from_slice(&write_u32(data_offset as u32));
+            data_area.extend_from_slice(value);
+            // Values start on word boundaries
+            if value.len() % 2 == 1 {
+                data_area.push(0);
+            }
+            data_offset += value.len() + value.len() % 2;
+        }
+    }
+
+    output.extend_from_slice(&write_u32(0));
+    output.extend_from_slice(&data_area);
+    Some(output)
+}
+
+/// Dimensions and generated variants of a published image
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ResponsiveImage {
+    width: u32,
+    height: u32,
+    // Resized variants as (width, path relative to the assets directory), smallest first
+    variants: Vec<(u32, String)>,
+    webp_variants: Vec<(u32, String)>,
+}
+
+/// Entry of the image cache manifest, reused while the source and settings are unchanged
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedImage {
+    fingerprint: String,
+    widths: Vec<u32>,
+    webp: bool,
+    image: ResponsiveImage,
+}
+
+// Reads dimensions of every published image and generates its resized variants.
+// Variants are kept in the cache directory so unchanged images are not re-encoded.
+fn build_image_catalog(
+    assets: &[String],
+    output_assets_dir: &Path,
+    config: &Config,
+) -> std::io::Result<HashMap<String, ResponsiveImage>> {
+    let mut catalog = HashMap::new();
+    if !config.images.responsive {
+        return Ok(catalog);
+    }
+
+    let cache_root = Path::new(&config.cache_dir).join("images");
+    fs::create_dir_all(&cache_root)?;
+    let manifest_path = cache_root.join("manifest.json");
+    let mut manifest: HashMap<String, CachedImage> = fs::read_to_string(&manifest_path)
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default();
+    let mut new_manifest = HashMap::new();
+
+    let mut widths = config.images.widths.clone();
+    widths.sort_unstable();
+    widths.dedup();
+
+    let mut from_cache = 0;
+    for asset in assets {
+        let extension = asset.rsplit('.').next().unwrap_or("").to_lowercase();
+        if !matches!(extension.as_str(), "jpg" | "jpeg" | "png" | "webp") {
+            continue;
+        }
+
+        let asset_path = output_assets_dir.join(asset);
+        let data = match fs::read(&asset_path) {
+            Ok(data) => data,
+            Err(_) => continue,
+        };
+
+        let fingerprint = format!("{:016x}", fnv1a_hash(&data));
+        // Lossless WebP copies of lossy JPEGs come out several times larger than the JPEGs
+        let webp = config.images.webp && extension == "png";
+        let cache_dir = cache_root.join(&fingerprint);
+
+        let cached = manifest.remove(asset).filter(|cached| {
+            cached.fingerprint == fingerprint
+                && cached.widths == widths
+                && cached.webp == webp
+                && cached
+                    .image
+                    .variants
+                    .iter()
+                    .chain(&cached.image.webp_variants)
+                    .all(|(_, variant)| cache_dir.join(variant_file_name(variant)).exists())
+        });
+
+        let image = match cached {
+            Some(cached) => {
+                from_cache += 1;
+                cached.image
+            }
+            None => {
+                match generate_image_variants(asset, &data, &extension, &widths, webp, &cache_dir) {
+                    Ok(image) => image,
+                    Err(e) => {
+                        println!("Warning: Could not process image assets/{}: {}", asset, e);
+                        continue;
+                    }
+                }
+            }
+        };
+
+        for (_, variant) in image.variants.iter().chain(&image.webp_variants) {
+            fs::copy(
+                cache_dir.join(variant_file_name(variant)),
+                output_assets_dir.join(variant),
+            )?;
+        }
+
+        new_manifest.insert(
+            asset.clone(),
+            CachedImage {
+                fingerprint,
+                widths: widths.clone(),
+                webp,
+                image: image.clone(),
+            },
+        );
+        catalog.insert(asset.clone(), image);
+    }
+
+    // Drop cache entries of images that are gone or changed, unless an image with the same
+    // content still uses them
+    let in_use: HashSet<&String> = new_manifest
+        .values()
+        .map(|cached: &CachedImage| &cached.fingerprint)
+        .collect();
+    for (_, stale) in manifest {
+        if !in_use.contains(&stale.fingerprint) {
+            let _ = fs::remove_dir_all(cache_root.join(stale.fingerprint));
+        }
+    }
+    fs::write(
+        &manifest_path,
+        serde_json::to_string_pretty(&new_manifest).unwrap_or_default(),
+    )?;
+
+    println!(
+        "Processed {} images ({} reused from cache)",
+        catalog.len(),
+        from_cache
+    );
+    Ok(catalog)
+}
+
+// File name of a variant inside its cache directory
+fn variant_file_name(variant: &str) -> &str {
+    variant.rsplit('/').next().unwrap_or(variant)
+}
+
+// Decodes an image, applies its EXIF orientation and writes every resized variant to the cache
+fn generate_image_variants(
+    asset: &str,
+    data: &[u8],
+    extension: &str,
+    widths: &[u32],
+    webp: bool,
+    cache_dir: &Path,
+) -> Result<ResponsiveImage, Box<dyn std::error::Error>> {
+    let mut decoder = ImageReader::new(std::io::Cursor::new(data))
+        .with_guessed_format()?
+        .into_decoder()?;
+    let orientation = decoder.orientation().unwrap_or(Orientation::NoTransforms);
+    let mut original = DynamicImage::from_decoder(decoder)?;
+    original.apply_orientation(orientation);
+
+    let (width, height) = (original.width(), original.height());
+    let stem = asset.rsplit_once('.').map_or(asset, |(stem, _)| stem);
+    fs::create_dir_all(cache_dir)?;
+
+    let mut variants = Vec::new();
+    let mut webp_variants = Vec::new();
+
+    for &variant_width in widths.iter().filter(|&&w| w < width) {
+        let resized = original.resize(variant_width, u32::MAX, FilterType::Lanczos3);
+        let variant = format!("{}-{}w.{}", stem, variant_width, extension);
+        write_image_variant(
+            &resized,
+            extension,
+            &cache_dir.join(variant_file_name(&variant)),
+        )?;
+        variants.push((variant_width, variant));
+
+        if webp {
+            let variant = format!("{}-{}w.webp", stem, variant_width);
+            write_image_variant(
+                &resized,
+                "webp",
+                &cache_dir.join(variant_file_name(&variant)),
+            )?;
+            webp_variants.push((variant_width, variant));
+        }
+    }
+
+    // The full-size WebP completes the WebP srcset for wide screens
+    if webp {
+        let variant = format!("{}-{}w.webp", stem, width);
+        write_image_variant(
+            &original,
+            "webp",
+            &cache_dir.join(variant_file_name(&variant)),
+        )?;
+        webp_variants.push((width, variant));
+    }
+
+    Ok(ResponsiveImage {
+        width,
+        height,
+        variants,
+        webp_variants,
+    })
+}
+
+fn write_image_variant(
+    image: &DynamicImage,
+    extension: &str,
+    path: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let file = std::io::BufWriter::new(File::create(path)?);
+    match extension {
+        "png" => image.write_with_encoder(PngEncoder::new(file))?,
+        // Only lossless WebP encoding is available
+        "webp" => DynamicImage::ImageRgba8(image.to_rgba8())
+            .write_with_encoder(WebPEncoder::new_lossless(file))?,
+        // JPEG has no alpha channel
+        _ => DynamicImage::ImageRgb8(image.to_rgb8())
+            .write_with_encoder(JpegEncoder::new_with_quality(file, 82))?,
+    }
+    Ok(())
+}
+
+fn find_sy_files(dir: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    if dir.is_dir() {
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let path = entry.path();
+
+            if path.is_dir() {
+                find_sy_files(&path, files)?;
+            } else if let Some(extension) = path.extension() {
+                if extension == "sy" {
+                    files.push(path);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// Meta and OpenGraph description of a note from its first paragraphs, HTML-escaped
+fn note_description(
+    note: &Note,
+    title: &str,
+    notes_map: &HashMap<String, Note>,
+    id_to_path: &HashMap<String, PathBuf>,
+    margin_info_tracker: &MarginInfoTracker,
+    render_context: &RenderContext,
+) -> String {
+    let mut description = String::new();
+    let mut paragraph_count = 0;
+
+    for block in &note.Children {
+        if block.Type == "NodeParagraph" || block.Type == "Paragraph" {
+            let paragraph_content = render_blocks(
+                &block.Children,
+                notes_map,
+                id_to_path,
+                margin_info_tracker,
+                render_context,
+                false,
+            );
+            let plain_text = readable_text(&paragraph_content);
+            for word in plain_text.split_whitespace() {
+                if !description.is_empty() {
+                    description.push(' ');
+                }
+                description.push_str(word);
+            }
+            paragraph_count += 1;
+
+            // Limit to 2-3 paragraphs for description
+            if paragraph_count >= 2 && description.graphemes(true).count() > 100 {
+                break;
+            }
+        }
+    }
+
+    // If no paragraphs found, use title
+    if description.is_empty() {
+        return escape_html(title);
+    }
+
+    escape_html(&smart_truncate_excerpt(&description, usize::MAX, 200))
+}
+
+// Renders a note as one of the generated pages ("index" or "404"), followed by extra_content
+#[allow(clippy::too_many_arguments)]
+fn generate_custom_page(
+    page_name: &str,
+    note_id: &str,
+    extra_content: &str,
+    notes_map: &HashMap<String, Note>,
+    id_to_path: &HashMap<String, PathBuf>,
+    output_dir: &Path,
+    all_tags: &HashSet<String>,
+    html_template: &str,
+    margin_info_tracker: &MarginInfoTracker,
+    render_context: &RenderContext,
+) -> std::io::Result<()> {
+    let note = &notes_map[note_id];
+    let title = if !note.Properties.title.is_empty() {
+        note.Properties.title.clone()
+    } else if page_name == "404" {
+        "Page not found".to_string()
+    } else {
+        "Notes Index".to_string()
+    };
+
+    // Extract creation date from ID if not present in properties
+    let created_date = if !note.Properties.created.is_empty() {
+        note.Properties.created.clone()
+    } else if note.ID.len() >= 14 {
+        // Extract timestamp from ID (format: YYYYMMDDhhmmss-xxx)
+        let timestamp = &note.ID[0..14];
+        timestamp.to_string()
+    } else {
+        String::new()
+    };
+
+    let mut html = html_template.replace("{{title}}", &title);
+    html = html.replace("{{article_title}}", &title);
+    html = html.replace("{{css_path}}", &render_context.urls.asset_url("styles.css"));
+    html = html.replace("{{site_name}}", "SyMark");
+
+    let description = note_description(
+        note,
+        &title,
+        notes_map,
+        id_to_path,
+        margin_info_tracker,
+        render_context,
+    );
+    html = html.replace("{{meta_description}}", &description);
+    html = html.replace("{{blog_description}}", "A collection of notes");
+    html = html.replace("{{back_navigation}}", "");
+
+    // Define site base URL for OpenGraph - this should be configurable in the future
+    let site_base_url = "https://du82.github.io/symark";
+
+    // OpenGraph URL
+    html = html.replace("{{og_url}}", &format!("{}", site_base_url));
+
+    // Set OpenGraph published time in ISO 8601 format
+    if !created_date.is_empty() && created_date.len() >= 14 {
+        let og_published_time = format!(
+            "{}-{}-{}T{}:{}:{}Z",
+            &created_date[0..4],
+            &created_date[4..6],
+            &created_date[6..8],
+            &created_date[8..10],
+            &created_date[10..12],
+            &created_date[12..14]
+        );
+        html = html.replace("{{og_published_time}}", &og_published_time);
+    } else {
+        let now = Local::now();
+        let og_published_time = now.format("%Y-%m-%dT%H:%M:%SZ").to_string();
+        html = html.replace("{{og_published_time}}", &og_published_time);
+    }
+
+    if !note.Properties.updated.is_empty() && note.Properties.updated != created_date {
+        if note.Properties.updated.len() >= 14 {
+            let og_modified_time = format!(
+                "{}-{}-{}T{}:{}:{}Z",
+                &note.Properties.updated[0..4],
+                &note.Properties.updated[4..6],
+                &note.Properties.updated[6..8],
+                &note.Properties.updated[8..10],
+                &note.Properties.updated[10..12],
+                &note.Properties.updated[12..14]
+            );
+            html = html.replace("{{og_modified_time}}", &og_modified_time);
+        } else {
+            html = html.replace("{{og_modified_time}}", "");
+        }
+    } else {
+        html = html.replace("{{og_modified_time}}", "");
+    }
+
+    // Add header image if it exists
+    if !note.Properties.title_img.is_empty() {
+        html = html.replace("{{#header_image}}", "");
+        html = html.replace("{{/header_image}}", "");
+        html = html.replace(
+            "{{header_image}}",
+            &render_context
+                .urls
+                .rebase_assets(&note.Properties.title_img),
+        );
+    } else {
+        // Remove header image section if no image
+        html = html.replace("{{#header_image}}", "<!-- ");
+        html = html.replace("{{/header_image}}", " -->");
+    }
+    html = html.replace("{{blog_description}}", "A collection of notes");
+    html = html.replace("{{author_name}}", "Notes Author");
+    html = html.replace("{{publish_date}}", &naturalize_date(&created_date));
+
+    let formatted_date =
+        if !note.Properties.updated.is_empty() && note.Properties.updated != created_date {
+            if note.Properties.updated.len() >= 14 {
+                let og_modified_time = format!(
+                    "{}-{}-{}T{}:{}:{}Z",
+                    &note.Properties.updated[0..4],
+                    &note.Properties.updated[4..6],
+                    &note.Properties.updated[6..8],
+                    &note.Properties.updated[8..10],
+                    &note.Properties.updated[10..12],
+                    &note.Properties.updated[12..14]
+                );
+                html = html.replace("{{og_modified_time}}", &og_modified_time);
+            } else {
+                html = html.replace(
+                    "<meta property=\"article:modified_time\" content=\"{{og_modified_time}}\">",
+                    "",
+                );
+            }
+
+            format!(
+                "Created on {}, updated on {}",
+                naturalize_date(&created_date),
+                naturalize_date(&note.Properties.updated)
+            )
+        } else {
+            // If no image, remove the OpenGraph image tag
+            html = html.replace("<meta property=\"og:image\" content=\"{{og_image}}\">", "");
+
+            format!("Created on {}", naturalize_date(&created_date))
+        };
+    html = html.replace("{{last_updated_date}}", &formatted_date);
+
+    html = html.replace("{{category}}", "Notes");
+    html = html.replace("{{next_article_url}}", "#");
+    html = html.replace("{{next_article_title}}", "");
+    html = html.replace("{{back_navigation}}", "");
+
+    let mut toc_items = Vec::new();
+    let mut id_counter = 0;
+    extract_toc_items(&note.Children, &mut toc_items, &mut id_counter);
+
+    let toc_html = generate_toc_html(&toc_items);
+    html = html.replace("{{table_of_contents}}", &toc_html);
+
+    // Generate content with heading IDs for TOC, with the page itself at the bottom of
+    // the transclusion stack so embeds of this note are caught as circular
+    render_context.linked_virtual_refs.borrow_mut().clear();
+    render_context
+        .transclusion_stack
+        .borrow_mut()
+        .push(note.ID.clone());
+    let content_html = render_blocks_with_ids(
+        &note.Children,
+        notes_map,
+        id_to_path,
+        margin_info_tracker,
+        render_context,
+        false,
+    );
+    render_context.transclusion_stack.borrow_mut().pop();
+
+    let reading = &render_context.config.reading;
+    let stats = TextStats::from_html(&content_html);
+    html = stats.fill_template(&html, reading);
+
+    html = html.replace(
+        "{{content}}",
+        &format!("{}\n{}", content_html, extra_content),
+    );
+    html = html.replace(
+        "{{backlinks}}",
+        &generate_backlinks_html(
+            note,
+            notes_map,
+            id_to_path,
+            margin_info_tracker,
+            render_context,
+        ),
+    );
+    html = html.replace(
+        "{{unlinked_mentions}}",
+        &generate_unlinked_mentions_html(
+            note,
+            notes_map,
+            id_to_path,
+            margin_info_tracker,
+            render_context,
+        ),
+    );
+    html = html.replace(
+        "{{local_graph}}",
+        &generate_local_graph_html(note, notes_map, output_dir, render_context)?,
+    );
+
+    let mut meta = String::new();
+
+    // Display creation date as a tag
+    if !created_date.is_empty() {
+        meta.push_str(&format!(
+            "<span class=\"meta-tag date-tag\">Created: {}</span>",
+            naturalize_date(&created_date)
+        ));
+    }
+
+    // Display update date if it's different from creation date
+    if !note.Properties.updated.is_empty() && note.Properties.updated != created_date {
+        meta.push_str(&format!(
+            "<span class=\"meta-tag date-tag\">Updated: {}</span>",
+            naturalize_date(&note.Properties.updated)
+        ));
+    }
+
+    if stats.word_count() > 0 {
+        meta.push_str(&stats.meta_html(reading));
+    }
+
+    // Add tags
+    if !note.Properties.tags.is_empty() {
+        let mut tags: Vec<_> = note
+            .Properties
+            .tags
+            .split(',')
+            .map(|t| t.trim())
+            .filter(|t| !PAGE_TAGS.contains(t))
+            .collect();
+        tags.sort();
+
+        for tag in tags {
+            if !tag.is_empty() {
+                meta.push_str(&format!(
+                    "<a href=\"{}\" class=\"meta-tag\">{}</a>",
+                    render_context.urls.tag_url(tag),
+                    tag
+                ));
+            }
+        }
+    }
+
+    html = html.replace("{{note_meta}}", &meta);
+    html = html.replace(
+        "{{generation_date}}",
+        &Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+    );
+
+    // Remove zero-width spaces and clean up any remaining template variables
+    let cleaned_html = remove_zero_width_spaces(&html);
+    let cleaned_html = cleanup_template_variables(&html);
+
+    let final_html = comment_processor(&cleaned_html);
+
+    // Write to file
+    let file_path = output_dir.join(render_context.urls.page_file(page_name));
+    let mut file = create_page_file(&file_path)?;
+    file.write_all(final_html.as_bytes())?;
+
+    Ok(())
+}
+
+fn generate_all_notes_page(
+    notes_map: &HashMap<String, Note>,
+    output_dir: &Path,
+    all_tags: &HashSet<String>,
+    urls: &SiteUrls,
+    html_template: &str,
+    reading: &ReadingStats,
+) -> std::io::Result<()> {
+    let mut html = html_template.replace("{{title}}", "All Notes");
+    html = html.replace("{{article_title}}", "All Notes");
+    html = html.replace("{{css_path}}", &urls.asset_url("styles.css"));
+    html = html.replace("{{site_name}}", "SyMark");
+    html = html.replace("{{meta_description}}", "Collection of all notes");
+
+    // Define site base URL for OpenGraph - this should be configurable in the future
+    let site_base_url = "https://du82.github.io/symark";
+
+    html = html.replace("{{og_url}}", &format!("{}", site_base_url));
+
+    // Set OpenGraph published time in ISO 8601 format
+    let now_iso = Local::now();
+    let og_published_time = now_iso.format("%Y-%m-%dT%H:%M:%SZ").to_string();
+    html = html.replace("{{og_published_time}}", &og_published_time);
+
+    // No modified time for all notes page
+    html = html.replace("{{og_modified_time}}", "");
+
+    // Remove OpenGraph image tag if no image
+    html = html.replace("<meta property=\"og:image\" content=\"{{og_image}}\">", "");
+    html = html.replace("{{blog_description}}", "A collection of all notes");
+    let reading_total = reading.total(notes_map.keys());
+    html = reading_total.fill_template(&html, reading.config);
+    html = html.replace("{{author_name}}", "Notes Author");
+
+    // Use current timestamp for publish date
+    let now = Local::now().format("%Y%m%d%H%M%S").to_string();
+    html = html.replace("{{publish_date}}", &naturalize_date(&now));
+
+    let formatted_date = format!("Created on {}", naturalize_date(&now));
+    html = html.replace("{{last_updated_date}}", &formatted_date);
+    // Create tag cloud metadata for all notes page
+    let meta = format!(
+        "<span class=\"meta-tag date-tag\">Created on {}</span>{}",
+        naturalize_date(&now),
+        reading_total.meta_html(reading.config)
+    );
+    html = html.replace("{{note_meta}}", &meta);
+    html = html.replace("{{last_updated_date}}", ""); // Clear this as we're using note_meta
+
+    html = html.replace("{{category}}", "Notes");
+    html = html.replace("{{next_article_url}}", "#");
+    html = html.replace("{{next_article_title}}", "");
+    html = html.replace("{{back_navigation}}", &back_navigation_html(urls));
+
+    // No header image for all notes page
+    html = html.replace("{{#header_image}}", "<!-- ");
+    html = html.replace("{{/header_image}}", " -->");
+
+    // Generate navigation items - sort by title for better navigation
+    let mut sorted_notes: Vec<_> = notes_map
+        .values()
+        .filter(|n| !n.Properties.title.is_empty())
+        .collect();
+    sorted_notes.sort_by(|a, b| a.Properties.title.cmp(&b.Properties.title));
+
+    let mut nav_items = String::new();
+    for note in &sorted_notes {
+        nav_items.push_str(&format!(
+            "<li><a href=\"{}\">{}</a></li>\n",
+            urls.note_url(&note.ID),
+            note.Properties.title
+        ));
+    }
+
+    // Generate table of contents
+    let mut toc_items = Vec::new();
+    let mut id_counter = 0;
+
+    toc_items.push(TocItem {
+        id: "section-all-notes".to_string(),
+        text: "All Notes".to_string(),
+        level: 2,
+    });
+
+    toc_items.push(TocItem {
+        id: "section-tags".to_string(),
+        text: "Tags".to_string(),
+        level: 2,
+    });
+
+    toc_items.push(TocItem {
+        id: "section-graph".to_string(),
+        text: "Content Graph".to_string(),
+        level: 2,
+    });
+
+    let toc_html = generate_toc_html(&toc_items);
+    html = html.replace("{{table_of_contents}}", &toc_html);
+
+    // Generate tags section - sort alphabetically
+    let mut tags: Vec<_> = all_tags.iter().collect();
+    tags.sort();
+
+    let mut tags_html = String::new();
+    for tag in tags {
+        tags_html.push_str(&format!(
+            "<a href=\"{}\" class=\"tag\">{}</a>\n",
+            urls.tag_url(tag),
+            tag
+        ));
+    }
+
+    // Generate content
+    let mut content = String::from("\n<ul>\n");
+    for note in &sorted_notes {
+        content.push_str(&format!(
+            "<li><a href=\"{}\">{}</a></li>\n",
+            urls.note_url(&note.ID),
+            note.Properties.title
+        ));
+    }
+    content.push_str("</ul>");
+
+    content.push_str("<h2 id=\"section-tags\">Tags</h2>\n<div class=\"tags-container\">\n");
+    content.push_str(&tags_html);
+    content.push_str("</div>");
+
+    html = html.replace("{{content}}", &content);
+
+    // Set metadata
+    html = html.replace("{{note_meta}}", "");
+    html = html.replace(
+        "{{generation_date}}",
+        &Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+    );
+
+    // Remove zero-width spaces and clean up template variables before writing to file
+    let cleaned_html = remove_zero_width_spaces(&html);
+    let cleaned_html = cleanup_template_variables(&cleaned_html);
+
+    let final_html = comment_processor(&cleaned_html);
+
+    // Write to file - use all.html, not index.html to avoid overwriting the custom index
+    let all_notes_path = output_dir.join(urls.page_file("all"));
+    let mut file = create_page_file(&all_notes_path)?;
+    file.write_all(final_html.as_bytes())?;
+
+    Ok(())
+}
+
+fn generate_index_page(
+    notes_map: &HashMap<String, Note>,
+    output_dir: &Path,
+    all_tags: &HashSet<String>,
+    urls: &SiteUrls,
+    html_template: &str,
+    reading: &ReadingStats,
+) -> std::io::Result<()> {
+    let mut html = html_template.replace("{{title}}", "Notes Index");
+    html = html.replace("{{article_title}}", "Notes Index");
+    html = html.replace("{{css_path}}", &urls.asset_url("styles.css"));
+    html = html.replace("{{site_name}}", "SyMark");
+    html = html.replace("{{meta_description}}", "Collection of all notes");
+    html = html.replace("{{blog_description}}", "A collection of all notes");
+    html = html.replace("{{back_navigation}}", "");
+
+    // Define site base URL for OpenGraph - this should be configurable in the future
+    let site_base_url = "https://du82.github.io/symark";
+
+    // OpenGraph URL
+    html = html.replace("{{og_url}}", &format!("{}", site_base_url));
+
+    // Set OpenGraph published time in ISO 8601 format
+    let now_iso = Local::now();
+    let og_published_time = now_iso.format("%Y-%m-%dT%H:%M:%SZ").to_string();
+    html = html.replace("{{og_published_time}}", &og_published_time);
+
+    // No modified time for index page
+    html = html.replace("{{og_modified_time}}", "");
+
+    // Remove OpenGraph image tag if no image
+    html = html.replace("<meta property=\"og:image\" content=\"{{og_image}}\">", "");
+
+    html = html.replace("{{#header_image}}", "<!-- ");
+    html = html.replace("{{/header_image}}", " -->");
+    html = html.replace("{{header_image}}", "");
+    html = html.replace("{{blog_description}}", "A collection of all notes");
+    let reading_total = reading.total(notes_map.keys());
+    html = reading_total.fill_template(&html, reading.config);
+    html = html.replace("{{author_name}}", "Notes Author");
+    let now = Local::now().format("%Y%m%d%H%M%S").to_string();
+    html = html.replace("{{publish_date}}", &naturalize_date(&now));
+
+    let formatted_date = format!("Created on {}", naturalize_date(&now));
+    html = html.replace("{{last_updated_date}}", &formatted_date);
+
+    html = html.replace("{{category}}", "Notes");
+    html = html.replace("{{next_article_url}}", "#");
+    html = html.replace("{{next_article_title}}", "");
+    html = html.replace("{{back_navigation}}", "");
+
+    // Generate navigation items - sort by title for better navigation
+    let mut sorted_notes: Vec<_> = notes_map
+        .values()
+        .filter(|n| !n.Properties.title.is_empty())
+        .collect();
+    sorted_notes.sort_by(|a, b| a.Properties.title.cmp(&b.Properties.title));
+
+    let mut nav_items = String::new();
+    for note in &sorted_notes {
+        nav_items.push_str(&format!(
+            "<li><a href=\"{}\">{}</a></li>\n",
+            urls.note_url(&note.ID),
+            note.Properties.title
+        ));
+    }
+
+    // Generate table of contents
+    let mut toc_items = Vec::new();
+    let mut id_counter = 0;
+
+    toc_items.push(TocItem {
+        id: "section-all-notes".to_string(),
+        text: "All Notes".to_string(),
+        level: 2,
+    });
+
+    toc_items.push(TocItem {
+        id: "section-tags".to_string(),
+        text: "Tags".to_string(),
+        level: 2,
+    });
+
+    toc_items.push(TocItem {
+        id: "section-graph".to_string(),
+        text: "Content Graph".to_string(),
+        level: 2,
+    });
+
+    let toc_html = generate_toc_html(&toc_items);
+    html = html.replace("{{table_of_contents}}", &toc_html);
+
+    // Generate tags section - sort alphabetically
+    let mut tags: Vec<_> = all_tags.iter().collect();
+    tags.sort();
+
+    let mut tags_html = String::new();
+    for tag in tags {
+        tags_html.push_str(&format!(
+            "<a href=\"{}\" class=\"tag\">{}</a>\n",
+            urls.tag_url(tag),
+            tag
+        ));
+    }
+
+    // Generate content
+    let mut content = String::from("<h2 id=\"section-all-notes\">All Notes</h2>\n<ul>\n");
+    for note in &sorted_notes {
+        content.push_str(&format!(
+            "<li><a href=\"{}\">{}</a></li>\n",
+            urls.note_url(&note.ID),
+            note.Properties.title
+        ));
+    }
+    content.push_str("</ul>");
+
+    content.push_str("<h2 id=\"section-tags\">Tags</h2>\n<div class=\"tags-container\">\n");
+    content.push_str(&tags_html);
+    content.push_str("</div>");
+
+    // Add graph visualization section
+    content.push_str("<h2 id=\"section-graph\">Content Graph</h2>\n");
+    content.push_str(
+        "<p>Explore the connections between notes in an interactive visualization.</p>\n",
+    );
+    content.push_str(&format!(
+        "<p><a href=\"{}\" class=\"nav-link\">View Content Graph</a></p>\n",
+        urls.page_url("graph")
+    ));
+
+    html = html.replace("{{content}}", &content);
+
+    // Set metadata
+    html = html.replace("{{note_meta}}", &reading_total.meta_html(reading.config));
+    html = html.replace(
+        "{{generation_date}}",
+        &Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+    );
+
+    // Remove zero-width spaces and clean up template variables before writing to file
+    let cleaned_html = remove_zero_width_spaces(&html);
+    let cleaned_html = cleanup_template_variables(&cleaned_html);
+
+    let final_html = comment_processor(&cleaned_html);
+
+    // Write to file
+    let all_notes_path = output_dir.join(urls.page_file("all"));
+    let mut file = create_page_file(&all_notes_path)?;
+    file.write_all(final_html.as_bytes())?;
+
+    Ok(())
+}
+
+fn generate_graph_page(
+    notes_map: &HashMap<String, Note>,
+    output_dir: &Path,
+    tag_colors: &BTreeMap<String, String>,
+    link_graph: &LinkGraph,
+    config: &GraphConfig,
+    urls: &SiteUrls,
+    graph_template: &str,
+) -> std::io::Result<()> {
+    println!("Generating graph page");
+
+    let graph_nodes = collect_graph_nodes(notes_map, link_graph, urls);
+    let edges = link_graph.sorted_edges();
+
+    // Large graphs are laid out here, since the browser can't simulate them fast enough
+    let server_layout = match config.layout.as_str() {
+        "server" => true,
+        "client" => false,
+        _ => graph_nodes.len() >= config.layout_threshold,
+    };
+    let layout = if server_layout {
+        println!("Computing graph layout for {} nodes", graph_nodes.len());
+        Some(layout_graph(&graph_nodes, &edges, config.layout_iterations))
+    } else {
+        None
+    };
 
-                notes_map.insert(id, note);
-            }
-            Err(e) => {
-                eprintln!("Error parsing file {:?}: {}", path, e);
+    let nodes: Vec<_> = graph_nodes
+        .iter()
+        .enumerate()
+        .map(|(index, node)| {
+            let mut value = json!({
+                "id": node.id,
+                "kind": node.kind,
+                "url": node.url,
+                "title": node.title,
+                "tags": node.tags,
+                "connections": node.connections
+            });
+            if let Some(layout) = &layout {
+                let (x, y) = layout.positions[index];
+                value["x"] = json!((x * 10.0).round() / 10.0);
+                value["y"] = json!((y * 10.0).round() / 10.0);
+                value["community"] = json!(layout.communities[index]);
+                value["lod"] = json!(layout.lod[index]);
             }
-        }
+            value
+        })
+        .collect();
+
+    // Links point from the referencing note (or child, or tagged note) to the other end
+    let links: Vec<_> = edges
+        .iter()
+        .map(|(source, target, kind, weight)| {
+            json!({
+                "source": source,
+                "target": target,
+                "kind": kind,
+                "weight": weight,
+                "value": weight
+            })
+        })
+        .collect();
+
+    // Create the graph data JSON
+    let graph_data = json!({
+        "layout": if server_layout { "server" } else { "client" },
+        "nodes": nodes,
+        "links": links
+    });
+
+    // Create tag color JSON
+    let tag_colors_json = json!(tag_colors);
+
+    // Generate tag color HTML blocks
+    let mut tag_color_blocks = String::new();
+    for (tag, color) in tag_colors {
+        tag_color_blocks.push_str(&format!(
+            "<span style=\"display: inline-block; padding: 5px 10px; background: {}; color: white; border-radius: 4px;\">{}</span>\n",
+            escape_html(color),
+            escape_html(tag)
+        ));
     }
+    // Add default color
+    tag_color_blocks.push_str(
+        "<span style=\"display: inline-block; padding: 5px 10px; background: #69b3a2; color: white; border-radius: 4px;\">Default</span>\n"
+    );
 
-    // Collect margin info (transclusions and linked mentions)
-    println!("Collecting margin info...");
-    let margin_info_tracker = collect_margin_info(&notes_map);
+    // Process the graph template
+    let mut graph_html = graph_template.to_string();
 
-    println!("Reading HTML template...");
-    let html_template_path = format!("themes/{}/page.html", theme_name);
-    let html_template = read_template(&html_template_path);
+    // Replace template variables
+    graph_html = graph_html.replace("{{site_name}}", "SyMark");
 
-    if let Some(index_id) = &index_note_id {
-        println!("Generating custom index page with ID: {}", index_id);
+    // Remove tag colors section since we're using a full-screen layout
+    graph_html = graph_html.replace("{{#tag_colors}}", "<!-- ");
+    graph_html = graph_html.replace("{{/tag_colors}}", " -->");
+    graph_html = graph_html.replace("{{tag_color_blocks}}", "");
 
-        generate_custom_index_page(
-            index_id,
-            &notes_map,
-            &id_to_path,
-            &output_dir,
-            &all_tags,
-            &html_template,
-            &margin_info_tracker,
-        )?;
-        page_count += 1;
+    // Insert the tag colors JSON
+    graph_html = graph_html.replace("{{tag_colors_json}}", &tag_colors_json.to_string());
 
-        generate_all_notes_page(&notes_map, &output_dir, &all_tags, &html_template)?;
-        page_count += 1;
-    } else {
-        generate_index_page(&notes_map, &output_dir, &all_tags, &html_template)?;
-        page_count += 1;
-    }
+    // Insert the graph data
+    graph_html = graph_html.replace(
+        "const graphData = {\n            nodes: [],\n            links: []\n        };",
+        &format!("const graphData = {};", graph_data.to_string()),
+    );
 
-    println!("Generating HTML for each note...");
-    for (id, note) in &notes_map {
-        println!("Generating HTML for note: {}", id);
-        generate_html_for_note(
-            id,
-            &notes_map,
-            &id_to_path,
-            &output_dir,
-            &all_tags,
-            &html_template,
-            &margin_info_tracker,
-        )?;
-        page_count += 1;
-    }
+    // Generate the HTML file
+    let output_path = output_dir.join(urls.page_file("graph"));
+    let mut file = create_page_file(&output_path)?;
 
-    println!("Generating tag pages...");
-    for tag in &all_tags {
-        println!("Generating page for tag: {}", tag);
-        generate_tag_page(tag, &notes_map, &output_dir, &all_tags, &html_template)?;
-        page_count += 1;
-    }
+    // Clean up any remaining template variables
+    let cleaned_html = remove_zero_width_spaces(&graph_html);
+    let cleaned_html = cleanup_template_variables(&cleaned_html);
 
-    println!("Generating graph page...");
-    let graph_template_path = format!("themes/{}/graph.html", theme_name);
-    let graph_template = read_template(&graph_template_path);
-    generate_graph_page(&notes_map, &output_dir, &all_tags, &graph_template)?;
-    page_count += 1;
+    file.write_all(cleaned_html.as_bytes())?;
 
-    let elapsed = start_time.elapsed();
-    let elapsed_ms = elapsed.as_millis();
+    println!("Generated graph page: {:?}", output_path);
 
-    if elapsed_ms < 1000 {
-        println!("✓ Built {} pages in {} ms", page_count, elapsed_ms);
-    } else {
-        let elapsed_sec = elapsed.as_secs_f64();
-        println!("✓ Built {} pages in {:.2} s", page_count, elapsed_sec);
-    }
+    write_graph_exports(&graph_nodes, &edges, output_dir, &config.exports)
+}
 
-    println!("Checking output directory:");
-    match fs::read_dir(&output_dir) {
-        Ok(entries) => {
-            let entries: Vec<_> = entries.collect();
-            if entries.is_empty() {
-                println!("  Warning: Output directory is empty!");
-            } else {
-                for entry in entries {
-                    if let Ok(entry) = entry {
-                        println!("  - {:?}", entry.path());
-                    }
-                }
+// Build-time layout of the graph page: a position, community and level of detail per node,
+// in the order of the nodes it was computed from
+struct GraphLayout {
+    positions: Vec<(f64, f64)>,
+    communities: Vec<usize>,
+    // 0 for the nodes shown at any zoom, 1 for those shown from medium zoom, 2 for the rest
+    lod: Vec<u8>,
+}
+
+fn layout_graph(
+    nodes: &[GraphNode],
+    edges: &[(&str, &str, &'static str, usize)],
+    iterations: usize,
+) -> GraphLayout {
+    let index: HashMap<&str, usize> = nodes
+        .iter()
+        .enumerate()
+        .map(|(i, node)| (node.id.as_str(), i))
+        .collect();
+
+    // Undirected, with the weights of links between the same two nodes added up
+    let mut weights: HashMap<(usize, usize), f64> = HashMap::new();
+    for (source, target, _, weight) in edges {
+        match (index.get(source), index.get(target)) {
+            (Some(&a), Some(&b)) if a != b => {
+                *weights.entry((a.min(b), a.max(b))).or_insert(0.0) += *weight as f64;
             }
+            _ => {}
         }
-        Err(e) => println!("  Error reading output directory: {}", e),
+    }
+    let mut links: Vec<(usize, usize, f64)> =
+        weights.into_iter().map(|((a, b), w)| (a, b, w)).collect();
+    links.sort_by_key(|&(a, b, _)| (a, b));
+
+    let mut adjacency: Vec<Vec<(usize, f64)>> = vec![Vec::new(); nodes.len()];
+    for &(a, b, weight) in &links {
+        adjacency[a].push((b, weight));
+        adjacency[b].push((a, weight));
     }
 
-    println!(
-        "HTML generation complete. Output written to {:?}",
-        output_dir
+    let communities = detect_communities(&adjacency);
+    let positions = force_layout(
+        &links,
+        &adjacency,
+        spiral_positions(&communities),
+        iterations,
     );
-    Ok(())
-}
 
-fn copy_directory(src: &Path, dst: &Path) -> std::io::Result<()> {
-    if !dst.exists() {
-        fs::create_dir_all(dst)?;
+    // The best connected nodes stay visible when zoomed out, along with the hub of each community
+    let mut ranked: Vec<usize> = (0..nodes.len()).collect();
+    ranked.sort_by_key(|&i| (std::cmp::Reverse(adjacency[i].len()), i));
+    let mut lod = vec![2u8; nodes.len()];
+    let top = (nodes.len() / 100).max(50);
+    let medium = (nodes.len() / 10).max(500);
+    let mut community_sizes: HashMap<usize, usize> = HashMap::new();
+    for &community in &communities {
+        *community_sizes.entry(community).or_insert(0) += 1;
     }
-
-    for entry in fs::read_dir(src)? {
-        let entry = entry?;
-        let entry_path = entry.path();
-        let file_name = entry.file_name();
-        let dst_path = dst.join(file_name);
-
-        if entry_path.is_dir() {
-            copy_directory(&entry_path, &dst_path)?;
-        } else {
-            fs::copy(&entry_path, &dst_path)?;
+    let mut hubs = HashSet::new();
+    for (rank, &i) in ranked.iter().enumerate() {
+        let hub = community_sizes[&communities[i]] >= 5 && hubs.insert(communities[i]);
+        if rank < top || hub {
+            lod[i] = 0;
+        } else if rank < medium {
+            lod[i] = 1;
         }
     }
 
-    Ok(())
+    GraphLayout {
+        positions,
+        communities,
+        lod,
+    }
 }
 
-fn find_and_copy_assets(dir: &Path, output_assets_dir: &Path) -> std::io::Result<()> {
-    if dir.is_dir() {
-        // Check if the current directory is named "assets"
-        if dir.file_name().map_or(false, |name| name == "assets") {
-            let rel_path = dir.file_name().unwrap();
-            let target_dir = output_assets_dir;
+// Groups densely linked nodes with the Louvain method: nodes move to the neighboring community
+// that raises modularity the most, then each community becomes a single node and the process
+// repeats on the smaller graph. Communities are numbered from the largest.
+fn detect_communities(adjacency: &[Vec<(usize, f64)>]) -> Vec<usize> {
+    let mut membership: Vec<usize> = (0..adjacency.len()).collect();
+    let mut graph = adjacency.to_vec();
 
-            copy_directory(dir, target_dir)?;
-            println!("Copied assets from {:?} to {:?}", dir, target_dir);
-        }
+    for _ in 0..10 {
+        let labels = louvain_local_moving(&graph);
 
-        for entry in fs::read_dir(dir)? {
-            let entry = entry?;
-            let path = entry.path();
+        // Number the communities in order of appearance
+        let mut numbers: HashMap<usize, usize> = HashMap::new();
+        let labels: Vec<usize> = labels
+            .iter()
+            .map(|label| {
+                let next = numbers.len();
+                *numbers.entry(*label).or_insert(next)
+            })
+            .collect();
+        if numbers.len() == graph.len() {
+            break;
+        }
+        for community in membership.iter_mut() {
+            *community = labels[*community];
+        }
 
-            if path.is_dir() {
-                find_and_copy_assets(&path, output_assets_dir)?;
+        // Links inside a community become a self-loop of its node, which keeps its degree
+        let mut weights: HashMap<(usize, usize), f64> = HashMap::new();
+        for (node, links) in graph.iter().enumerate() {
+            for &(neighbor, weight) in links {
+                *weights
+                    .entry((labels[node], labels[neighbor]))
+                    .or_insert(0.0) += weight;
             }
         }
+        let mut aggregated: Vec<((usize, usize), f64)> = weights.into_iter().collect();
+        aggregated.sort_by_key(|&(key, _)| key);
+        graph = vec![Vec::new(); numbers.len()];
+        for ((a, b), weight) in aggregated {
+            graph[a].push((b, weight));
+        }
     }
-    Ok(())
+
+    let mut sizes: HashMap<usize, usize> = HashMap::new();
+    for &community in &membership {
+        *sizes.entry(community).or_insert(0) += 1;
+    }
+    let mut order: Vec<(usize, usize)> = sizes.into_iter().collect();
+    order.sort_by_key(|&(community, size)| (std::cmp::Reverse(size), community));
+    let numbers: HashMap<usize, usize> = order
+        .iter()
+        .enumerate()
+        .map(|(number, &(community, _))| (community, number))
+        .collect();
+
+    membership
+        .iter()
+        .map(|community| numbers[community])
+        .collect()
 }
 
-fn find_sy_files(dir: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
-    if dir.is_dir() {
-        for entry in fs::read_dir(dir)? {
-            let entry = entry?;
-            let path = entry.path();
+// One Louvain pass over `graph`, whose lists hold every link in both directions: returns the
+// community label of each node
+fn louvain_local_moving(graph: &[Vec<(usize, f64)>]) -> Vec<usize> {
+    let mut labels: Vec<usize> = (0..graph.len()).collect();
+    let degrees: Vec<f64> = graph
+        .iter()
+        .map(|links| links.iter().map(|(_, weight)| weight).sum())
+        .collect();
+    let total_weight: f64 = degrees.iter().sum();
+    if total_weight == 0.0 {
+        return labels;
+    }
+    // Sum of the degrees in each community
+    let mut community_degrees = degrees.clone();
+
+    for _ in 0..50 {
+        let mut moved = false;
+        for node in 0..graph.len() {
+            if degrees[node] == 0.0 {
+                continue;
+            }
+            let current = labels[node];
+            community_degrees[current] -= degrees[node];
 
-            if path.is_dir() {
-                find_sy_files(&path, files)?;
-            } else if let Some(extension) = path.extension() {
-                if extension == "sy" {
-                    files.push(path);
+            let mut links_to: HashMap<usize, f64> = HashMap::new();
+            for &(neighbor, weight) in &graph[node] {
+                if neighbor != node {
+                    *links_to.entry(labels[neighbor]).or_insert(0.0) += weight;
+                }
+            }
+
+            // Ties go to the smallest label so the result is the same between builds
+            let gain = |community: usize, weight: f64| {
+                weight - community_degrees[community] * degrees[node] / total_weight
+            };
+            let mut best = (
+                current,
+                gain(current, links_to.get(&current).copied().unwrap_or(0.0)),
+            );
+            let mut candidates: Vec<(usize, f64)> = links_to.into_iter().collect();
+            candidates.sort_by_key(|&(community, _)| community);
+            for (community, weight) in candidates {
+                let community_gain = gain(community, weight);
+                if community_gain > best.1 + 1e-12 {
+                    best = (community, community_gain);
                 }
             }
+
+            community_degrees[best.0] += degrees[node];
+            if best.0 != current {
+                labels[node] = best.0;
+                moved = true;
+            }
+        }
+        if !moved {
+            break;
         }
     }
-    Ok(())
+
+    labels
 }
 
-fn generate_custom_index_page(
-    index_id: &str,
-    notes_map: &HashMap<String, Note>,
-    id_to_path: &HashMap<String, PathBuf>,
-    output_dir: &Path,
-    all_tags: &HashSet<String>,
-    html_template: &str,
-    margin_info_tracker: &MarginInfoTracker,
-) -> std::io::Result<()> {
-    let note = &notes_map[index_id];
-    let title = if !note.Properties.title.is_empty() {
-        note.Properties.title.clone()
-    } else {
-        "Notes Index".to_string()
-    };
+// A cell of the Barnes-Hut quadtree, holding either child cells or, as a leaf, the nodes in it
+struct QuadCell {
+    size: f64,
+    mass: f64,
+    center: (f64, f64),
+    children: Vec<usize>,
+    bodies: Vec<usize>,
+}
 
-    // Extract creation date from ID if not present in properties
-    let created_date = if !note.Properties.created.is_empty() {
-        note.Properties.created.clone()
-    } else if note.ID.len() >= 14 {
-        // Extract timestamp from ID (format: YYYYMMDDhhmmss-xxx)
-        let timestamp = &note.ID[0..14];
-        timestamp.to_string()
-    } else {
-        String::new()
-    };
+// Builds the quadtree over `bodies` into `cells` and returns the index of its root
+fn build_quadtree(
+    cells: &mut Vec<QuadCell>,
+    positions: &[(f64, f64)],
+    bodies: Vec<usize>,
+    origin: (f64, f64),
+    size: f64,
+    depth: usize,
+) -> usize {
+    let mass = bodies.len() as f64;
+    let center = (
+        bodies.iter().map(|&i| positions[i].0).sum::<f64>() / mass,
+        bodies.iter().map(|&i| positions[i].1).sum::<f64>() / mass,
+    );
+    let cell = cells.len();
+    cells.push(QuadCell {
+        size,
+        mass,
+        center,
+        children: Vec::new(),
+        bodies: Vec::new(),
+    });
 
-    let mut html = html_template.replace("{{title}}", &title);
-    html = html.replace("{{article_title}}", &title);
-    html = html.replace("{{css_path}}", "styles.css");
-    html = html.replace("{{site_name}}", "SyMark");
+    // Nodes on top of each other would split forever, so depth is capped
+    if bodies.len() == 1 || depth >= 20 {
+        cells[cell].bodies = bodies;
+        return cell;
+    }
 
-    // Extract a good description for meta and OpenGraph tags
-    let mut description = String::new();
-    let mut paragraph_count = 0;
+    let half = size / 2.0;
+    let mut quadrants: [Vec<usize>; 4] = Default::default();
+    for i in bodies {
+        let right = positions[i].0 >= origin.0 + half;
+        let bottom = positions[i].1 >= origin.1 + half;
+        quadrants[right as usize + 2 * bottom as usize].push(i);
+    }
+    for (quadrant, quadrant_bodies) in quadrants.into_iter().enumerate() {
+        if quadrant_bodies.is_empty() {
+            continue;
+        }
+        let quadrant_origin = (
+            origin.0 + half * (quadrant % 2) as f64,
+            origin.1 + half * (quadrant / 2) as f64,
+        );
+        let child = build_quadtree(
+            cells,
+            positions,
+            quadrant_bodies,
+            quadrant_origin,
+            half,
+            depth + 1,
+        );
+        cells[cell].children.push(child);
+    }
 
-    // Process blocks to find meaningful content
-    for block in &note.Children {
-        if block.Type == "NodeParagraph" || block.Type == "Paragraph" {
-            // Only process first few paragraphs
-            if paragraph_count > 0 {
-                description.push(' '); // Add space between paragraphs
-            }
+    cell
+}
 
-            // Get text from paragraph
-            let paragraph_content = render_blocks(
-                &block.Children,
-                notes_map,
-                id_to_path,
-                margin_info_tracker,
-                false,
-            );
+// Starting positions on a spiral with each community in one stretch, so linked nodes begin
+// close together
+fn spiral_positions(communities: &[usize]) -> Vec<(f64, f64)> {
+    let mut order: Vec<usize> = (0..communities.len()).collect();
+    order.sort_by_key(|&i| (communities[i], i));
+    let golden_angle = std::f64::consts::PI * (3.0 - 5f64.sqrt());
+    let mut positions = vec![(0.0, 0.0); communities.len()];
+    for (step, &i) in order.iter().enumerate() {
+        let radius = 10.0 * (0.5 + step as f64).sqrt();
+        let angle = step as f64 * golden_angle;
+        positions[i] = (radius * angle.cos(), radius * angle.sin());
+    }
+    positions
+}
 
-            // Strip HTML
-            let mut plain_text = String::new();
-            let mut in_tag = false;
+// Force-directed layout in the style of d3-force from the given starting positions: nodes repel
+// each other (approximated with a Barnes-Hut quadtree), links pull like springs and a weak pull
+// keeps everything centered
+fn force_layout(
+    links: &[(usize, usize, f64)],
+    adjacency: &[Vec<(usize, f64)>],
+    mut positions: Vec<(f64, f64)>,
+    iterations: usize,
+) -> Vec<(f64, f64)> {
+    const CHARGE: f64 = -300.0;
+    const LINK_DISTANCE: f64 = 80.0;
+    const CENTER_STRENGTH: f64 = 0.02;
+    const THETA_SQUARED: f64 = 0.81;
+    const VELOCITY_DECAY: f64 = 0.6;
+
+    let count = adjacency.len();
+    if count == 0 {
+        return Vec::new();
+    }
 
-            for c in paragraph_content.chars() {
-                if c == '<' {
-                    in_tag = true;
-                } else if c == '>' {
-                    in_tag = false;
-                } else if !in_tag {
-                    plain_text.push(c);
+    let mut velocities = vec![(0.0, 0.0); count];
+    let iterations = iterations.max(1);
+    let alpha_decay = 0.001f64.powf(1.0 / iterations as f64);
+    let mut alpha = 1.0;
+    let mut cells = Vec::with_capacity(count * 2);
+
+    for _ in 0..iterations {
+        // Repulsion
+        let (min_x, min_y, max_x, max_y) = positions.iter().fold(
+            (f64::MAX, f64::MAX, f64::MIN, f64::MIN),
+            |(x0, y0, x1, y1), &(x, y)| (x0.min(x), y0.min(y), x1.max(x), y1.max(y)),
+        );
+        let size = (max_x - min_x).max(max_y - min_y).max(1.0) * (1.0 + 1e-9);
+        cells.clear();
+        let root = build_quadtree(
+            &mut cells,
+            &positions,
+            (0..count).collect(),
+            (min_x, min_y),
+            size,
+            0,
+        );
+
+        for i in 0..count {
+            let (x, y) = positions[i];
+            let mut force = (0.0, 0.0);
+            let mut stack = vec![root];
+            while let Some(cell) = stack.pop() {
+                let cell = &cells[cell];
+                let dx = cell.center.0 - x;
+                let dy = cell.center.1 - y;
+                let distance_squared = (dx * dx + dy * dy).max(1.0);
+
+                if !cell.children.is_empty() {
+                    // A cell centered right on the node pushes it nowhere, so nodes on top of
+                    // each other look inside it to be nudged apart below
+                    let centered = dx == 0.0 && dy == 0.0;
+                    if !centered && cell.size * cell.size / distance_squared < THETA_SQUARED {
+                        force.0 += dx * CHARGE * cell.mass / distance_squared;
+                        force.1 += dy * CHARGE * cell.mass / distance_squared;
+                    } else {
+                        stack.extend(&cell.children);
+                    }
+                    continue;
+                }
+
+                for &j in &cell.bodies {
+                    if j == i {
+                        continue;
+                    }
+                    let mut dx = positions[j].0 - x;
+                    let mut dy = positions[j].1 - y;
+                    if dx == 0.0 && dy == 0.0 {
+                        // Nudge nodes on top of each other apart, the same way every build
+                        dx = (j as f64 - i as f64) * 1e-3;
+                        dy = dx;
+                    }
+                    let distance_squared = (dx * dx + dy * dy).max(1.0);
+                    force.0 += dx * CHARGE / distance_squared;
+                    force.1 += dy * CHARGE / distance_squared;
                 }
             }
+            velocities[i].0 += force.0 * alpha;
+            velocities[i].1 += force.1 * alpha;
+        }
 
-            description.push_str(&plain_text);
-            paragraph_count += 1;
+        // Springs along links, which move the less connected end more
+        for &(a, b, weight) in links {
+            let dx = positions[b].0 + velocities[b].0 - positions[a].0 - velocities[a].0;
+            let dy = positions[b].1 + velocities[b].1 - positions[a].1 - velocities[a].1;
+            let distance = (dx * dx + dy * dy).sqrt().max(1e-6);
+            let degree_a = adjacency[a].len() as f64;
+            let degree_b = adjacency[b].len() as f64;
+            let strength = weight.min(3.0) / degree_a.min(degree_b);
+            let pull = (distance - LINK_DISTANCE) / distance * alpha * strength;
+            let bias = degree_a / (degree_a + degree_b);
+            velocities[b].0 -= dx * pull * bias;
+            velocities[b].1 -= dy * pull * bias;
+            velocities[a].0 += dx * pull * (1.0 - bias);
+            velocities[a].1 += dy * pull * (1.0 - bias);
+        }
 
-            // Limit to 2-3 paragraphs for description
-            if paragraph_count >= 2 && description.len() > 100 {
-                break;
-            }
+        for i in 0..count {
+            velocities[i].0 -= positions[i].0 * CENTER_STRENGTH * alpha;
+            velocities[i].1 -= positions[i].1 * CENTER_STRENGTH * alpha;
+            velocities[i].0 *= VELOCITY_DECAY;
+            velocities[i].1 *= VELOCITY_DECAY;
+            positions[i].0 += velocities[i].0;
+            positions[i].1 += velocities[i].1;
         }
-    }
 
-    // If no paragraphs found, use title
-    if description.is_empty() {
-        description = title.clone();
+        alpha *= alpha_decay;
     }
 
-    // Escape HTML in the description
-    let description = escape_html(&description);
+    positions
+}
 
-    // Truncate description if too long (typical limit is around 200 chars)
-    let truncated_description = if description.len() > 200 {
-        // Ensure we cut at a character boundary
-        let mut end_index = 0;
-        let mut char_count = 0;
-        for (idx, _) in description.char_indices() {
-            end_index = idx;
-            char_count += 1;
-            if char_count >= 197 {
-                break;
-            }
-        }
-        format!("{}...", &description[0..end_index])
+// Gives every tag a color that depends only on its name, so colors stay put between builds and
+// don't shift when tags come and go. Colors set in the configuration come first, then those set by
+// the theme; other tags take the palette color their name hashes to.
+fn assign_tag_colors(
+    all_tags: &HashSet<String>,
+    config: &GraphConfig,
+    theme_colors: &ThemeColors,
+) -> BTreeMap<String, String> {
+    let palette: Vec<String> = if !config.palette.is_empty() {
+        config.palette.clone()
+    } else if !theme_colors.palette.is_empty() {
+        theme_colors.palette.clone()
     } else {
-        description
+        DEFAULT_TAG_PALETTE.map(String::from).to_vec()
     };
 
-    html = html.replace("{{meta_description}}", &truncated_description);
-    html = html.replace("{{blog_description}}", "A collection of notes");
-    html = html.replace("{{back_navigation}}", "");
+    let mut colors = BTreeMap::new();
+    for tag in all_tags {
+        // The palette slot comes from the name alone, so two tags may share a color
+        let color = config
+            .tag_colors
+            .get(tag)
+            .or(theme_colors.tags.get(tag))
+            .cloned()
+            .unwrap_or_else(|| {
+                palette[(fnv1a_hash(tag.as_bytes()) % palette.len() as u64) as usize].clone()
+            });
+        colors.insert(tag.clone(), color);
+    }
 
-    // Define site base URL for OpenGraph - this should be configurable in the future
-    let site_base_url = "https://du82.github.io/symark";
+    colors
+}
 
-    // OpenGraph URL
-    html = html.replace("{{og_url}}", &format!("{}", site_base_url));
+// FNV-1a, a hash that stays the same across builds and Rust versions
+fn fnv1a_hash(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
 
-    // Set OpenGraph published time in ISO 8601 format
-    if !created_date.is_empty() && created_date.len() >= 14 {
-        let og_published_time = format!(
-            "{}-{}-{}T{}:{}:{}Z",
-            &created_date[0..4],
-            &created_date[4..6],
-            &created_date[6..8],
-            &created_date[8..10],
-            &created_date[10..12],
-            &created_date[12..14]
-        );
-        html = html.replace("{{og_published_time}}", &og_published_time);
-    } else {
-        let now = Local::now();
-        let og_published_time = now.format("%Y-%m-%dT%H:%M:%SZ").to_string();
-        html = html.replace("{{og_published_time}}", &og_published_time);
+// A node of the link graph with the attributes shared by graph.html and the exports
+struct GraphNode {
+    id: String,
+    // "note" or "tag"
+    kind: &'static str,
+    title: String,
+    url: String,
+    tags: Vec<String>,
+    // ISO 8601 dates, empty for tags
+    created: String,
+    updated: String,
+    connections: usize,
+}
+
+// The notes in ID order, so output is the same between builds, followed by the tag nodes
+fn collect_graph_nodes(
+    notes_map: &HashMap<String, Note>,
+    link_graph: &LinkGraph,
+    urls: &SiteUrls,
+) -> Vec<GraphNode> {
+    let mut connections: HashMap<&str, usize> = HashMap::new();
+    for (source, target, _) in link_graph.edges.keys() {
+        *connections.entry(source).or_insert(0) += 1;
+        *connections.entry(target).or_insert(0) += 1;
     }
 
-    if !note.Properties.updated.is_empty() && note.Properties.updated != created_date {
-        if note.Properties.updated.len() >= 14 {
-            let og_modified_time = format!(
-                "{}-{}-{}T{}:{}:{}Z",
-                &note.Properties.updated[0..4],
-                &note.Properties.updated[4..6],
-                &note.Properties.updated[6..8],
-                &note.Properties.updated[8..10],
-                &note.Properties.updated[10..12],
-                &note.Properties.updated[12..14]
-            );
-            html = html.replace("{{og_modified_time}}", &og_modified_time);
+    let mut note_ids: Vec<&String> = notes_map.keys().collect();
+    note_ids.sort();
+
+    let mut nodes = Vec::new();
+    for id in note_ids {
+        let note = &notes_map[id];
+        let title = if !note.Properties.title.is_empty() {
+            note.Properties.title.clone()
         } else {
-            html = html.replace("{{og_modified_time}}", "");
-        }
-    } else {
-        html = html.replace("{{og_modified_time}}", "");
+            id.clone()
+        };
+
+        // Collect all tags for color grouping
+        let tags = note
+            .Properties
+            .tags
+            .split(',')
+            .map(|t| t.trim().to_string())
+            .filter(|t| !t.is_empty())
+            .collect();
+
+        // The creation time is part of the ID when it's missing from the properties
+        let created = if note.Properties.created.is_empty() {
+            iso_timestamp(id)
+        } else {
+            iso_timestamp(&note.Properties.created)
+        };
+        let updated = if note.Properties.updated.is_empty() {
+            created.clone()
+        } else {
+            iso_timestamp(&note.Properties.updated)
+        };
+
+        nodes.push(GraphNode {
+            id: id.clone(),
+            kind: "note",
+            title,
+            url: urls.note_url(id),
+            tags,
+            created,
+            updated,
+            connections: connections.get(id.as_str()).copied().unwrap_or(0),
+        });
     }
 
-    // Add header image if it exists
-    if !note.Properties.title_img.is_empty() {
-        html = html.replace("{{#header_image}}", "");
-        html = html.replace("{{/header_image}}", "");
-        html = html.replace("{{header_image}}", &note.Properties.title_img);
-    } else {
-        // Remove header image section if no image
-        html = html.replace("{{#header_image}}", "<!-- ");
-        html = html.replace("{{/header_image}}", " -->");
+    for tag in &link_graph.tags {
+        let id = format!("tag:{}", tag);
+        nodes.push(GraphNode {
+            connections: connections.get(id.as_str()).copied().unwrap_or(0),
+            id,
+            kind: "tag",
+            title: format!("#{}", tag),
+            url: urls.tag_url(tag),
+            tags: vec![tag.clone()],
+            created: String::new(),
+            updated: String::new(),
+        });
     }
-    html = html.replace("{{blog_description}}", "A collection of notes");
-    html = html.replace("{{reading_time}}", "2");
-    html = html.replace("{{author_name}}", "Notes Author");
-    html = html.replace("{{publish_date}}", &naturalize_date(&created_date));
 
-    let formatted_date =
-        if !note.Properties.updated.is_empty() && note.Properties.updated != created_date {
-            if note.Properties.updated.len() >= 14 {
-                let og_modified_time = format!(
-                    "{}-{}-{}T{}:{}:{}Z",
-                    &note.Properties.updated[0..4],
-                    &note.Properties.updated[4..6],
-                    &note.Properties.updated[6..8],
-                    &note.Properties.updated[8..10],
-                    &note.Properties.updated[10..12],
-                    &note.Properties.updated[12..14]
-                );
-                html = html.replace("{{og_modified_time}}", &og_modified_time);
-            } else {
-                html = html.replace(
-                    "<meta property=\"article:modified_time\" content=\"{{og_modified_time}}\">",
-                    "",
-                );
-            }
+    nodes
+}
 
-            format!(
-                "Created on {}, updated on {}",
-                naturalize_date(&created_date),
-                naturalize_date(&note.Properties.updated)
-            )
-        } else {
-            // If no image, remove the OpenGraph image tag
-            html = html.replace("<meta property=\"og:image\" content=\"{{og_image}}\">", "");
+// Turns a SiYuan timestamp (YYYYMMDDhhmmss, possibly followed by more) into ISO 8601
+fn iso_timestamp(timestamp: &str) -> String {
+    match timestamp.get(0..14) {
+        Some(digits) if digits.bytes().all(|b| b.is_ascii_digit()) => format!(
+            "{}-{}-{}T{}:{}:{}Z",
+            &digits[0..4],
+            &digits[4..6],
+            &digits[6..8],
+            &digits[8..10],
+            &digits[10..12],
+            &digits[12..14]
+        ),
+        _ => String::new(),
+    }
+}
 
-            format!("Created on {}", naturalize_date(&created_date))
+// Writes the link graph next to graph.html in each configured format, for tools such as Gephi,
+// networkx or Graphviz
+fn write_graph_exports(
+    nodes: &[GraphNode],
+    edges: &[(&str, &str, &'static str, usize)],
+    output_dir: &Path,
+    formats: &[String],
+) -> std::io::Result<()> {
+    for format in formats {
+        let (file_name, contents) = match format.as_str() {
+            "json" => ("graph.json", graph_json(nodes, edges)),
+            "graphml" => ("graph.graphml", graph_graphml(nodes, edges)),
+            "gexf" => ("graph.gexf", graph_gexf(nodes, edges)),
+            "dot" => ("graph.dot", graph_dot(nodes, edges)),
+            _ => {
+                println!("Warning: Unknown graph export format \"{}\"", format);
+                continue;
+            }
         };
-    html = html.replace("{{last_updated_date}}", &formatted_date);
 
-    html = html.replace("{{category}}", "Notes");
-    html = html.replace("{{next_article_url}}", "#");
-    html = html.replace("{{next_article_title}}", "");
-    html = html.replace("{{back_navigation}}", "");
+        let mut file = File::create(output_dir.join(file_name))?;
+        file.write_all(contents.as_bytes())?;
+        println!("Exported graph to {}", file_name);
+    }
 
-    let mut toc_items = Vec::new();
-    let mut id_counter = 0;
-    extract_toc_items(&note.Children, &mut toc_items, &mut id_counter);
+    Ok(())
+}
 
-    let toc_html = generate_toc_html(&toc_items);
-    html = html.replace("{{table_of_contents}}", &toc_html);
+// networkx's node-link format, which nx.node_link_graph reads back
+fn graph_json(nodes: &[GraphNode], edges: &[(&str, &str, &'static str, usize)]) -> String {
+    let data = json!({
+        "directed": true,
+        "multigraph": true,
+        "graph": { "generator": "SyMark" },
+        "nodes": nodes.iter().map(|node| json!({
+            "id": node.id,
+            "kind": node.kind,
+            "title": node.title,
+            "url": node.url,
+            "tags": node.tags,
+            "created": node.created,
+            "updated": node.updated
+        })).collect::<Vec<_>>(),
+        "links": edges.iter().map(|(source, target, kind, weight)| json!({
+            "source": source,
+            "target": target,
+            "key": kind,
+            "kind": kind,
+            "weight": weight
+        })).collect::<Vec<_>>()
+    });
 
-    // Generate content with heading IDs for TOC
-    let content_html = render_blocks_with_ids(
-        &note.Children,
-        notes_map,
-        id_to_path,
-        margin_info_tracker,
-        false,
-    );
+    serde_json::to_string_pretty(&data).unwrap_or_default()
+}
 
-    let content_with_link = format!(
-        "{}\n<div class=\"all-notes-link\"><a href=\"all.html\">View All Notes</a></div>",
-        content_html
+fn graph_graphml(nodes: &[GraphNode], edges: &[(&str, &str, &'static str, usize)]) -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n",
     );
-
-    html = html.replace("{{content}}", &content_with_link);
-
-    let mut meta = String::new();
-
-    // Display creation date as a tag
-    if !created_date.is_empty() {
-        meta.push_str(&format!(
-            "<span class=\"meta-tag date-tag\">Created: {}</span>",
-            naturalize_date(&created_date)
+    for (id, owner, name, kind) in [
+        ("title", "node", "title", "string"),
+        ("kind", "node", "kind", "string"),
+        ("url", "node", "url", "string"),
+        ("tags", "node", "tags", "string"),
+        ("created", "node", "created", "string"),
+        ("updated", "node", "updated", "string"),
+        ("edge_kind", "edge", "kind", "string"),
+        ("weight", "edge", "weight", "int"),
+    ] {
+        xml.push_str(&format!(
+            "  <key id=\"{}\" for=\"{}\" attr.name=\"{}\" attr.type=\"{}\"/>\n",
+            id, owner, name, kind
         ));
     }
 
-    // Display update date if it's different from creation date
-    if !note.Properties.updated.is_empty() && note.Properties.updated != created_date {
-        meta.push_str(&format!(
-            "<span class=\"meta-tag date-tag\">Updated: {}</span>",
-            naturalize_date(&note.Properties.updated)
+    xml.push_str("  <graph id=\"symark\" edgedefault=\"directed\">\n");
+    for node in nodes {
+        xml.push_str(&format!("    <node id=\"{}\">\n", escape_html(&node.id)));
+        for (key, value) in [
+            ("title", node.title.as_str()),
+            ("kind", node.kind),
+            ("url", node.url.as_str()),
+            ("tags", &node.tags.join(",")),
+            ("created", node.created.as_str()),
+            ("updated", node.updated.as_str()),
+        ] {
+            if !value.is_empty() {
+                xml.push_str(&format!(
+                    "      <data key=\"{}\">{}</data>\n",
+                    key,
+                    escape_html(value)
+                ));
+            }
+        }
+        xml.push_str("    </node>\n");
+    }
+    for (index, (source, target, kind, weight)) in edges.iter().enumerate() {
+        xml.push_str(&format!(
+            "    <edge id=\"e{}\" source=\"{}\" target=\"{}\">\n      \
+             <data key=\"edge_kind\">{}</data>\n      <data key=\"weight\">{}</data>\n    </edge>\n",
+            index,
+            escape_html(source),
+            escape_html(target),
+            kind,
+            weight
         ));
     }
+    xml.push_str("  </graph>\n</graphml>\n");
+    xml
+}
 
-    // Add tags
-    if !note.Properties.tags.is_empty() {
-        let mut tags: Vec<_> = note
-            .Properties
-            .tags
-            .split(',')
-            .map(|t| t.trim())
-            .filter(|t| *t != "index")
-            .collect();
-        tags.sort();
+fn graph_gexf(nodes: &[GraphNode], edges: &[(&str, &str, &'static str, usize)]) -> String {
+    // Dated by the newest note rather than the build, so unchanged notes give the same file
+    let last_modified = nodes
+        .iter()
+        .filter_map(|node| node.updated.get(0..10))
+        .max()
+        .map(|date| format!(" lastmodifieddate=\"{}\"", date))
+        .unwrap_or_default();
+    let mut xml = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <gexf xmlns=\"http://gexf.net/1.3\" version=\"1.3\">\n  \
+         <meta{}>\n    <creator>SyMark</creator>\n  </meta>\n  \
+         <graph defaultedgetype=\"directed\" mode=\"static\">\n",
+        last_modified
+    );
 
-        for tag in tags {
-            if !tag.is_empty() {
-                meta.push_str(&format!(
-                    "<a href=\"tag_{}.html\" class=\"meta-tag\">{}</a>",
-                    tag.replace(" ", "_"),
-                    tag
+    let node_attributes = ["kind", "url", "tags", "created", "updated"];
+    xml.push_str("    <attributes class=\"node\">\n");
+    for (index, name) in node_attributes.iter().enumerate() {
+        xml.push_str(&format!(
+            "      <attribute id=\"{}\" title=\"{}\" type=\"string\"/>\n",
+            index, name
+        ));
+    }
+    xml.push_str("    </attributes>\n");
+    xml.push_str(
+        "    <attributes class=\"edge\">\n      \
+         <attribute id=\"0\" title=\"kind\" type=\"string\"/>\n    </attributes>\n",
+    );
+
+    xml.push_str("    <nodes>\n");
+    for node in nodes {
+        xml.push_str(&format!(
+            "      <node id=\"{}\" label=\"{}\">\n        <attvalues>\n",
+            escape_html(&node.id),
+            escape_html(&node.title)
+        ));
+        let values = [
+            node.kind,
+            node.url.as_str(),
+            &node.tags.join(","),
+            node.created.as_str(),
+            node.updated.as_str(),
+        ];
+        for (index, value) in values.iter().enumerate() {
+            if !value.is_empty() {
+                xml.push_str(&format!(
+                    "          <attvalue for=\"{}\" value=\"{}\"/>\n",
+                    index,
+                    escape_html(value)
                 ));
             }
         }
+        xml.push_str("        </attvalues>\n      </node>\n");
     }
+    xml.push_str("    </nodes>\n    <edges>\n");
+    for (index, (source, target, kind, weight)) in edges.iter().enumerate() {
+        xml.push_str(&format!(
+            "      <edge id=\"{}\" source=\"{}\" target=\"{}\" label=\"{}\" weight=\"{}\">\n        \
+             <attvalues>\n          <attvalue for=\"0\" value=\"{}\"/>\n        </attvalues>\n      </edge>\n",
+            index,
+            escape_html(source),
+            escape_html(target),
+            kind,
+            weight,
+            kind
+        ));
+    }
+    xml.push_str("    </edges>\n  </graph>\n</gexf>\n");
+    xml
+}
 
-    html = html.replace("{{note_meta}}", &meta);
-    html = html.replace(
-        "{{generation_date}}",
-        &Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
-    );
-
-    // Remove zero-width spaces and clean up any remaining template variables
-    let cleaned_html = remove_zero_width_spaces(&html);
-    let cleaned_html = cleanup_template_variables(&html);
+// Graphviz, with each node linking to its page
+fn graph_dot(nodes: &[GraphNode], edges: &[(&str, &str, &'static str, usize)]) -> String {
+    let quote = |text: &str| format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""));
+
+    let mut dot = String::from("digraph symark {\n");
+    for node in nodes {
+        dot.push_str(&format!(
+            "  {} [label={}, kind={}, URL={}, tags={}",
+            quote(&node.id),
+            quote(&node.title),
+            quote(node.kind),
+            quote(&node.url),
+            quote(&node.tags.join(","))
+        ));
+        if !node.created.is_empty() {
+            dot.push_str(&format!(
+                ", created={}, updated={}",
+                quote(&node.created),
+                quote(&node.updated)
+            ));
+        }
+        dot.push_str("];\n");
+    }
+    for (source, target, kind, weight) in edges {
+        dot.push_str(&format!(
+            "  {} -> {} [kind={}, weight={}];\n",
+            quote(source),
+            quote(target),
+            quote(kind),
+            weight
+        ));
+    }
+    dot.push_str("}\n");
+    dot
+}
 
-    let final_html = comment_processor(&cleaned_html);
+// Adds the references and embeds in `blocks` to the graph as links from `source_id`; links to a
+// block count as links to the note containing it
+fn scan_blocks_for_links(
+    blocks: &[Block],
+    block_index: &HashMap<String, String>,
+    source_id: &str,
+    graph: &mut LinkGraph,
+) {
+    for block in blocks {
+        let target = if block.Type == "NodeTextMark"
+            && block.TextMarkType.split(' ').any(|t| t == "block-ref")
+        {
+            Some((block.TextMarkBlockRefID.as_str(), "ref"))
+        } else {
+            embed_target_id(block).map(|content_id| (content_id, "embed"))
+        };
 
-    // Write to file
-    let file_path = output_dir.join("index.html");
-    let mut file = File::create(&file_path)?;
-    file.write_all(final_html.as_bytes())?;
+        let target_note = target.and_then(|(content_id, kind)| {
+            block_index
+                .get(content_id)
+                .filter(|target_id| *target_id != source_id)
+                .map(|target_id| (target_id, kind))
+        });
+        if let Some((target_id, kind)) = target_note {
+            graph.add_edge(source_id, target_id, kind);
+        }
 
-    Ok(())
+        // Recursively check children blocks
+        if !block.Children.is_empty() {
+            scan_blocks_for_links(&block.Children, block_index, source_id, graph);
+        }
+    }
 }
 
-fn generate_all_notes_page(
+fn generate_tag_page(
+    tag: &str,
     notes_map: &HashMap<String, Note>,
     output_dir: &Path,
     all_tags: &HashSet<String>,
+    urls: &SiteUrls,
     html_template: &str,
+    reading: &ReadingStats,
 ) -> std::io::Result<()> {
-    let mut html = html_template.replace("{{title}}", "All Notes");
-    html = html.replace("{{article_title}}", "All Notes");
-    html = html.replace("{{css_path}}", "styles.css");
-    html = html.replace("{{site_name}}", "SyMark");
-    html = html.replace("{{meta_description}}", "Collection of all notes");
-
-    // Define site base URL for OpenGraph - this should be configurable in the future
-    let site_base_url = "https://du82.github.io/symark";
-
-    html = html.replace("{{og_url}}", &format!("{}", site_base_url));
+    // Count notes with this tag (we already calculated this above)
 
-    // Set OpenGraph published time in ISO 8601 format
-    let now_iso = Local::now();
-    let og_published_time = now_iso.format("%Y-%m-%dT%H:%M:%SZ").to_string();
-    html = html.replace("{{og_published_time}}", &og_published_time);
+    let title = format!("Tag: {}", escape_html(tag));
+    let mut html = html_template.replace("{{title}}", &title);
+    html = html.replace("{{article_title}}", &title);
+    html = html.replace("{{css_path}}", &urls.asset_url("styles.css"));
+    html = html.replace("{{site_name}}", "SyMark");
+    // Filter notes with this tag (or a tag nested below it) for meta description and TOC
+    let tagged_notes: Vec<&Note> = notes_map
+        .values()
+        .filter(|n| note_has_tag(n, tag))
+        .collect();
+    let note_count = tagged_notes.len();
 
-    // No modified time for all notes page
-    html = html.replace("{{og_modified_time}}", "");
+    let meta_description = if note_count == 1 {
+        format!("1 note has the tag \"{}\"", tag)
+    } else {
+        format!("{} notes have the tag \"{}\"", note_count, tag)
+    };
 
-    // Remove OpenGraph image tag if no image
-    html = html.replace("<meta property=\"og:image\" content=\"{{og_image}}\">", "");
-    html = html.replace("{{blog_description}}", "A collection of all notes");
-    html = html.replace("{{reading_time}}", "2");
+    let meta_description = escape_html(&meta_description);
+    html = html.replace("{{meta_description}}", &meta_description);
+    html = html.replace("{{blog_description}}", &meta_description);
+    let reading_total = reading.total(tagged_notes.iter().map(|n| &n.ID));
+    html = reading_total.fill_template(&html, reading.config);
     html = html.replace("{{author_name}}", "Notes Author");
 
-    // Use current timestamp for publish date
-    let now = Local::now().format("%Y%m%d%H%M%S").to_string();
-    html = html.replace("{{publish_date}}", &naturalize_date(&now));
+    // Get current date for tag page generation
+    let timestamp = Local::now().format("%Y%m%d%H%M%S").to_string();
 
-    let formatted_date = format!("Created on {}", naturalize_date(&now));
-    html = html.replace("{{last_updated_date}}", &formatted_date);
-    // Create tag cloud metadata for all notes page
+    // Create tag cloud metadata for tag page
     let meta = format!(
-        "<span class=\"meta-tag date-tag\">Created on {}</span>",
-        naturalize_date(&now)
+        "<span class=\"meta-tag date-tag\">Created on {}</span>{}",
+        naturalize_date(&timestamp),
+        reading_total.meta_html(reading.config)
     );
     html = html.replace("{{note_meta}}", &meta);
     html = html.replace("{{last_updated_date}}", ""); // Clear this as we're using note_meta
 
-    html = html.replace("{{category}}", "Notes");
+    html = html.replace("{{category}}", "Tags");
     html = html.replace("{{next_article_url}}", "#");
     html = html.replace("{{next_article_title}}", "");
-    html = html.replace("{{back_navigation}}", BACK_NAVIGATION_HTML);
+    html = html.replace("{{back_navigation}}", &back_navigation_html(urls));
 
-    // No header image for all notes page
+    // No header image for tag pages
     html = html.replace("{{#header_image}}", "<!-- ");
     html = html.replace("{{/header_image}}", " -->");
 
-    // Generate navigation items - sort by title for better navigation
+    // Generate navigation items - sort by title
     let mut sorted_notes: Vec<_> = notes_map
         .values()
         .filter(|n| !n.Properties.title.is_empty())
         .collect();
     sorted_notes.sort_by(|a, b| a.Properties.title.cmp(&b.Properties.title));
 
-    let mut nav_items = String::new();
-    for note in &sorted_notes {
-        nav_items.push_str(&format!(
-            "<li><a href=\"{}.html\">{}</a></li>\n",
-            note.ID, note.Properties.title
-        ));
-    }
-
     // Generate table of contents
     let mut toc_items = Vec::new();
-    let mut id_counter = 0;
 
     toc_items.push(TocItem {
-        id: "section-all-notes".to_string(),
-        text: "All Notes".to_string(),
+        id: "section-tagged-notes".to_string(),
+        text: if tagged_notes.len() == 1 {
+            format!("1 note has the tag \"{}\"", escape_html(tag))
+        } else {
+            format!(
+                "{} notes have the tag \"{}\"",
+                tagged_notes.len(),
+                escape_html(tag)
+            )
+        },
         level: 2,
     });
 
-    toc_items.push(TocItem {
-        id: "section-tags".to_string(),
-        text: "Tags".to_string(),
-        level: 2,
-    });
+    // Tags nested directly below this one
+    let mut child_tags: Vec<_> = all_tags
+        .iter()
+        .filter(|t| parent_tags(t).last() == Some(&tag))
+        .collect();
+    child_tags.sort();
+
+    if !child_tags.is_empty() {
+        toc_items.push(TocItem {
+            id: "section-child-tags".to_string(),
+            text: "Nested Tags".to_string(),
+            level: 2,
+        });
+    }
 
     toc_items.push(TocItem {
-        id: "section-graph".to_string(),
-        text: "Content Graph".to_string(),
+        id: "section-all-tags".to_string(),
+        text: "All Tags".to_string(),
         level: 2,
     });
 
@@ -1381,173 +5376,181 @@ fn generate_all_notes_page(
     tags.sort();
 
     let mut tags_html = String::new();
-    for tag in tags {
-        tags_html.push_str(&format!(
-            "<a href=\"tag_{}.html\" class=\"tag\">{}</a>\n",
-            tag.replace(" ", "_"),
-            tag
-        ));
-    }
-
-    // Generate content
-    let mut content = String::from("\n<ul>\n");
-    for note in &sorted_notes {
-        content.push_str(&format!(
-            "<li><a href=\"{}.html\">{}</a></li>\n",
-            note.ID, note.Properties.title
-        ));
-    }
-    content.push_str("</ul>");
-
-    content.push_str("<h2 id=\"section-tags\">Tags</h2>\n<div class=\"tags-container\">\n");
-    content.push_str(&tags_html);
-    content.push_str("</div>");
+    for t in tags {
+        let class = if t == tag { "tag active" } else { "tag" };
 
-    html = html.replace("{{content}}", &content);
+        // Count notes with this tag
+        let tag_notes: Vec<&Note> = notes_map.values().filter(|n| note_has_tag(n, t)).collect();
+        let tag_count = tag_notes.len();
 
-    // Set metadata
-    html = html.replace("{{note_meta}}", "");
-    html = html.replace(
-        "{{generation_date}}",
-        &Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
-    );
+        let mut tooltip_text = if tag_count == 1 {
+            format!("1 note has the tag \"{}\"", t)
+        } else {
+            format!("{} notes have the tag \"{}\"", tag_count, t)
+        };
 
-    // Remove zero-width spaces and clean up template variables before writing to file
-    let cleaned_html = remove_zero_width_spaces(&html);
-    let cleaned_html = cleanup_template_variables(&cleaned_html);
+        // Add titles of up to 3 notes in the tooltip
+        if !tag_notes.is_empty() {
+            tooltip_text.push_str(": ");
+            let note_titles: Vec<String> = tag_notes
+                .iter()
+                .take(3)
+                .map(|n| format!("\"{}\"", n.Properties.title))
+                .collect();
 
-    let final_html = comment_processor(&cleaned_html);
+            tooltip_text.push_str(&note_titles.join(", "));
 
-    // Write to file - use all.html, not index.html to avoid overwriting the custom index
-    let all_notes_path = output_dir.join("all.html");
-    let mut file = File::create(&all_notes_path)?;
-    file.write_all(final_html.as_bytes())?;
+            if tag_count > 3 {
+                tooltip_text.push_str(", ...");
+            }
+        }
 
-    Ok(())
-}
+        tags_html.push_str(&format!(
+            "<span class=\"tooltip\"><a href=\"{}\" class=\"{}\">{}</a><span class=\"right bottom\"><span class=\"tooltip-excerpt\">{}</span><i></i></span></span>\n",
+            urls.tag_url(t),
+            class,
+            escape_html(t),
+            escape_html(&tooltip_text)
+        ));
+    }
 
-fn generate_index_page(
-    notes_map: &HashMap<String, Note>,
-    output_dir: &Path,
-    all_tags: &HashSet<String>,
-    html_template: &str,
-) -> std::io::Result<()> {
-    let mut html = html_template.replace("{{title}}", "Notes Index");
-    html = html.replace("{{article_title}}", "Notes Index");
-    html = html.replace("{{css_path}}", "styles.css");
-    html = html.replace("{{site_name}}", "SyMark");
-    html = html.replace("{{meta_description}}", "Collection of all notes");
-    html = html.replace("{{blog_description}}", "A collection of all notes");
-    html = html.replace("{{back_navigation}}", "");
+    // Breadcrumbs back up through the tag index and the parents of a nested tag
+    let mut content = format!(
+        "<nav class=\"tag-breadcrumbs\"><a href=\"{}\">Tags</a> / ",
+        urls.page_url("tags")
+    );
+    for parent in parent_tags(tag) {
+        // Page tags have no tag page to link to
+        if PAGE_TAGS.contains(&parent) {
+            content.push_str(&format!("{} / ", escape_html(tag_leaf_name(parent))));
+            continue;
+        }
+        content.push_str(&format!(
+            "<a href=\"{}\" class=\"tag\">{}</a> / ",
+            urls.tag_url(parent),
+            escape_html(tag_leaf_name(parent))
+        ));
+    }
+    content.push_str(&format!(
+        "<span class=\"tag active\">{}</span></nav>\n",
+        escape_html(tag_leaf_name(tag))
+    ));
 
-    // Define site base URL for OpenGraph - this should be configurable in the future
-    let site_base_url = "https://du82.github.io/symark";
+    // Process notes with this tag
+    content.push_str("<ul>");
+    for note in &tagged_notes {
+        // Get excerpt from note content for tooltip
+        let excerpt = {
+            // First, look for the first paragraph with actual content
+            let mut content_text = String::new();
 
-    // OpenGraph URL
-    html = html.replace("{{og_url}}", &format!("{}", site_base_url));
+            // Process blocks to find meaningful content
+            for block in &note.Children {
+                // Find the first paragraph with actual content
+                if block.Type == "P" && !block.Data.is_empty() {
+                    content_text = block.Data.clone();
 
-    // Set OpenGraph published time in ISO 8601 format
-    let now_iso = Local::now();
-    let og_published_time = now_iso.format("%Y-%m-%dT%H:%M:%SZ").to_string();
-    html = html.replace("{{og_published_time}}", &og_published_time);
+                    // If it's a short paragraph, try to get more content
+                    if content_text.graphemes(true).count() < 120 && note.Children.len() > 1 {
+                        // Look for a second paragraph
+                        for second_block in &note.Children {
+                            if second_block.ID != block.ID
+                                && second_block.Type == "P"
+                                && !second_block.Data.is_empty()
+                            {
+                                content_text.push_str(" ");
+                                content_text.push_str(&second_block.Data);
+                                break;
+                            }
+                        }
+                    }
 
-    // No modified time for index page
-    html = html.replace("{{og_modified_time}}", "");
+                    break;
+                }
+            }
 
-    // Remove OpenGraph image tag if no image
-    html = html.replace("<meta property=\"og:image\" content=\"{{og_image}}\">", "");
+            // If we didn't find a paragraph, look for any text in the first few blocks
+            if content_text.is_empty() {
+                for block in note.Children.iter().take(3) {
+                    if !block.Data.is_empty() {
+                        content_text = block.Data.clone();
+                        break;
+                    }
 
-    html = html.replace("{{#header_image}}", "<!-- ");
-    html = html.replace("{{/header_image}}", " -->");
-    html = html.replace("{{header_image}}", "");
-    html = html.replace("{{blog_description}}", "A collection of all notes");
-    html = html.replace("{{reading_time}}", "2");
-    html = html.replace("{{author_name}}", "Notes Author");
-    let now = Local::now().format("%Y%m%d%H%M%S").to_string();
-    html = html.replace("{{publish_date}}", &naturalize_date(&now));
+                    // Check children if this block has no direct content
+                    if block.Children.len() > 0 {
+                        for child in &block.Children {
+                            if !child.Data.is_empty() {
+                                content_text = child.Data.clone();
+                                break;
+                            }
+                        }
+                        if !content_text.is_empty() {
+                            break;
+                        }
+                    }
+                }
+            }
 
-    let formatted_date = format!("Created on {}", naturalize_date(&now));
-    html = html.replace("{{last_updated_date}}", &formatted_date);
+            // Smart truncation - limit to 1 sentence for shorter previews
+            content_text = escape_html(&smart_truncate_excerpt(&content_text, 1, 200));
 
-    html = html.replace("{{category}}", "Notes");
-    html = html.replace("{{next_article_url}}", "#");
-    html = html.replace("{{next_article_title}}", "");
-    html = html.replace("{{back_navigation}}", "");
+            if !content_text.is_empty() {
+                content_text
+            } else {
+                // If no content found, use tags as fallback
+                let tags = note
+                    .Properties
+                    .tags
+                    .split(',')
+                    .map(|t| t.trim())
+                    .filter(|t| !t.is_empty())
+                    .collect::<Vec<_>>();
 
-    // Generate navigation items - sort by title for better navigation
-    let mut sorted_notes: Vec<_> = notes_map
-        .values()
-        .filter(|n| !n.Properties.title.is_empty())
-        .collect();
-    sorted_notes.sort_by(|a, b| a.Properties.title.cmp(&b.Properties.title));
+                if !tags.is_empty() {
+                    format!("Tagged with: {}", tags.join(", "))
+                } else {
+                    // Last resort: use title
+                    note.Properties.title.clone()
+                }
+            }
+        };
 
-    let mut nav_items = String::new();
-    for note in &sorted_notes {
-        nav_items.push_str(&format!(
-            "<li><a href=\"{}.html\">{}</a></li>\n",
-            note.ID, note.Properties.title
+        // Create tooltip with title and excerpt
+        content.push_str(&format!(
+            "<li><span class=\"tooltip\"><a href=\"{}\">{}</a><span class=\"right bottom\"><span class=\"tooltip-title\">{}</span><span class=\"tooltip-excerpt\">{}</span><i></i></span></span>\n",
+            urls.note_url(&note.ID),
+            note.Properties.title,
+            note.Properties.title,
+            excerpt
         ));
     }
 
-    // Generate table of contents
-    let mut toc_items = Vec::new();
-    let mut id_counter = 0;
-
-    toc_items.push(TocItem {
-        id: "section-all-notes".to_string(),
-        text: "All Notes".to_string(),
-        level: 2,
-    });
-
-    toc_items.push(TocItem {
-        id: "section-tags".to_string(),
-        text: "Tags".to_string(),
-        level: 2,
-    });
-
-    toc_items.push(TocItem {
-        id: "section-graph".to_string(),
-        text: "Content Graph".to_string(),
-        level: 2,
-    });
-
-    let toc_html = generate_toc_html(&toc_items);
-    html = html.replace("{{table_of_contents}}", &toc_html);
-
-    // Generate tags section - sort alphabetically
-    let mut tags: Vec<_> = all_tags.iter().collect();
-    tags.sort();
-
-    let mut tags_html = String::new();
-    for tag in tags {
-        tags_html.push_str(&format!(
-            "<a href=\"tag_{}.html\" class=\"tag\">{}</a>\n",
-            tag.replace(" ", "_"),
-            tag
-        ));
-    }
+    content.push_str("</ul>");
 
-    // Generate content
-    let mut content = String::from("<h2 id=\"section-all-notes\">All Notes</h2>\n<ul>\n");
-    for note in &sorted_notes {
-        content.push_str(&format!(
-            "<li><a href=\"{}.html\">{}</a></li>\n",
-            note.ID, note.Properties.title
-        ));
+    if !child_tags.is_empty() {
+        content.push_str(
+            "<h2 id=\"section-child-tags\">Nested Tags</h2>\n<div class=\"tags-container\">\n",
+        );
+        for child in &child_tags {
+            let count = tagged_notes
+                .iter()
+                .filter(|n| note_has_tag(n, child))
+                .count();
+            content.push_str(&format!(
+                "<a href=\"{}\" class=\"tag\">{} <span class=\"tag-count\">{}</span></a>\n",
+                urls.tag_url(child),
+                escape_html(&child[tag.len() + 1..]),
+                count
+            ));
+        }
+        content.push_str("</div>");
     }
-    content.push_str("</ul>");
 
-    content.push_str("<h2 id=\"section-tags\">Tags</h2>\n<div class=\"tags-container\">\n");
+    content.push_str("<h2 id=\"section-all-tags\">All Tags</h2>\n<div class=\"tags-container\">\n");
     content.push_str(&tags_html);
     content.push_str("</div>");
 
-    // Add graph visualization section
-    content.push_str("<h2 id=\"section-graph\">Content Graph</h2>\n");
-    content.push_str(
-        "<p>Explore the connections between notes in an interactive visualization.</p>\n",
-    );
-    content.push_str("<p><a href=\"graph.html\" class=\"nav-link\">View Content Graph</a></p>\n");
-
     html = html.replace("{{content}}", &content);
 
     // Set metadata
@@ -1557,443 +5560,532 @@ fn generate_index_page(
         &Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
     );
 
-    // Remove zero-width spaces and clean up template variables before writing to file
+    // Remove zero-width spaces and cleanup template variables before writing to file
     let cleaned_html = remove_zero_width_spaces(&html);
     let cleaned_html = cleanup_template_variables(&cleaned_html);
 
     let final_html = comment_processor(&cleaned_html);
 
     // Write to file
-    let all_notes_path = output_dir.join("all.html");
-    let mut file = File::create(&all_notes_path)?;
+    let file_path = output_dir.join(urls.tag_file(tag));
+    let mut file = create_page_file(&file_path)?;
     file.write_all(final_html.as_bytes())?;
 
     Ok(())
 }
 
-fn generate_graph_page(
+// Suggestions and recent notes for the 404 page. Suggestions are picked in the browser, since
+// only the browser knows which URL was missing.
+fn generate_not_found_extras(
     notes_map: &HashMap<String, Note>,
-    output_dir: &Path,
-    all_tags: &HashSet<String>,
-    graph_template: &str,
-) -> std::io::Result<()> {
-    println!("Generating graph page");
-
-    // Create the graph data structure
-    let mut nodes = Vec::new();
-    let mut links = Vec::new();
-    let mut node_indices = HashMap::new();
-    let mut node_index = 0;
-
-    // Create a color palette for tags
-    let predefined_colors = [
-        "#4285F4", // Blue
-        "#EA4335", // Red
-        "#FBBC05", // Yellow
-        "#34A853", // Green
-        "#9C27B0", // Purple
-        "#FF9800", // Orange
-        "#00BCD4", // Cyan
-        "#795548", // Brown
-        "#607D8B", // Blue-gray
-        "#E91E63", // Pink
-    ];
-
-    // Map tags to colors
-    let mut tag_colors = HashMap::new();
-    let mut color_index = 0;
-
-    for tag in all_tags {
-        if color_index < predefined_colors.len() {
-            tag_colors.insert(tag.clone(), predefined_colors[color_index].to_string());
-            color_index += 1;
-        } else {
-            // If we run out of colors, reuse them
-            tag_colors.insert(
-                tag.clone(),
-                predefined_colors[color_index % predefined_colors.len()].to_string(),
-            );
-            color_index += 1;
-        }
-    }
-
-    // First pass: Create all nodes
-    for (id, note) in notes_map {
-        let title = if !note.Properties.title.is_empty() {
-            note.Properties.title.clone()
-        } else {
-            id.clone()
-        };
-
-        // Store the node index for quick lookup
-        node_indices.insert(id.clone(), node_index);
-        node_index += 1;
-
-        // Collect all tags for color grouping
-        let tags_list = if !note.Properties.tags.is_empty() {
-            note.Properties
-                .tags
-                .split(',')
-                .map(|t| t.trim().to_string())
-                .collect::<Vec<_>>()
-        } else {
-            Vec::new()
-        };
-
-        // Create a node object
-        nodes.push(json!({
-            "id": id,
-            "title": title,
-            "tags": tags_list,
-            "connections": 0
-        }));
-    }
+    urls: &SiteUrls,
+    not_found_config: &NotFoundConfig,
+) -> String {
+    let mut html = String::new();
 
-    // Second pass: Create all links
-    for (id, note) in notes_map {
-        // Recursively scan blocks for links
-        scan_blocks_for_links(&note.Children, notes_map, id, &mut links, &node_indices);
+    if not_found_config.suggestions {
+        let mut notes: Vec<_> = notes_map.values().collect();
+        notes.sort_by_key(|note| &note.ID);
+        let entries: Vec<_> = notes
+            .iter()
+            .map(|note| {
+                json!({
+                    "title": note.Properties.title,
+                    "url": urls.note_url(&note.ID),
+                })
+            })
+            .collect();
+        // Keep titles from closing the script element
+        let notes_json = serde_json::Value::Array(entries)
+            .to_string()
+            .replace("</", "<\\/");
+
+        html.push_str("<div class=\"not-found-suggestions\" hidden>\n<h2>Were you looking for</h2>\n<ul></ul>\n</div>\n");
+        html.push_str(&format!(
+            r#"<script>
+(function () {{
+    const notes = {};
+    const words = decodeURIComponent(location.pathname)
+        .toLowerCase()
+        .split(/[^\p{{L}}\p{{N}}]+/u)
+        .filter((word) => word.length > 1 && word !== "html" && word !== "index");
+    if (words.length === 0) return;
+    const matches = notes
+        .map((note) => ({{
+            note: note,
+            score: words.filter((word) =>
+                note.title.toLowerCase().includes(word) || note.url.toLowerCase().includes(word)
+            ).length,
+        }}))
+        .filter((match) => match.score > 0)
+        .sort((a, b) => b.score - a.score)
+        .slice(0, 5);
+    if (matches.length === 0) return;
+    const section = document.querySelector(".not-found-suggestions");
+    for (const match of matches) {{
+        const item = document.createElement("li");
+        const link = document.createElement("a");
+        link.href = match.note.url;
+        link.textContent = match.note.title || match.note.url;
+        item.appendChild(link);
+        section.querySelector("ul").appendChild(item);
+    }}
+    section.hidden = false;
+}})();
+</script>
+"#,
+            notes_json
+        ));
     }
 
-    // Update node connection counts
-    let mut node_connections = HashMap::new();
-    for link in &links {
-        // Increment source node connections
-        let source = link["source"].as_str().unwrap();
-        *node_connections.entry(source.to_string()).or_insert(0) += 1;
-
-        // Increment target node connections
-        let target = link["target"].as_str().unwrap();
-        *node_connections.entry(target.to_string()).or_insert(0) += 1;
-    }
+    if not_found_config.recent_notes > 0 && !notes_map.is_empty() {
+        let mut notes: Vec<_> = notes_map.values().collect();
+        notes.sort_by(|a, b| {
+            let updated = |note: &Note| {
+                if !note.Properties.updated.is_empty() {
+                    note.Properties.updated.clone()
+                } else {
+                    note.Properties.created.clone()
+                }
+            };
+            updated(b).cmp(&updated(a)).then_with(|| a.ID.cmp(&b.ID))
+        });
 
-    // Update the nodes with connection counts
-    for node in &mut nodes {
-        if let Some(count) = node_connections.get(node["id"].as_str().unwrap()) {
-            node["connections"] = json!(*count);
+        html.push_str("<h2>Recently updated</h2>\n<ul class=\"recent-notes\">\n");
+        for note in notes.iter().take(not_found_config.recent_notes) {
+            let title = if note.Properties.title.is_empty() {
+                &note.ID
+            } else {
+                &note.Properties.title
+            };
+            html.push_str(&format!(
+                "<li><a href=\"{}\">{}</a></li>\n",
+                urls.note_url(&note.ID),
+                escape_html(title)
+            ));
         }
+        html.push_str("</ul>\n");
     }
 
-    // Create the graph data JSON
-    let graph_data = json!({
-        "nodes": nodes,
-        "links": links
-    });
+    html
+}
 
-    // Create tag color JSON
-    let tag_colors_json = json!(tag_colors);
+// The 404 page when no note is tagged 404, from the theme's 404.html or the page template
+fn generate_not_found_page(
+    output_dir: &Path,
+    urls: &SiteUrls,
+    template: &str,
+    extra_content: &str,
+) -> std::io::Result<()> {
+    let mut html = template.replace("{{title}}", "Page not found");
+    html = html.replace("{{article_title}}", "Page not found");
+    html = html.replace("{{css_path}}", &urls.asset_url("styles.css"));
+    html = html.replace("{{site_name}}", "SyMark");
+    html = html.replace("{{meta_description}}", "This page doesn't exist");
+    html = html.replace("{{blog_description}}", "This page doesn't exist");
+    html = html.replace("{{note_meta}}", "");
+    html = html.replace("{{last_updated_date}}", "");
+    html = html.replace("{{category}}", "Notes");
+    html = html.replace("{{next_article_url}}", "#");
+    html = html.replace("{{next_article_title}}", "");
+    html = html.replace("{{back_navigation}}", &back_navigation_html(urls));
+    html = html.replace("{{#header_image}}", "<!-- ");
+    html = html.replace("{{/header_image}}", " -->");
 
-    // Generate tag color HTML blocks
-    let mut tag_color_blocks = String::new();
-    for (tag, color) in &tag_colors {
-        tag_color_blocks.push_str(&format!(
-            "<span style=\"display: inline-block; padding: 5px 10px; background: {}; color: white; border-radius: 4px;\">{}</span>\n",
-            color, tag
-        ));
-    }
-    // Add default color
-    tag_color_blocks.push_str(
-        "<span style=\"display: inline-block; padding: 5px 10px; background: #69b3a2; color: white; border-radius: 4px;\">Default</span>\n"
+    let content = format!(
+        "<p>The page you are looking for doesn't exist or has moved.</p>\n{}",
+        extra_content
     );
-
-    // Process the graph template
-    let mut graph_html = graph_template.to_string();
-
-    // Replace template variables
-    graph_html = graph_html.replace("{{site_name}}", "SyMark");
-
-    // Remove tag colors section since we're using a full-screen layout
-    graph_html = graph_html.replace("{{#tag_colors}}", "<!-- ");
-    graph_html = graph_html.replace("{{/tag_colors}}", " -->");
-    graph_html = graph_html.replace("{{tag_color_blocks}}", "");
-
-    // Insert the tag colors JSON
-    graph_html = graph_html.replace("{{tag_colors_json}}", &tag_colors_json.to_string());
-
-    // Insert the graph data
-    graph_html = graph_html.replace(
-        "const graphData = {\n            nodes: [],\n            links: []\n        };",
-        &format!("const graphData = {};", graph_data.to_string()),
+    html = html.replace("{{content}}", &content);
+    html = html.replace(
+        "{{generation_date}}",
+        &Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
     );
 
-    // Generate the HTML file
-    let output_path = output_dir.join("graph.html");
-    let mut file = File::create(&output_path)?;
-
-    // Clean up any remaining template variables
-    let cleaned_html = remove_zero_width_spaces(&graph_html);
+    let cleaned_html = remove_zero_width_spaces(&html);
     let cleaned_html = cleanup_template_variables(&cleaned_html);
+    let final_html = comment_processor(&cleaned_html);
 
-    file.write_all(cleaned_html.as_bytes())?;
+    let file_path = output_dir.join(urls.page_file("404"));
+    let mut file = create_page_file(&file_path)?;
+    file.write_all(final_html.as_bytes())?;
 
-    println!("Generated graph page: {:?}", output_path);
     Ok(())
 }
 
-// Helper function to scan blocks for links
-fn scan_blocks_for_links(
-    blocks: &[Block],
-    notes_map: &HashMap<String, Note>,
-    source_id: &str,
-    links: &mut Vec<serde_json::Value>,
-    node_indices: &HashMap<String, usize>,
-) {
-    for block in blocks {
-        // Check if this is a block reference
-        if block.Type == "NodeTextMark" && block.TextMarkType == "block-ref" {
-            let target_id = &block.TextMarkBlockRefID;
-            if !target_id.is_empty() && notes_map.contains_key(target_id) && source_id != target_id
-            {
-                // Check if this link already exists
-                let link_exists = links.iter().any(|link| {
-                    let s = link["source"].as_str().unwrap();
-                    let t = link["target"].as_str().unwrap();
-                    (s == source_id && t == target_id) || (s == target_id && t == source_id)
-                });
-
-                if !link_exists {
-                    links.push(json!({
-                        "source": source_id,
-                        "target": target_id,
-                        "value": 1
-                    }));
-                }
-            }
-        }
+// A page that only forwards to `target`, for permalinks and moved pages
+fn redirect_page_html(target: &str, title: &str) -> String {
+    let target = escape_html(target);
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>{title}</title>
+<link rel="canonical" href="{target}">
+<meta name="robots" content="noindex">
+<meta http-equiv="refresh" content="0; url={target}">
+</head>
+<body>
+<p>This page has moved to <a href="{target}">{title}</a>.</p>
+</body>
+</html>
+"#,
+        title = escape_html(title),
+        target = target
+    )
+}
 
-        // Recursively check children blocks
-        if !block.Children.is_empty() {
-            scan_blocks_for_links(&block.Children, notes_map, source_id, links, node_indices);
-        }
+// Output file for an old path written by hand, such as "/old-name.html" or "/posts/old-name/";
+// a path without an extension is named the way note pages are
+fn redirect_file(path: &str, urls: &SiteUrls) -> Option<String> {
+    let mut path = path.trim();
+    if urls.root.starts_with('/') && urls.root.len() > 1 {
+        path = path.strip_prefix(urls.root.as_str()).unwrap_or(path);
+    }
+    let path = path.trim_start_matches('/');
+    // Backslashes separate directories on Windows, so ".." is looked for between them too
+    if path.is_empty() || path.contains("://") || path.split(['/', '\\']).any(|part| part == "..") {
+        return None;
+    }
+
+    if path.ends_with('/') {
+        Some(format!("{}index.html", path))
+    } else if path.ends_with(".html") || path.ends_with(".htm") {
+        Some(path.to_string())
+    } else {
+        Some(urls.page_file(path))
     }
 }
 
-fn generate_tag_page(
-    tag: &str,
+// Collects every old path that should forward to a note: the redirect map from the config,
+// alias and custom-redirect-from attributes, and URLs a note had in earlier builds. Explicit
+// redirects come first, and the URL history in the cache is updated with the current paths.
+fn collect_redirects(
     notes_map: &HashMap<String, Note>,
-    output_dir: &Path,
-    all_tags: &HashSet<String>,
-    html_template: &str,
-) -> std::io::Result<()> {
-    // Count notes with this tag (we already calculated this above)
-
-    let mut html = html_template.replace("{{title}}", &format!("Tag: {}", tag));
-    html = html.replace("{{article_title}}", &format!("Tag: {}", tag));
-    html = html.replace("{{css_path}}", "styles.css");
-    html = html.replace("{{site_name}}", "SyMark");
-    // Filter notes with this tag for meta description and TOC
-    let tagged_notes: Vec<&Note> = notes_map
-        .values()
-        .filter(|n| n.Properties.tags.split(',').any(|t| t.trim() == tag))
-        .collect();
-    let note_count = tagged_notes.len();
+    urls: &SiteUrls,
+    config: &Config,
+) -> std::io::Result<Vec<(String, String, bool)>> {
+    let mut redirects = Vec::new();
+
+    let mut map: Vec<_> = config.redirects.map.iter().collect();
+    map.sort();
+    for (from, to) in map {
+        match redirect_file(from, urls) {
+            Some(file) => redirects.push((file, to.clone(), true)),
+            None => println!("Warning: Ignoring redirect from invalid path \"{}\"", from),
+        }
+    }
 
-    let meta_description = if note_count == 1 {
-        format!("1 note has the tag \"{}\"", tag)
-    } else {
-        format!("{} notes have the tag \"{}\"", note_count, tag)
-    };
+    let mut notes: Vec<_> = notes_map.values().collect();
+    notes.sort_by_key(|note| &note.ID);
+    for note in &notes {
+        for alias in note.Properties.alias.split(',') {
+            let slug = slugify(alias.trim(), config.urls.transliterate).to_lowercase();
+            if !slug.is_empty() {
+                redirects.push((urls.page_file(&slug), note.ID.clone(), true));
+            }
+        }
+        for from in note.Properties.custom_redirect_from.split(',') {
+            if from.trim().is_empty() {
+                continue;
+            }
+            match redirect_file(from, urls) {
+                Some(file) => redirects.push((file, note.ID.clone(), true)),
+                None => println!(
+                    "Warning: Ignoring redirect from invalid path \"{}\" on {}",
+                    from.trim(),
+                    note.ID
+                ),
+            }
+        }
+    }
 
-    html = html.replace("{{meta_description}}", &meta_description);
-    html = html.replace("{{blog_description}}", &meta_description);
-    html = html.replace("{{reading_time}}", "2");
-    html = html.replace("{{author_name}}", "Notes Author");
+    if config.redirects.track_moves {
+        // Output file -> ID of the note last published there
+        let history_path = Path::new(&config.cache_dir).join("urls.json");
+        let mut history: BTreeMap<String, String> = fs::read_to_string(&history_path)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default();
 
-    // Get current date for tag page generation
-    let timestamp = Local::now().format("%Y%m%d%H%M%S").to_string();
+        let mut moved: Vec<_> = history
+            .iter()
+            .filter(|(file, id)| notes_map.contains_key(*id) && urls.note_file(id) != **file)
+            .map(|(file, id)| (file.clone(), id.clone(), false))
+            .collect();
+        moved.sort();
+        redirects.extend(moved);
 
-    // Create tag cloud metadata for tag page
-    let meta = format!(
-        "<span class=\"meta-tag date-tag\">Created on {}</span>",
-        naturalize_date(&timestamp)
-    );
-    html = html.replace("{{note_meta}}", &meta);
-    html = html.replace("{{last_updated_date}}", ""); // Clear this as we're using note_meta
+        for note in &notes {
+            history.insert(urls.note_file(&note.ID), note.ID.clone());
+        }
+        fs::create_dir_all(&config.cache_dir)?;
+        fs::write(
+            &history_path,
+            serde_json::to_string_pretty(&history).unwrap_or_default(),
+        )?;
+    }
 
-    html = html.replace("{{category}}", "Tags");
-    html = html.replace("{{next_article_url}}", "#");
-    html = html.replace("{{next_article_title}}", "");
-    html = html.replace("{{back_navigation}}", BACK_NAVIGATION_HTML);
+    Ok(redirects)
+}
 
-    // No header image for tag pages
-    html = html.replace("{{#header_image}}", "<!-- ");
-    html = html.replace("{{/header_image}}", " -->");
+// Writes a redirect page at every old path of a note, skipping paths a generated page already
+// uses, and optionally a _redirects file with the same rules
+fn generate_redirects(
+    notes_map: &HashMap<String, Note>,
+    output_dir: &Path,
+    urls: &SiteUrls,
+    config: &Config,
+) -> std::io::Result<usize> {
+    let mut written = HashSet::new();
+    let mut rules = String::new();
+
+    for (file, to, explicit) in collect_redirects(notes_map, urls, config)? {
+        let path = output_dir.join(&file);
+        if written.contains(&file) || path.exists() {
+            if explicit && !written.contains(&file) {
+                println!(
+                    "Warning: Redirect from \"{}\" to {} skipped, a page is already published there",
+                    file, to
+                );
+            }
+            continue;
+        }
 
-    // Generate navigation items - sort by title
-    let mut sorted_notes: Vec<_> = notes_map
-        .values()
-        .filter(|n| !n.Properties.title.is_empty())
-        .collect();
-    sorted_notes.sort_by(|a, b| a.Properties.title.cmp(&b.Properties.title));
+        let (target, title) = match notes_map.get(&to) {
+            Some(note) => (urls.note_url(&note.ID), note.Properties.title.clone()),
+            None => (urls.asset_url(&to), to.clone()),
+        };
 
-    // Generate table of contents
-    let mut toc_items = Vec::new();
+        let mut out = create_page_file(&path)?;
+        out.write_all(
+            redirect_page_html(&urls.url_from_file(&file, target.clone()), &title).as_bytes(),
+        )?;
 
-    toc_items.push(TocItem {
-        id: "section-tagged-notes".to_string(),
-        text: if tagged_notes.len() == 1 {
-            format!("1 note has the tag \"{}\"", tag)
+        let target = if target.contains("://") || target.starts_with('/') {
+            target
         } else {
-            format!("{} notes have the tag \"{}\"", tagged_notes.len(), tag)
-        },
-        level: 2,
-    });
+            format!("/{}", target)
+        };
+        rules.push_str(&format!("{} {} 301\n", urls.site_path(&file), target));
+        written.insert(file);
+    }
 
-    toc_items.push(TocItem {
-        id: "section-all-tags".to_string(),
-        text: "All Tags".to_string(),
-        level: 2,
-    });
+    if config.redirects.redirects_file && !rules.is_empty() {
+        fs::write(output_dir.join("_redirects"), rules)?;
+    }
 
-    let toc_html = generate_toc_html(&toc_items);
-    html = html.replace("{{table_of_contents}}", &toc_html);
+    Ok(written.len())
+}
 
-    // Generate tags section - sort alphabetically
-    let mut tags: Vec<_> = all_tags.iter().collect();
-    tags.sort();
+// Writes a redirect stub for every referenced block to the note that currently contains it, so
+// external links to b/<block-id>.html keep working when a block moves to another note
+fn generate_block_redirects(
+    notes_map: &HashMap<String, Note>,
+    output_dir: &Path,
+    margin_info_tracker: &MarginInfoTracker,
+    block_index: &HashMap<String, String>,
+    urls: &SiteUrls,
+) -> std::io::Result<usize> {
+    let mut block_ids: Vec<&String> = margin_info_tracker
+        .transclusions
+        .keys()
+        .chain(margin_info_tracker.linked_mentions.keys())
+        .filter(|id| !notes_map.contains_key(*id))
+        .collect();
+    block_ids.sort();
+    block_ids.dedup();
 
-    let mut tags_html = String::new();
-    for t in tags {
-        let class = if t == tag { "tag active" } else { "tag" };
+    let mut count = 0;
+    for block_id in block_ids {
+        let Some(note) = block_index.get(block_id).and_then(|id| notes_map.get(id)) else {
+            continue;
+        };
 
-        // Count notes with this tag
-        let tag_notes: Vec<&Note> = notes_map
-            .values()
-            .filter(|n| n.Properties.tags.split(',').any(|tag| tag.trim() == *t))
-            .collect();
-        let tag_count = tag_notes.len();
+        let file = urls.block_file(block_id);
+        let target = urls.url_from_file(&file, ref_target_href(urls, note, block_id));
+        let mut out = create_page_file(&output_dir.join(&file))?;
+        out.write_all(redirect_page_html(&target, &note.Properties.title).as_bytes())?;
+        count += 1;
+    }
 
-        let mut tooltip_text = if tag_count == 1 {
-            format!("1 note has the tag \"{}\"", t)
-        } else {
-            format!("{} notes have the tag \"{}\"", tag_count, t)
-        };
+    Ok(count)
+}
 
-        // Add titles of up to 3 notes in the tooltip
-        if !tag_notes.is_empty() {
-            tooltip_text.push_str(": ");
-            let note_titles: Vec<String> = tag_notes
-                .iter()
-                .take(3)
-                .map(|n| format!("\"{}\"", n.Properties.title))
-                .collect();
+// Name of a tag without its parents: "design" for "project/alpha/design"
+fn tag_leaf_name(tag: &str) -> &str {
+    &tag[tag.rfind('/').map_or(0, |index| index + 1)..]
+}
+
+// Most recent update (or creation) timestamp among the notes with a tag
+fn tag_last_updated(tag: &str, notes_map: &HashMap<String, Note>) -> String {
+    notes_map
+        .values()
+        .filter(|n| note_has_tag(n, tag))
+        .map(|n| {
+            if !n.Properties.updated.is_empty() {
+                n.Properties.updated.clone()
+            } else {
+                n.Properties.created.clone()
+            }
+        })
+        .max()
+        .unwrap_or_default()
+}
 
-            tooltip_text.push_str(&note_titles.join(", "));
+// One line of the tag index: link, note count and last update
+fn tag_index_entry(
+    tag: &str,
+    label: &str,
+    notes_map: &HashMap<String, Note>,
+    urls: &SiteUrls,
+) -> String {
+    let count = notes_map.values().filter(|n| note_has_tag(n, tag)).count();
+    format!(
+        "<a href=\"{}\" class=\"tag\">{}</a> <span class=\"tag-count\">{} {}</span> <span class=\"tag-updated\">Updated {}</span>",
+        urls.tag_url(tag),
+        escape_html(label),
+        count,
+        if count == 1 { "note" } else { "notes" },
+        naturalize_date(&tag_last_updated(tag, notes_map))
+    )
+}
 
-            if tag_count > 3 {
-                tooltip_text.push_str(", ...");
-            }
-        }
+// Nested list of a tag and everything below it, for the hierarchy grouping
+fn tag_index_tree(
+    tag: &str,
+    tags: &[&String],
+    notes_map: &HashMap<String, Note>,
+    urls: &SiteUrls,
+    html: &mut String,
+) {
+    html.push_str("<li>");
+    html.push_str(&tag_index_entry(tag, tag_leaf_name(tag), notes_map, urls));
 
-        tags_html.push_str(&format!(
-            "<span class=\"tooltip\"><a href=\"tag_{}.html\" class=\"{}\">{}</a><span class=\"right bottom\"><span class=\"tooltip-excerpt\">{}</span><i></i></span></span>\n",
-            t.replace(" ", "_"),
-            class,
-            t,
-            tooltip_text
-        ));
+    let children: Vec<_> = tags
+        .iter()
+        .filter(|t| parent_tags(t).last() == Some(&tag))
+        .collect();
+    if !children.is_empty() {
+        html.push_str("\n<ul>\n");
+        for child in children {
+            tag_index_tree(child, tags, notes_map, urls, html);
+        }
+        html.push_str("</ul>");
     }
+    html.push_str("</li>\n");
+}
 
-    // Process notes with this tag
-    let mut content = format!("<ul>");
-    for note in &tagged_notes {
-        // Get excerpt from note content for tooltip
-        let excerpt = {
-            // First, look for the first paragraph with actual content
-            let mut content_text = String::new();
+fn generate_tags_index_page(
+    notes_map: &HashMap<String, Note>,
+    output_dir: &Path,
+    all_tags: &HashSet<String>,
+    urls: &SiteUrls,
+    html_template: &str,
+    tags_config: &TagsConfig,
+    reading: &ReadingStats,
+) -> std::io::Result<()> {
+    let mut html = html_template.replace("{{title}}", "All Tags");
+    html = html.replace("{{article_title}}", "All Tags");
+    html = html.replace("{{css_path}}", &urls.asset_url("styles.css"));
+    html = html.replace("{{site_name}}", "SyMark");
 
-            // Process blocks to find meaningful content
-            for block in &note.Children {
-                // Find the first paragraph with actual content
-                if block.Type == "P" && !block.Data.is_empty() {
-                    content_text = escape_html(&block.Data);
+    let meta_description = if all_tags.len() == 1 {
+        "1 tag".to_string()
+    } else {
+        format!("{} tags", all_tags.len())
+    };
+    html = html.replace("{{meta_description}}", &meta_description);
+    html = html.replace("{{blog_description}}", &meta_description);
+    let reading_total = reading.total(notes_map.keys());
+    html = reading_total.fill_template(&html, reading.config);
+    html = html.replace("{{author_name}}", "Notes Author");
 
-                    // If it's a short paragraph, try to get more content
-                    if content_text.len() < 120 && note.Children.len() > 1 {
-                        // Look for a second paragraph
-                        for second_block in &note.Children {
-                            if second_block.ID != block.ID
-                                && second_block.Type == "P"
-                                && !second_block.Data.is_empty()
-                            {
-                                content_text.push_str(" ");
-                                content_text.push_str(&escape_html(&second_block.Data));
-                                break;
-                            }
-                        }
-                    }
+    let now = Local::now().format("%Y%m%d%H%M%S").to_string();
+    let meta = format!(
+        "<span class=\"meta-tag date-tag\">Created on {}</span>{}",
+        naturalize_date(&now),
+        reading_total.meta_html(reading.config)
+    );
+    html = html.replace("{{note_meta}}", &meta);
+    html = html.replace("{{last_updated_date}}", "");
 
-                    break;
-                }
-            }
+    html = html.replace("{{category}}", "Tags");
+    html = html.replace("{{next_article_url}}", "#");
+    html = html.replace("{{next_article_title}}", "");
+    html = html.replace("{{back_navigation}}", &back_navigation_html(urls));
 
-            // If we didn't find a paragraph, look for any text in the first few blocks
-            if content_text.is_empty() {
-                for block in note.Children.iter().take(3) {
-                    if !block.Data.is_empty() {
-                        content_text = escape_html(&block.Data);
-                        break;
-                    }
+    // No header image for the tag index
+    html = html.replace("{{#header_image}}", "<!-- ");
+    html = html.replace("{{/header_image}}", " -->");
 
-                    // Check children if this block has no direct content
-                    if block.Children.len() > 0 {
-                        for child in &block.Children {
-                            if !child.Data.is_empty() {
-                                content_text = escape_html(&child.Data);
-                                break;
-                            }
-                        }
-                        if !content_text.is_empty() {
-                            break;
+    let mut tags: Vec<_> = all_tags.iter().collect();
+    tags.sort_by_key(|t| t.to_lowercase());
+
+    // Group the tags into sections, each with its own table of contents entry
+    let mut groups: Vec<(String, String)> = Vec::new();
+    match tags_config.group_by.as_str() {
+        "letter" => {
+            for tag in &tags {
+                let letter = tag
+                    .chars()
+                    .next()
+                    .map(|c| {
+                        if c.is_alphabetic() {
+                            c.to_uppercase().collect()
+                        } else {
+                            "#".to_string()
                         }
-                    }
+                    })
+                    .unwrap_or_default();
+                let entry = format!("<li>{}</li>\n", tag_index_entry(tag, tag, notes_map, urls));
+                match groups.last_mut() {
+                    Some((group, entries)) if *group == letter => entries.push_str(&entry),
+                    _ => groups.push((letter, entry)),
                 }
             }
-
-            // Smart truncation - limit to 1 sentence for shorter previews
-            content_text = smart_truncate_excerpt(&content_text, 1);
-
-            if !content_text.is_empty() {
-                content_text
-            } else {
-                // If no content found, use tags as fallback
-                let tags = note
-                    .Properties
-                    .tags
-                    .split(',')
-                    .map(|t| t.trim())
-                    .filter(|t| !t.is_empty())
-                    .collect::<Vec<_>>();
-
-                if !tags.is_empty() {
-                    format!("Tagged with: {}", tags.join(", "))
-                } else {
-                    // Last resort: use title
-                    note.Properties.title.clone()
-                }
+        }
+        "hierarchy" => {
+            // Top-level tags, and tags below a page tag that has no entry of its own
+            let roots = tags.iter().filter(|t| {
+                parent_tags(t)
+                    .last()
+                    .is_none_or(|parent| PAGE_TAGS.contains(parent))
+            });
+            for tag in roots {
+                let mut entries = String::new();
+                tag_index_tree(tag, &tags, notes_map, urls, &mut entries);
+                groups.push((tag.to_string(), entries));
             }
-        };
-
-        // Create tooltip with title and excerpt
-        content.push_str(&format!(
-            "<li><span class=\"tooltip\"><a href=\"{}.html\">{}</a><span class=\"right bottom\"><span class=\"tooltip-title\">{}</span><span class=\"tooltip-excerpt\">{}</span><i></i></span></span>\n",
-            note.ID,
-            note.Properties.title,
-            note.Properties.title,
-            excerpt
-        ));
+        }
+        _ => {
+            let entries = tags
+                .iter()
+                .map(|tag| format!("<li>{}</li>\n", tag_index_entry(tag, tag, notes_map, urls)))
+                .collect();
+            groups.push((String::new(), entries));
+        }
     }
 
-    content.push_str("</ul>");
-
-    content.push_str("<h2 id=\"section-all-tags\">All Tags</h2>\n<div class=\"tags-container\">\n");
-    content.push_str(&tags_html);
-    content.push_str("</div>");
+    let mut toc_items = Vec::new();
+    let mut content = String::new();
+    for (index, (group, entries)) in groups.iter().enumerate() {
+        if !group.is_empty() {
+            let id = format!("section-tags-{}", index);
+            let text = escape_html(group);
+            content.push_str(&format!("<h2 id=\"{}\">{}</h2>\n", id, text));
+            toc_items.push(TocItem { id, text, level: 2 });
+        }
+        content.push_str("<ul class=\"tag-index\">\n");
+        content.push_str(entries);
+        content.push_str("</ul>\n");
+    }
 
+    let toc_html = generate_toc_html(&toc_items);
+    html = html.replace("{{table_of_contents}}", &toc_html);
     html = html.replace("{{content}}", &content);
 
-    // Set metadata
-    html = html.replace("{{note_meta}}", "");
     html = html.replace(
         "{{generation_date}}",
         &Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
@@ -2005,14 +6097,57 @@ fn generate_tag_page(
 
     let final_html = comment_processor(&cleaned_html);
 
-    // Write to file
-    let file_path = output_dir.join(format!("tag_{}.html", tag.replace(" ", "_")));
-    let mut file = File::create(&file_path)?;
+    let file_path = output_dir.join(urls.page_file("tags"));
+    let mut file = create_page_file(&file_path)?;
     file.write_all(final_html.as_bytes())?;
 
     Ok(())
 }
 
+// Tag cloud for the {{tag_cloud}} template variable, with more used tags drawn larger
+fn generate_tag_cloud(
+    notes_map: &HashMap<String, Note>,
+    all_tags: &HashSet<String>,
+    urls: &SiteUrls,
+) -> String {
+    let mut tags: Vec<_> = all_tags
+        .iter()
+        .map(|tag| {
+            let count = notes_map.values().filter(|n| note_has_tag(n, tag)).count();
+            (tag, count)
+        })
+        .collect();
+    tags.sort_by_key(|(tag, _)| tag.to_lowercase());
+
+    let max_count = tags
+        .iter()
+        .map(|(_, count)| *count)
+        .max()
+        .unwrap_or(1)
+        .max(1);
+
+    let mut html = String::from("<div class=\"tag-cloud-list\">\n");
+    for (tag, count) in tags {
+        // Scale from 0.8em to 1.6em on a logarithmic curve so one huge tag doesn't dwarf the rest
+        let weight = ((count + 1) as f64).ln() / ((max_count + 1) as f64).ln();
+        html.push_str(&format!(
+            "<a href=\"{}\" class=\"tag\" style=\"font-size: {:.2}em\" title=\"{} {}\">{}</a>\n",
+            urls.tag_url(tag),
+            0.8 + 0.8 * weight,
+            count,
+            if count == 1 { "note" } else { "notes" },
+            escape_html(tag)
+        ));
+    }
+    html.push_str(&format!(
+        "<a href=\"{}\" class=\"tag-cloud-all\">All tags</a>\n</div>",
+        urls.page_url("tags")
+    ));
+
+    html
+}
+
+#[allow(clippy::too_many_arguments)]
 fn generate_html_for_note(
     id: &str,
     notes_map: &HashMap<String, Note>,
@@ -2021,7 +6156,8 @@ fn generate_html_for_note(
     all_tags: &HashSet<String>,
     html_template: &str,
     margin_info_tracker: &MarginInfoTracker,
-) -> std::io::Result<()> {
+    render_context: &RenderContext,
+) -> std::io::Result<TextStats> {
     println!("Generating HTML for note ID: {}", id);
     let note = &notes_map[id];
     let title = if !note.Properties.title.is_empty() {
@@ -2038,7 +6174,11 @@ fn generate_html_for_note(
                 let tooltip_content = page_mentions
                     .iter()
                     .map(|(note_id, note_title)| {
-                        format!(r#"<a href="{}.html">{}</a>"#, note_id, note_title)
+                        format!(
+                            r#"<a href="{}">{}</a>"#,
+                            render_context.urls.note_url(note_id),
+                            note_title
+                        )
                     })
                     .collect::<Vec<_>>()
                     .join("");
@@ -2076,83 +6216,23 @@ fn generate_html_for_note(
     let title_with_mentions = format!("{}{}", title, page_mentions_html);
     let mut html = html_template.replace("{{title}}", &title);
     html = html.replace("{{article_title}}", &title_with_mentions);
-    html = html.replace("{{css_path}}", "styles.css");
+    html = html.replace("{{css_path}}", &render_context.urls.asset_url("styles.css"));
     html = html.replace("{{site_name}}", "SyMark");
 
-    // Extract a good description for meta and OpenGraph tags
-    // Similar to how tooltip excerpts are generated
-    let mut description = String::new();
-    let mut paragraph_count = 0;
-
-    // Process blocks to find meaningful content
-    for block in &note.Children {
-        if block.Type == "NodeParagraph" || block.Type == "Paragraph" {
-            // Only process first few paragraphs
-            if paragraph_count > 0 {
-                description.push(' '); // Add space between paragraphs
-            }
-
-            // Get text from paragraph
-            let paragraph_content = render_blocks(
-                &block.Children,
-                notes_map,
-                id_to_path,
-                margin_info_tracker,
-                false,
-            );
-
-            // Strip HTML
-            let mut plain_text = String::new();
-            let mut in_tag = false;
-
-            for c in paragraph_content.chars() {
-                if c == '<' {
-                    in_tag = true;
-                } else if c == '>' {
-                    in_tag = false;
-                } else if !in_tag {
-                    plain_text.push(c);
-                }
-            }
-
-            description.push_str(&plain_text);
-            paragraph_count += 1;
-
-            // Limit to 2-3 paragraphs for description
-            if paragraph_count >= 2 && description.len() > 100 {
-                break;
-            }
-        }
-    }
-
-    // If no paragraphs found, use title
-    if description.is_empty() {
-        description = title.clone();
-    }
-
-    // Escape HTML in the description
-    let description = escape_html(&description);
-
-    // Truncate description if too long (typical limit is around 200 chars)
-    let truncated_description = if description.len() > 200 {
-        // Ensure we cut at a character boundary
-        let mut end_index = 0;
-        let mut char_count = 0;
-        for (idx, _) in description.char_indices() {
-            end_index = idx;
-            char_count += 1;
-            if char_count >= 197 {
-                break;
-            }
-        }
-        format!("{}...", &description[0..end_index])
-    } else {
-        description
-    };
-
-    html = html.replace("{{meta_description}}", &truncated_description);
+    let description = note_description(
+        note,
+        &title,
+        notes_map,
+        id_to_path,
+        margin_info_tracker,
+        render_context,
+    );
+    html = html.replace("{{meta_description}}", &description);
     html = html.replace("{{blog_description}}", "A collection of notes");
-    html = html.replace("{{back_navigation}}", BACK_NAVIGATION_HTML);
+    html = html.replace(
+        "{{back_navigation}}",
+        &back_navigation_html(render_context.urls),
+    );
 
     // Define site base URL - this should be configurable in the future
     let site_base_url = "https://du82.github.io/symark";
@@ -2162,7 +6242,10 @@ fn generate_html_for_note(
     if id == "index" {
         html = html.replace("{{og_url}}", &format!("{}", site_base_url));
     } else {
-        html = html.replace("{{og_url}}", &format!("{}{}.html", site_base_url, id));
+        html = html.replace(
+            "{{og_url}}",
+            &format!("{}/{}", site_base_url, render_context.urls.note_path(id)),
+        );
     }
 
     // Handle OpenGraph image if there's a header image
@@ -2198,16 +6281,18 @@ fn generate_html_for_note(
     if !note.Properties.title_img.is_empty() {
         html = html.replace("{{#header_image}}", "");
         html = html.replace("{{/header_image}}", "");
-        html = html.replace("{{header_image}}", &note.Properties.title_img);
+        html = html.replace(
+            "{{header_image}}",
+            &render_context
+                .urls
+                .rebase_assets(&note.Properties.title_img),
+        );
     } else {
         // Remove header image section if no image
         html = html.replace("{{#header_image}}", "<!-- ");
         html = html.replace("{{/header_image}}", " -->");
     }
 
-    // Reading time will be calculated by JavaScript
-    html = html.replace("{{reading_time}}", "");
-
     html = html.replace("{{author_name}}", "Notes Author");
 
     // Use created date for publish_date
@@ -2284,16 +6369,53 @@ fn generate_html_for_note(
     let mut tags: Vec<_> = all_tags.iter().collect();
     tags.sort();
 
-    // Generate content with heading IDs for TOC
+    // Generate content with heading IDs for TOC, with the page itself at the bottom of
+    // the transclusion stack so embeds of this note are caught as circular
+    render_context.linked_virtual_refs.borrow_mut().clear();
+    render_context
+        .transclusion_stack
+        .borrow_mut()
+        .push(note.ID.clone());
     let content_html = render_blocks_with_ids(
         &note.Children,
         notes_map,
         id_to_path,
         margin_info_tracker,
+        render_context,
         false,
     );
+    render_context.transclusion_stack.borrow_mut().pop();
+
+    let reading = &render_context.config.reading;
+    let stats = TextStats::from_html(&content_html);
+    html = stats.fill_template(&html, reading);
     html = html.replace("{{content}}", &content_html);
 
+    html = html.replace(
+        "{{backlinks}}",
+        &generate_backlinks_html(
+            note,
+            notes_map,
+            id_to_path,
+            margin_info_tracker,
+            render_context,
+        ),
+    );
+    html = html.replace(
+        "{{unlinked_mentions}}",
+        &generate_unlinked_mentions_html(
+            note,
+            notes_map,
+            id_to_path,
+            margin_info_tracker,
+            render_context,
+        ),
+    );
+    html = html.replace(
+        "{{local_graph}}",
+        &generate_local_graph_html(note, notes_map, output_dir, render_context)?,
+    );
+
     // Already handled OpenGraph URL earlier
 
     // Generate note metadata as a tag cloud
@@ -2315,6 +6437,10 @@ fn generate_html_for_note(
         ));
     }
 
+    if stats.word_count() > 0 {
+        meta.push_str(&stats.meta_html(reading));
+    }
+
     // Add tags
     if !note.Properties.tags.is_empty() {
         let mut tags: Vec<_> = note.Properties.tags.split(',').map(|t| t.trim()).collect();
@@ -2326,8 +6452,8 @@ fn generate_html_for_note(
         for tag in &tags {
             if !tag.is_empty() {
                 meta.push_str(&format!(
-                    "<a href=\"tag_{}.html\" class=\"meta-tag\">{}</a>",
-                    tag.replace(" ", "_"),
+                    "<a href=\"{}\" class=\"meta-tag\">{}</a>",
+                    render_context.urls.tag_url(tag),
                     tag
                 ));
 
@@ -2355,7 +6481,10 @@ fn generate_html_for_note(
     }
 
     // Generate absolute URL for OpenGraph URL
-    html = html.replace("{{og_url}}", &format!("{}{}.html", site_base_url, id));
+    html = html.replace(
+        "{{og_url}}",
+        &format!("{}/{}", site_base_url, render_context.urls.note_path(id)),
+    );
 
     // TODO: Add configuration option for site_base_url
     // In the future, this should be read from a config file and used consistently
@@ -2378,12 +6507,12 @@ fn generate_html_for_note(
     let final_html = comment_processor(&cleaned_html);
 
     // Write to file
-    let file_path = output_dir.join(format!("{}.html", id));
+    let file_path = output_dir.join(render_context.urls.note_file(id));
     println!("Writing HTML to file: {:?}", file_path);
-    let mut file = File::create(&file_path)?;
+    let mut file = create_page_file(&file_path)?;
     file.write_all(final_html.as_bytes())?;
 
-    Ok(())
+    Ok(stats)
 }
 
 fn render_blocks_with_ids(
@@ -2391,6 +6520,7 @@ fn render_blocks_with_ids(
     notes_map: &HashMap<String, Note>,
     id_to_path: &HashMap<String, PathBuf>,
     margin_info_tracker: &MarginInfoTracker,
+    render_context: &RenderContext,
     is_in_transclusion: bool,
 ) -> String {
     let mut id_counter = 0;
@@ -2410,26 +6540,44 @@ fn render_blocks_with_ids(
                     id
                 };
 
-                html.push_str(&format!("<h{} id=\"{}\">", level, id));
+                let mut heading_html = format!("<h{} id=\"{}\">", level, id);
 
                 // Render the heading content
+                let was_in_heading = render_context.in_heading.replace(true);
                 for child in &block.Children {
                     if child.Type == "NodeText" {
-                        html.push_str(&escape_html(&child.Data));
+                        heading_html.push_str(&escape_html(&child.Data));
                     } else if child.Type == "NodeTextMark" {
-                        html.push_str(&render_text_mark(child, notes_map, id_to_path));
+                        heading_html.push_str(&render_text_mark(
+                            child,
+                            notes_map,
+                            id_to_path,
+                            render_context,
+                        ));
                     } else {
-                        html.push_str(&render_block(
+                        heading_html.push_str(&render_block(
                             child,
                             notes_map,
                             id_to_path,
                             margin_info_tracker,
+                            render_context,
                             is_in_transclusion,
                         ));
                     }
                 }
+                render_context.in_heading.set(was_in_heading);
 
-                html.push_str(&format!("</h{}>\n", level));
+                heading_html.push_str(&format!("</h{}>\n", level));
+
+                // Headings show the numbers for references to the heading itself, which stand for
+                // its section; blocks inside the section keep their own numbers
+                html.push_str(&add_margin_info(
+                    block,
+                    heading_html,
+                    margin_info_tracker,
+                    render_context,
+                    is_in_transclusion,
+                ));
             }
             // For other block types, use the regular render_block function
             _ => {
@@ -2438,6 +6586,7 @@ fn render_blocks_with_ids(
                     notes_map,
                     id_to_path,
                     margin_info_tracker,
+                    render_context,
                     is_in_transclusion,
                 ));
             }
@@ -2481,106 +6630,134 @@ fn render_blocks(
     notes_map: &HashMap<String, Note>,
     id_to_path: &HashMap<String, PathBuf>,
     margin_info_tracker: &MarginInfoTracker,
+    render_context: &RenderContext,
     is_in_transclusion: bool,
 ) -> String {
     let mut html = String::new();
 
     for block in blocks {
         // Render the actual block content with potential indicator
-        let mut block_html = render_single_block(
+        let block_html = render_single_block(
             block,
             notes_map,
             id_to_path,
             margin_info_tracker,
+            render_context,
             is_in_transclusion,
         );
 
-        // Add transcluded indicator if needed (only in original context)
-        // Check for margin info indicators (transcluded references and linked mentions)
-        let transclusion_count = if !is_in_transclusion && !block.ID.is_empty() {
-            margin_info_tracker.get_transclusion_count(&block.ID)
-        } else {
-            0
-        };
+        html.push_str(&add_margin_info(
+            block,
+            block_html,
+            margin_info_tracker,
+            render_context,
+            is_in_transclusion,
+        ));
+    }
 
-        let linked_mention_count = if !is_in_transclusion && !block.ID.is_empty() {
-            margin_info_tracker.get_linked_mention_count(&block.ID)
-        } else {
-            0
-        };
+    html
+}
 
-        if transclusion_count > 0 || linked_mention_count > 0 {
-            let mut indicators_html = String::new();
-
-            // Add transcluded indicator
-            if transclusion_count > 0 {
-                let tooltip_content =
-                    if let Some(transclusions) = margin_info_tracker.get_transclusions(&block.ID) {
-                        transclusions
-                            .iter()
-                            .map(|(note_id, note_title)| {
-                                format!(r#"<a href="{}.html">{}</a>"#, note_id, note_title)
-                            })
-                            .collect::<Vec<_>>()
-                            .join("")
-                    } else {
-                        String::new()
-                    };
+// Wraps a rendered block with its margin info numbers (transcluded references and linked
+// mentions), which are only shown where the block originally lives
+fn add_margin_info(
+    block: &Block,
+    block_html: String,
+    margin_info_tracker: &MarginInfoTracker,
+    render_context: &RenderContext,
+    is_in_transclusion: bool,
+) -> String {
+    // Add transcluded indicator if needed (only in original context)
+    // Check for margin info indicators (transcluded references and linked mentions)
+    let transclusion_count = if !is_in_transclusion && !block.ID.is_empty() {
+        margin_info_tracker.get_transclusion_count(&block.ID)
+    } else {
+        0
+    };
 
-                indicators_html.push_str(&format!(
-                    r#"<span class="margin-infonumber transcluded" data-count="{}">
-                        <span class="margin-infonumber-count">{}</span>
-                        <span class="margin-infonumber-tooltip">
-                            <span class="margin-infonumber-tooltip-title">Transcluded in:</span>
-                            <span class="margin-infonumber-tooltip-content">{}</span>
-                        </span>
-                    </span>"#,
-                    transclusion_count,
-                    MarginInfoTracker::format_count(transclusion_count),
-                    tooltip_content
-                ));
-            }
+    let linked_mention_count = if !is_in_transclusion && !block.ID.is_empty() {
+        margin_info_tracker.get_linked_mention_count(&block.ID)
+    } else {
+        0
+    };
 
-            // Add linked mentions indicator
-            if linked_mention_count > 0 {
-                let tooltip_content =
-                    if let Some(mentions) = margin_info_tracker.get_linked_mentions(&block.ID) {
-                        mentions
-                            .iter()
-                            .map(|(note_id, note_title)| {
-                                format!(r#"<a href="{}.html">{}</a>"#, note_id, note_title)
-                            })
-                            .collect::<Vec<_>>()
-                            .join("")
-                    } else {
-                        String::new()
-                    };
+    if transclusion_count > 0 || linked_mention_count > 0 {
+        let mut indicators_html = String::new();
+
+        // Add transcluded indicator
+        if transclusion_count > 0 {
+            let tooltip_content =
+                if let Some(transclusions) = margin_info_tracker.get_transclusions(&block.ID) {
+                    transclusions
+                        .iter()
+                        .map(|(note_id, note_title)| {
+                            format!(
+                                r#"<a href="{}">{}</a>"#,
+                                render_context.urls.note_url(note_id),
+                                note_title
+                            )
+                        })
+                        .collect::<Vec<_>>()
+                        .join("")
+                } else {
+                    String::new()
+                };
+
+            indicators_html.push_str(&format!(
+                r#"<span class="margin-infonumber transcluded" data-count="{}">
+                    <span class="margin-infonumber-count">{}</span>
+                    <span class="margin-infonumber-tooltip">
+                        <span class="margin-infonumber-tooltip-title">Transcluded in:</span>
+                        <span class="margin-infonumber-tooltip-content">{}</span>
+                    </span>
+                </span>"#,
+                transclusion_count,
+                MarginInfoTracker::format_count(transclusion_count),
+                tooltip_content
+            ));
+        }
 
-                indicators_html.push_str(&format!(
-                    r#"<span class="margin-infonumber linked" data-count="{}">
-                        <span class="margin-infonumber-count">{}</span>
-                        <span class="margin-infonumber-tooltip">
-                            <span class="margin-infonumber-tooltip-title">Linked mentions:</span>
-                            <span class="margin-infonumber-tooltip-content">{}</span>
-                        </span>
-                    </span>"#,
-                    linked_mention_count,
-                    MarginInfoTracker::format_count(linked_mention_count),
-                    tooltip_content
-                ));
-            }
+        // Add linked mentions indicator
+        if linked_mention_count > 0 {
+            let tooltip_content =
+                if let Some(mentions) = margin_info_tracker.get_linked_mentions(&block.ID) {
+                    mentions
+                        .iter()
+                        .map(|(note_id, note_title)| {
+                            format!(
+                                r#"<a href="{}">{}</a>"#,
+                                render_context.urls.note_url(note_id),
+                                note_title
+                            )
+                        })
+                        .collect::<Vec<_>>()
+                        .join("")
+                } else {
+                    String::new()
+                };
 
-            // Wrap the block with a positioning container and add the indicators
-            html.push_str(&format!(
-                r#"<div class="block-with-indicator">{}{}</div>"#,
-                block_html, indicators_html
+            indicators_html.push_str(&format!(
+                r#"<span class="margin-infonumber linked" data-count="{}">
+                    <span class="margin-infonumber-count">{}</span>
+                    <span class="margin-infonumber-tooltip">
+                        <span class="margin-infonumber-tooltip-title">Linked mentions:</span>
+                        <span class="margin-infonumber-tooltip-content">{}</span>
+                    </span>
+                </span>"#,
+                linked_mention_count,
+                MarginInfoTracker::format_count(linked_mention_count),
+                tooltip_content
             ));
-        } else {
-            html.push_str(&block_html);
         }
-    }
 
-    html
+        // Wrap the block with a positioning container and add the indicators
+        format!(
+            r#"<div class="block-with-indicator">{}{}</div>"#,
+            block_html, indicators_html
+        )
+    } else {
+        block_html
+    }
 }
 
 fn render_single_block(
@@ -2588,6 +6765,7 @@ fn render_single_block(
     notes_map: &HashMap<String, Note>,
     id_to_path: &HashMap<String, PathBuf>,
     margin_info_tracker: &MarginInfoTracker,
+    render_context: &RenderContext,
     is_in_transclusion: bool,
 ) -> String {
     let mut html = String::new();
@@ -2638,6 +6816,7 @@ fn render_single_block(
                         notes_map,
                         id_to_path,
                         margin_info_tracker,
+                        render_context,
                         is_in_transclusion,
                     ));
                 }
@@ -2658,6 +6837,7 @@ fn render_single_block(
                             notes_map,
                             id_to_path,
                             margin_info_tracker,
+                            render_context,
                             is_in_transclusion,
                         ));
                     }
@@ -2676,6 +6856,7 @@ fn render_single_block(
                             notes_map,
                             id_to_path,
                             margin_info_tracker,
+                            render_context,
                             is_in_transclusion,
                         ));
                         html.push_str("</div>\n");
@@ -2685,6 +6866,7 @@ fn render_single_block(
                             notes_map,
                             id_to_path,
                             margin_info_tracker,
+                            render_context,
                             is_in_transclusion,
                         ));
                         html.push_str("</div>\n");
@@ -2698,6 +6880,7 @@ fn render_single_block(
                                     notes_map,
                                     id_to_path,
                                     margin_info_tracker,
+                                    render_context,
                                     is_in_transclusion,
                                 ));
                             }
@@ -2712,6 +6895,7 @@ fn render_single_block(
                             notes_map,
                             id_to_path,
                             margin_info_tracker,
+                            render_context,
                             is_in_transclusion,
                         ));
                     }
@@ -2758,6 +6942,7 @@ fn render_single_block(
                 notes_map,
                 id_to_path,
                 margin_info_tracker,
+                render_context,
                 is_in_transclusion,
             ));
             html.push_str("</p>\n");
@@ -2770,13 +6955,16 @@ fn render_single_block(
                 String::new()
             };
             html.push_str(&format!("<h{}{}>", level, id));
+            let was_in_heading = render_context.in_heading.replace(true);
             html.push_str(&render_blocks(
                 &block.Children,
                 notes_map,
                 id_to_path,
                 margin_info_tracker,
+                render_context,
                 is_in_transclusion,
             ));
+            render_context.in_heading.set(was_in_heading);
             html.push_str(&format!("</h{}>\n", level));
         }
         "NodeList" => {
@@ -2818,6 +7006,7 @@ fn render_single_block(
                 notes_map,
                 id_to_path,
                 margin_info_tracker,
+                render_context,
                 is_in_transclusion,
             ));
 
@@ -2861,6 +7050,7 @@ fn render_single_block(
                                     notes_map,
                                     id_to_path,
                                     margin_info_tracker,
+                                    render_context,
                                     is_in_transclusion,
                                 ));
                             }
@@ -2876,6 +7066,7 @@ fn render_single_block(
                                     notes_map,
                                     id_to_path,
                                     margin_info_tracker,
+                                    render_context,
                                     is_in_transclusion,
                                 ));
                             }
@@ -2896,6 +7087,7 @@ fn render_single_block(
                                 notes_map,
                                 id_to_path,
                                 margin_info_tracker,
+                                render_context,
                                 is_in_transclusion,
                             ));
                         } else {
@@ -2904,6 +7096,7 @@ fn render_single_block(
                                 notes_map,
                                 id_to_path,
                                 margin_info_tracker,
+                                render_context,
                                 is_in_transclusion,
                             ));
                             last_was_paragraph = true;
@@ -2914,6 +7107,7 @@ fn render_single_block(
                             notes_map,
                             id_to_path,
                             margin_info_tracker,
+                            render_context,
                             is_in_transclusion,
                         ));
                         last_was_paragraph = false;
@@ -2939,6 +7133,7 @@ fn render_single_block(
                 notes_map,
                 id_to_path,
                 margin_info_tracker,
+                render_context,
                 is_in_transclusion,
             ));
             html.push_str("</blockquote>\n");
@@ -2963,6 +7158,7 @@ fn render_single_block(
                 notes_map,
                 id_to_path,
                 margin_info_tracker,
+                render_context,
                 is_in_transclusion,
             ));
             html.push_str("</table>\n");
@@ -2979,6 +7175,7 @@ fn render_single_block(
                 notes_map,
                 id_to_path,
                 margin_info_tracker,
+                render_context,
                 is_in_transclusion,
             ));
             html.push_str("</thead>\n");
@@ -2995,6 +7192,7 @@ fn render_single_block(
                 notes_map,
                 id_to_path,
                 margin_info_tracker,
+                render_context,
                 is_in_transclusion,
             ));
             html.push_str("</tr>\n");
@@ -3012,6 +7210,7 @@ fn render_single_block(
                     notes_map,
                     id_to_path,
                     margin_info_tracker,
+                    render_context,
                     is_in_transclusion,
                 ));
                 html.push_str("</th>\n");
@@ -3022,6 +7221,7 @@ fn render_single_block(
                     notes_map,
                     id_to_path,
                     margin_info_tracker,
+                    render_context,
                     is_in_transclusion,
                 ));
                 html.push_str("</td>\n");
@@ -3061,17 +7261,23 @@ fn render_single_block(
         "NodeText" => {
             // For text nodes, we generally don't add IDs as they're inline elements,
             // but we can wrap them in a span with an ID if needed
+            let text = virtual_ref_text(&block.Data, render_context);
             if !block.ID.is_empty() {
                 html.push_str(&format!("<span id=\"{}\">", block.ID));
-                html.push_str(&escape_html(&block.Data));
+                html.push_str(&text);
                 html.push_str("</span>");
             } else {
-                html.push_str(&escape_html(&block.Data));
+                html.push_str(&text);
             }
         }
         "NodeTextMark" => {
             // Update this line to pass all required arguments
-            html.push_str(&render_text_mark(block, notes_map, id_to_path));
+            html.push_str(&render_text_mark(
+                block,
+                notes_map,
+                id_to_path,
+                render_context,
+            ));
         }
         "NodeImage" => {
             // Handle image nodes
@@ -3089,7 +7295,7 @@ fn render_single_block(
             // Find the link destination in children
             for child in &block.Children {
                 if child.Type == "NodeLinkDest" {
-                    image_src = child.Data.clone();
+                    image_src = render_context.urls.asset_url(&child.Data);
                 } else if child.Type == "NodeLinkText" {
                     alt_text = child.Data.clone();
                 } else if child.Type == "NodeLinkTitle" {
@@ -3130,9 +7336,12 @@ fn render_single_block(
                     html.push_str(&format!("<div{}{}>", wrapper_id, parent_style_attr));
 
                     // In this case, don't add the ID to the img tag since it's on the wrapper
-                    html.push_str(&format!(
-                        "<img src=\"{}\" alt=\"{}\"{}/>",
-                        image_src, alt_text, style_attr
+                    html.push_str(&render_image_tag(
+                        "",
+                        &image_src,
+                        &alt_text,
+                        &style_attr,
+                        render_context,
                     ));
 
                     // Close the parent div
@@ -3141,9 +7350,12 @@ fn render_single_block(
                     // No wrapper, add ID directly to the img tag if no caption
                     // (if we have a caption, the ID is already on the figure element)
                     let img_id_attr = if has_caption { "" } else { &id_attr };
-                    html.push_str(&format!(
-                        "<img{} src=\"{}\" alt=\"{}\"{}/>",
-                        img_id_attr, image_src, alt_text, style_attr
+                    html.push_str(&render_image_tag(
+                        img_id_attr,
+                        &image_src,
+                        &alt_text,
+                        &style_attr,
+                        render_context,
                     ));
                 }
 
@@ -3192,7 +7404,7 @@ fn render_single_block(
 
                         // Add source link button
                         let source_url = if notes_map.contains_key(content_id) {
-                            format!("{}.html", content_id) // Link to the note
+                            render_context.urls.note_url(content_id) // Link to the note
                         } else {
                             // For block IDs, try to find which note contains it
                             let mut source_note_id = String::new();
@@ -3202,7 +7414,11 @@ fn render_single_block(
                                     break;
                                 }
                             }
-                            format!("{}.html#{}", source_note_id, content_id) // Link to the note with block ID anchor
+                            format!(
+                                "{}#{}",
+                                render_context.urls.note_url(&source_note_id),
+                                content_id
+                            ) // Link to the note with block ID anchor
                         };
 
                         html.push_str(&format!(
@@ -3210,46 +7426,35 @@ fn render_single_block(
                             source_url
                         ));
 
-                        // Check if this is a block ID or a note ID
-                        let mut found = false;
-
-                        // First try to find the specific block by ID
-                        for note in notes_map.values() {
-                            if let Some(found_block) = find_block_by_id(content_id, &note.Children)
-                            {
-                                html.push_str(&render_block(
-                                    found_block,
-                                    notes_map,
-                                    id_to_path,
-                                    margin_info_tracker,
-                                    true, // This is inside a transclusion
-                                ));
-                                found = true;
-                                break;
-                            }
-                        }
-
-                        // If not found as a block, check if it's a note ID
-                        if !found {
-                            if let Some(note) = notes_map.get(content_id) {
-                                // Render all blocks from the note
+                        if let Some(placeholder) = render_context.check_transclusion(content_id) {
+                            html.push_str(&placeholder);
+                        } else {
+                            render_context
+                                .transclusion_stack
+                                .borrow_mut()
+                                .push(content_id.to_string());
+                            // A note ID embeds the whole note, a heading ID embeds its section
+                            if let Some((_, section)) = resolve_ref_target(
+                                content_id,
+                                notes_map,
+                                render_context.block_index,
+                            ) {
                                 html.push_str(&render_blocks(
-                                    &note.Children,
+                                    section,
                                     notes_map,
                                     id_to_path,
                                     margin_info_tracker,
+                                    render_context,
                                     true, // This is inside a transclusion
                                 ));
-                                found = true;
+                            } else {
+                                html.push_str(&format!(
+                                    "<p><em>Transcluded content not found: {}</em></p>",
+                                    content_id
+                                ));
+                                // No need to remove the source link with CSS-based approach
                             }
-                        }
-
-                        if !found {
-                            html.push_str(&format!(
-                                "<p><em>Transcluded content not found: {}</em></p>",
-                                content_id
-                            ));
-                            // No need to remove the source link with CSS-based approach
+                            render_context.transclusion_stack.borrow_mut().pop();
                         }
 
                         html.push_str("</div>");
@@ -3262,6 +7467,7 @@ fn render_single_block(
                     notes_map,
                     id_to_path,
                     margin_info_tracker,
+                    render_context,
                     is_in_transclusion,
                 ));
             }
@@ -3273,6 +7479,7 @@ fn render_single_block(
                 notes_map,
                 id_to_path,
                 margin_info_tracker,
+                render_context,
                 is_in_transclusion,
             ));
         }
@@ -3281,10 +7488,75 @@ fn render_single_block(
     html
 }
 
+// Builds the <img> tag with dimensions, srcset and lazy loading, wrapped in a <picture>
+// when WebP variants were generated
+fn render_image_tag(
+    id_attr: &str,
+    image_src: &str,
+    alt_text: &str,
+    style_attr: &str,
+    render_context: &RenderContext,
+) -> String {
+    let images_config = &render_context.config.images;
+    let mut attrs = String::new();
+    let mut webp_srcset = String::new();
+
+    let image = asset_path_from_reference(image_src)
+        .and_then(|asset| render_context.image_catalog.get(&asset));
+
+    if let Some(image) = image {
+        attrs.push_str(&format!(
+            " width=\"{}\" height=\"{}\"",
+            image.width, image.height
+        ));
+
+        // Variants live next to the original, so they share its URL prefix
+        let prefix_end = image_src.find("assets/").unwrap_or(0) + "assets/".len();
+        let prefix = &image_src[..prefix_end];
+        let srcset_entries = |variants: &[(u32, String)]| {
+            variants
+                .iter()
+                .map(|(width, variant)| format!("{}{} {}w", prefix, variant, width))
+                .collect::<Vec<_>>()
+        };
+
+        if !image.variants.is_empty() {
+            let mut srcset = srcset_entries(&image.variants);
+            srcset.push(format!("{} {}w", image_src, image.width));
+            attrs.push_str(&format!(
+                " srcset=\"{}\" sizes=\"{}\"",
+                srcset.join(", "),
+                images_config.sizes
+            ));
+        }
+
+        webp_srcset = srcset_entries(&image.webp_variants).join(", ");
+    }
+
+    if images_config.lazy_loading {
+        attrs.push_str(" loading=\"lazy\" decoding=\"async\"");
+    }
+
+    let img_tag = format!(
+        "<img{} src=\"{}\" alt=\"{}\"{}{}/>",
+        id_attr, image_src, alt_text, attrs, style_attr
+    );
+
+    if webp_srcset.is_empty() {
+        img_tag
+    } else {
+        format!(
+            "<picture><source type=\"image/webp\" srcset=\"{}\" sizes=\"{}\">{}</picture>",
+            webp_srcset, images_config.sizes, img_tag
+        )
+    }
+}
+
 fn render_text_mark(
     block: &Block,
     notes_map: &HashMap<String, Note>,
     id_to_path: &HashMap<String, PathBuf>,
+    render_context: &RenderContext,
 ) -> String {
     let mut html = String::new();
     let id_attr = if !block.ID.is_empty() {
@@ -3297,7 +7569,9 @@ fn render_text_mark(
         "a" => {
             html.push_str(&format!(
                 "<a{} href=\"{}\" target=\"_blank\" class=\"link\">{}",
-                id_attr, block.TextMarkAHref, block.TextMarkTextContent
+                id_attr,
+                render_context.urls.link_href(&block.TextMarkAHref),
+                block.TextMarkTextContent
             ));
             html.push_str("</a>");
         }
@@ -3311,7 +7585,7 @@ fn render_text_mark(
         }
         "strong" | "strong text" => {
             // Handle both "strong" and "strong text" the same way
-            let content = escape_html(&block.TextMarkTextContent);
+            let content = virtual_ref_text(&block.TextMarkTextContent, render_context);
 
             // Check if there are style properties for special highlights
             if !block.Properties.style.is_empty() {
@@ -3349,7 +7623,7 @@ fn render_text_mark(
                 html.push_str(&format!(
                     "<a{} href=\"{}\" target=\"_blank\" class=\"link\"><em>{}",
                     id_attr,
-                    block.TextMarkAHref,
+                    render_context.urls.link_href(&block.TextMarkAHref),
                     escape_html(&block.TextMarkTextContent)
                 ));
                 html.push_str("</em></a>");
@@ -3357,7 +7631,7 @@ fn render_text_mark(
                 html.push_str(&format!(
                     "<em{}>{}",
                     id_attr,
-                    escape_html(&block.TextMarkTextContent)
+                    virtual_ref_text(&block.TextMarkTextContent, render_context)
                 ));
                 html.push_str("</em>");
             }
@@ -3366,7 +7640,7 @@ fn render_text_mark(
             html.push_str(&format!(
                 "<u{}>{}",
                 id_attr,
-                escape_html(&block.TextMarkTextContent)
+                virtual_ref_text(&block.TextMarkTextContent, render_context)
             ));
             html.push_str("</u>");
         }
@@ -3374,7 +7648,7 @@ fn render_text_mark(
             html.push_str(&format!(
                 "<s{}>{}",
                 id_attr,
-                escape_html(&block.TextMarkTextContent)
+                virtual_ref_text(&block.TextMarkTextContent, render_context)
             ));
             html.push_str("</s>");
         }
@@ -3386,21 +7660,18 @@ fn render_text_mark(
             };
 
             if additional_format == "block-ref" {
-                if notes_map.contains_key(&block.TextMarkBlockRefID) {
-                    let ref_note = &notes_map[&block.TextMarkBlockRefID];
-                    let title = if !block.TextMarkTextContent.is_empty() {
-                        block.TextMarkTextContent.clone()
-                    } else if !ref_note.Properties.title.is_empty() {
-                        ref_note.Properties.title.clone()
-                    } else {
-                        block.TextMarkBlockRefID.clone()
-                    };
+                if let Some((ref_note, section)) = resolve_ref_target(
+                    &block.TextMarkBlockRefID,
+                    notes_map,
+                    render_context.block_index,
+                ) {
+                    let title = block_ref_anchor_text(block, ref_note, section);
 
                     // Create tooltip HTML
                     html.push_str(&format!("<span{} class=\"tooltip\">", id_attr));
                     html.push_str(&format!(
-                        "<a href=\"{}.html\"><sub>{}",
-                        block.TextMarkBlockRefID,
+                        "<a href=\"{}\"><sub>{}",
+                        ref_target_href(render_context.urls, ref_note, &block.TextMarkBlockRefID),
                         escape_html(&title)
                     ));
                     html.push_str("</sub></a>");
@@ -3413,38 +7684,11 @@ fn render_text_mark(
                     ));
 
                     // Extract excerpt for tooltip
-                    let mut excerpt = String::new();
-                    let mut paragraph_count = 0;
-                    for child in &ref_note.Children {
-                        if child.Type == "NodeParagraph" {
-                            for grandchild in &child.Children {
-                                if grandchild.Type == "NodeText" {
-                                    excerpt.push_str(&escape_html(&grandchild.Data));
-                                    excerpt.push(' ');
-                                }
-                            }
-
-                            paragraph_count += 1;
-                            if paragraph_count >= 2 {
-                                break;
-                            }
-                        }
-                    }
-
-                    // Limit excerpt length
-                    if excerpt.len() > 300 {
-                        let mut end_index = 0;
-                        let mut char_count = 0;
-                        for (idx, _) in excerpt.char_indices() {
-                            end_index = idx;
-                            char_count += 1;
-                            if char_count >= 300 {
-                                break;
-                            }
-                        }
-                        excerpt = excerpt[0..end_index].to_string();
-                        excerpt.push_str("...");
-                    }
+                    let excerpt = escape_html(&smart_truncate_excerpt(
+                        &ref_excerpt_text(section),
+                        usize::MAX,
+                        300,
+                    ));
 
                     html.push_str(&format!(
                         "<span class=\"tooltip-excerpt\">{}</span>",
@@ -3464,7 +7708,7 @@ fn render_text_mark(
                 html.push_str(&format!(
                     "<a{} href=\"{}\" target=\"_blank\" class=\"link\"><sub>{}",
                     id_attr,
-                    block.TextMarkAHref,
+                    render_context.urls.link_href(&block.TextMarkAHref),
                     escape_html(&block.TextMarkTextContent)
                 ));
                 html.push_str("</sub></a>");
@@ -3505,10 +7749,12 @@ fn render_text_mark(
                 html.push_str("</sub></mark>");
             } else if additional_format == "tag" {
                 html.push_str(&format!(
-                    "<a{} href=\"tag_{}.html\" class=\"tag\"><sub>{}",
+                    "<a{} href=\"{}\" class=\"tag\"><sub>{}",
                     id_attr,
-                    block.TextMarkTextContent.replace(" ", "_"),
-                    block.TextMarkTextContent
+                    render_context
+                        .urls
+                        .tag_url(&inline_tag_name(&block.TextMarkTextContent)),
+                    escape_html(&block.TextMarkTextContent)
                 ));
                 html.push_str("</sub></a>");
             } else {
@@ -3529,21 +7775,22 @@ fn render_text_mark(
 
             match additional_format {
                 "block-ref" => {
-                    if notes_map.contains_key(&block.TextMarkBlockRefID) {
-                        let ref_note = &notes_map[&block.TextMarkBlockRefID];
-                        let title = if !block.TextMarkTextContent.is_empty() {
-                            block.TextMarkTextContent.clone()
-                        } else if !ref_note.Properties.title.is_empty() {
-                            ref_note.Properties.title.clone()
-                        } else {
-                            block.TextMarkBlockRefID.clone()
-                        };
+                    if let Some((ref_note, section)) = resolve_ref_target(
+                        &block.TextMarkBlockRefID,
+                        notes_map,
+                        render_context.block_index,
+                    ) {
+                        let title = block_ref_anchor_text(block, ref_note, section);
 
                         // Create tooltip HTML
                         html.push_str(&format!("<span{} class=\"tooltip\">", id_attr));
                         html.push_str(&format!(
-                            "<a href=\"{}.html\"><sup>{}",
-                            block.TextMarkBlockRefID,
+                            "<a href=\"{}\"><sup>{}",
+                            ref_target_href(
+                                render_context.urls,
+                                ref_note,
+                                &block.TextMarkBlockRefID
+                            ),
                             escape_html(&title)
                         ));
                         html.push_str("</sup></a>");
@@ -3556,38 +7803,11 @@ fn render_text_mark(
                         ));
 
                         // Extract excerpt for tooltip
-                        let mut excerpt = String::new();
-                        let mut paragraph_count = 0;
-                        for child in &ref_note.Children {
-                            if child.Type == "NodeParagraph" {
-                                for grandchild in &child.Children {
-                                    if grandchild.Type == "NodeText" {
-                                        excerpt.push_str(&escape_html(&grandchild.Data));
-                                        excerpt.push(' ');
-                                    }
-                                }
-
-                                paragraph_count += 1;
-                                if paragraph_count >= 2 {
-                                    break;
-                                }
-                            }
-                        }
-
-                        // Limit excerpt length
-                        if excerpt.len() > 300 {
-                            let mut end_index = 0;
-                            let mut char_count = 0;
-                            for (idx, _) in excerpt.char_indices() {
-                                end_index = idx;
-                                char_count += 1;
-                                if char_count >= 300 {
-                                    break;
-                                }
-                            }
-                            excerpt = excerpt[0..end_index].to_string();
-                            excerpt.push_str("...");
-                        }
+                        let excerpt = escape_html(&smart_truncate_excerpt(
+                            &ref_excerpt_text(section),
+                            usize::MAX,
+                            300,
+                        ));
 
                         html.push_str(&format!(
                             "<span class=\"tooltip-excerpt\">{}</span>",
@@ -3608,7 +7828,7 @@ fn render_text_mark(
                     html.push_str(&format!(
                         "<a{} href=\"{}\" target=\"_blank\" class=\"link\"><sup>{}",
                         id_attr,
-                        block.TextMarkAHref,
+                        render_context.urls.link_href(&block.TextMarkAHref),
                         escape_html(&block.TextMarkTextContent)
                     ));
                     html.push_str("</sup></a>");
@@ -3655,10 +7875,12 @@ fn render_text_mark(
                 }
                 "tag" => {
                     html.push_str(&format!(
-                        "<a{} href=\"tag_{}.html\" class=\"tag\"><sup>{}",
+                        "<a{} href=\"{}\" class=\"tag\"><sup>{}",
                         id_attr,
-                        block.TextMarkTextContent.replace(" ", "_"),
-                        block.TextMarkTextContent
+                        render_context
+                            .urls
+                            .tag_url(&inline_tag_name(&block.TextMarkTextContent)),
+                        escape_html(&block.TextMarkTextContent)
                     ));
                     html.push_str("</sup></a>");
                 }
@@ -3689,21 +7911,22 @@ fn render_text_mark(
 
             match additional_format {
                 "block-ref" => {
-                    if notes_map.contains_key(&block.TextMarkBlockRefID) {
-                        let ref_note = &notes_map[&block.TextMarkBlockRefID];
-                        let title = if !block.TextMarkTextContent.is_empty() {
-                            block.TextMarkTextContent.clone()
-                        } else if !ref_note.Properties.title.is_empty() {
-                            ref_note.Properties.title.clone()
-                        } else {
-                            block.TextMarkBlockRefID.clone()
-                        };
+                    if let Some((ref_note, section)) = resolve_ref_target(
+                        &block.TextMarkBlockRefID,
+                        notes_map,
+                        render_context.block_index,
+                    ) {
+                        let title = block_ref_anchor_text(block, ref_note, section);
 
                         // Create tooltip HTML
                         html.push_str(&format!("<span{} class=\"tooltip\">", id_attr));
                         html.push_str(&format!(
-                            "<a href=\"{}.html\"><sup>{}",
-                            block.TextMarkBlockRefID,
+                            "<a href=\"{}\"><sup>{}",
+                            ref_target_href(
+                                render_context.urls,
+                                ref_note,
+                                &block.TextMarkBlockRefID
+                            ),
                             escape_html(&title)
                         ));
                         html.push_str("</sup></a>");
@@ -3716,38 +7939,11 @@ fn render_text_mark(
                         ));
 
                         // Extract excerpt for tooltip
-                        let mut excerpt = String::new();
-                        let mut paragraph_count = 0;
-                        for child in &ref_note.Children {
-                            if child.Type == "NodeParagraph" {
-                                for grandchild in &child.Children {
-                                    if grandchild.Type == "NodeText" {
-                                        excerpt.push_str(&escape_html(&grandchild.Data));
-                                        excerpt.push(' ');
-                                    }
-                                }
-
-                                paragraph_count += 1;
-                                if paragraph_count >= 2 {
-                                    break;
-                                }
-                            }
-                        }
-
-                        // Limit excerpt length
-                        if excerpt.len() > 300 {
-                            let mut end_index = 0;
-                            let mut char_count = 0;
-                            for (idx, _) in excerpt.char_indices() {
-                                end_index = idx;
-                                char_count += 1;
-                                if char_count >= 300 {
-                                    break;
-                                }
-                            }
-                            excerpt = excerpt[0..end_index].to_string();
-                            excerpt.push_str("...");
-                        }
+                        let excerpt = escape_html(&smart_truncate_excerpt(
+                            &ref_excerpt_text(section),
+                            usize::MAX,
+                            300,
+                        ));
 
                         html.push_str(&format!(
                             "<span class=\"tooltip-excerpt\">{}</span>",
@@ -3768,7 +7964,7 @@ fn render_text_mark(
                     html.push_str(&format!(
                         "<a{} href=\"{}\" target=\"_blank\" class=\"link\"><sup>{}",
                         id_attr,
-                        block.TextMarkAHref,
+                        render_context.urls.link_href(&block.TextMarkAHref),
                         escape_html(&block.TextMarkTextContent)
                     ));
                     html.push_str("</sup></a>");
@@ -3815,10 +8011,12 @@ fn render_text_mark(
                 }
                 "tag" => {
                     html.push_str(&format!(
-                        "<a{} href=\"tag_{}.html\" class=\"tag\"><sup>{}",
+                        "<a{} href=\"{}\" class=\"tag\"><sup>{}",
                         id_attr,
-                        block.TextMarkTextContent.replace(" ", "_"),
-                        block.TextMarkTextContent
+                        render_context
+                            .urls
+                            .tag_url(&inline_tag_name(&block.TextMarkTextContent)),
+                        escape_html(&block.TextMarkTextContent)
                     ));
                     html.push_str("</sup></a>");
                 }
@@ -3852,14 +8050,14 @@ fn render_text_mark(
             html.push_str(&format!(
                 "<mark{}>{}",
                 id_attr,
-                escape_html(&block.TextMarkTextContent)
+                virtual_ref_text(&block.TextMarkTextContent, render_context)
             ));
             html.push_str("</mark>");
         }
         "text" | "text strong" => {
             // Check if there are style properties for special highlights
             if !block.Properties.style.is_empty() {
-                let content = escape_html(&block.TextMarkTextContent);
+                let content = virtual_ref_text(&block.TextMarkTextContent, render_context);
                 let tag_open = if block.TextMarkType == "text strong" {
                     "<strong"
                 } else {
@@ -3899,15 +8097,20 @@ fn render_text_mark(
                     ));
                 }
             } else {
-                html.push_str(&escape_html(&block.TextMarkTextContent));
+                html.push_str(&virtual_ref_text(
+                    &block.TextMarkTextContent,
+                    render_context,
+                ));
             }
         }
         "tag" => {
             html.push_str(&format!(
-                "<a{} href=\"tag_{}.html\" class=\"tag\">{}",
+                "<a{} href=\"{}\" class=\"tag\">{}",
                 id_attr,
-                block.TextMarkTextContent.replace(" ", "_"),
-                block.TextMarkTextContent
+                render_context
+                    .urls
+                    .tag_url(&inline_tag_name(&block.TextMarkTextContent)),
+                escape_html(&block.TextMarkTextContent)
             ));
             html.push_str("</a>");
         }
@@ -3941,48 +8144,22 @@ fn render_text_mark(
             html.push_str("<i></i></span></span>");
         }
         "block-ref" => {
-            if notes_map.contains_key(&block.TextMarkBlockRefID) {
-                let ref_note = &notes_map[&block.TextMarkBlockRefID];
-                let title = if !block.TextMarkTextContent.is_empty() {
-                    block.TextMarkTextContent.clone()
-                } else if !ref_note.Properties.title.is_empty() {
-                    ref_note.Properties.title.clone()
-                } else {
-                    block.TextMarkBlockRefID.clone()
-                };
-
-                // Extract first few paragraphs for excerpt
-                let mut excerpt = String::new();
-                let mut paragraph_count = 0;
-                for child in &ref_note.Children {
-                    if child.Type == "NodeParagraph" {
-                        if paragraph_count > 0 {
-                            // Add line break between paragraphs, not after
-                            excerpt.push_str("<br>");
-                        }
-
-                        for grandchild in &child.Children {
-                            if grandchild.Type == "NodeText" {
-                                excerpt.push_str(&escape_html(&grandchild.Data));
-                                excerpt.push(' ');
-                            }
-                        }
-
-                        paragraph_count += 1;
-                        if paragraph_count >= 2 {
-                            break;
-                        }
-                    }
-                }
+            if let Some((ref_note, section)) = resolve_ref_target(
+                &block.TextMarkBlockRefID,
+                notes_map,
+                render_context.block_index,
+            ) {
+                let title = block_ref_anchor_text(block, ref_note, section);
 
                 // Smart truncation - limit to 1 sentence for shorter previews
-                excerpt = smart_truncate_excerpt(&excerpt, 1);
+                let excerpt =
+                    escape_html(&smart_truncate_excerpt(&ref_excerpt_text(section), 1, 200));
 
                 // Create tooltip HTML
                 html.push_str(&format!("<span{} class=\"tooltip\">", id_attr));
                 html.push_str(&format!(
-                    "<a href=\"{}.html\">{}",
-                    block.TextMarkBlockRefID,
+                    "<a href=\"{}\">{}",
+                    ref_target_href(render_context.urls, ref_note, &block.TextMarkBlockRefID),
                     escape_html(&title)
                 ));
                 html.push_str("</a>");
@@ -4067,54 +8244,422 @@ fn comment_processor(html: &str) -> String {
             _ => {}
         }
 
-        if !in_tag && !in_comment && !in_script && !in_style {
-            if c == '\n' || c == ' ' || c == '>' {
-                let position_ratio = i as f64 / html.len() as f64;
-                if depth >= 3 && position_ratio > 0.2 && position_ratio < 0.8 {
-                    insertion_points.push((i, depth));
-                    total_points += 1;
+        if !in_tag && !in_comment && !in_script && !in_style {
+            if c == '\n' || c == ' ' || c == '>' {
+                let position_ratio = i as f64 / html.len() as f64;
+                if depth >= 3 && position_ratio > 0.2 && position_ratio < 0.8 {
+                    insertion_points.push((i, depth));
+                    total_points += 1;
+                }
+            }
+        }
+    }
+
+    if total_points > 0 {
+        let mid_section: Vec<(usize, usize)> = insertion_points
+            .iter()
+            .filter(|(pos, d)| {
+                let pos_ratio = *pos as f64 / html.len() as f64;
+                *d >= 3 && pos_ratio >= 0.2 && pos_ratio <= 0.8
+            })
+            .cloned()
+            .collect();
+
+        let weighted_points: Vec<(usize, usize)> = mid_section
+            .iter()
+            .flat_map(|(pos, d)| {
+                let weight = d * d; // Square the depth to increase probability for deeper points
+                std::iter::repeat((*pos, *d)).take(weight)
+            })
+            .collect();
+
+        if !weighted_points.is_empty() {
+            let seed = html.len() + html.chars().fold(0, |acc, c| acc + c as usize);
+            let index_pos = seed % weighted_points.len();
+            let (insertion_point, _) = weighted_points[index_pos];
+
+            let mut result = String::with_capacity(html.len() + COMMENT.len());
+            result.push_str(&html[..=insertion_point]);
+            result.push_str(COMMENT);
+            result.push_str(&html[insertion_point + 1..]);
+            return result;
+        }
+    }
+
+    let middle_point = html.len() / 2;
+    let mut result = String::with_capacity(html.len() + COMMENT.len());
+    result.push_str(&html[..middle_point]);
+    result.push_str(COMMENT);
+    result.push_str(&html[middle_point..]);
+    result
+}
+
+// Collects the IDs of a note's blocks, nested blocks included
+fn collect_block_ids<'a>(blocks: &'a [Block], ids: &mut Vec<&'a str>) {
+    for block in blocks {
+        if !block.ID.is_empty() {
+            ids.push(&block.ID);
+        }
+        collect_block_ids(&block.Children, ids);
+    }
+}
+
+// Groups (source note, context block) pairs by source note, sorted by the source's title,
+// leaving out the note itself and notes that aren't published
+fn group_reference_contexts<'a>(
+    note: &Note,
+    notes_map: &'a HashMap<String, Note>,
+    contexts: impl Iterator<Item = &'a (String, String)>,
+) -> Vec<(&'a str, Vec<&'a str>)> {
+    let mut groups: HashMap<&str, Vec<&str>> = HashMap::new();
+    for (source_id, context_id) in contexts {
+        if source_id == &note.ID || !notes_map.contains_key(source_id) {
+            continue;
+        }
+        let group = groups.entry(source_id.as_str()).or_default();
+        if !group.contains(&context_id.as_str()) {
+            group.push(context_id);
+        }
+    }
+
+    let mut groups: Vec<_> = groups.into_iter().collect();
+    groups.sort_by_key(|(source_id, _)| {
+        (
+            notes_map[*source_id].Properties.title.to_lowercase(),
+            *source_id,
+        )
+    });
+    groups
+}
+
+// Context blocks of every block reference to the note or one of its blocks
+fn linked_reference_contexts<'a>(
+    note: &Note,
+    margin_info_tracker: &'a MarginInfoTracker,
+) -> Vec<&'a (String, String)> {
+    let mut ids = vec![note.ID.as_str()];
+    collect_block_ids(&note.Children, &mut ids);
+
+    ids.into_iter()
+        .filter_map(|id| margin_info_tracker.reference_contexts.get(id))
+        .flatten()
+        .collect()
+}
+
+// Renders reference groups as a section with a linked heading per source note and the
+// context blocks below it
+#[allow(clippy::too_many_arguments)]
+fn render_reference_section(
+    heading: &str,
+    class: &str,
+    groups: Vec<(&str, Vec<&str>)>,
+    note: &Note,
+    notes_map: &HashMap<String, Note>,
+    id_to_path: &HashMap<String, PathBuf>,
+    margin_info_tracker: &MarginInfoTracker,
+    render_context: &RenderContext,
+) -> String {
+    if groups.is_empty() {
+        return String::new();
+    }
+
+    let reference_count: usize = groups.iter().map(|(_, contexts)| contexts.len()).sum();
+    let mut html = format!(
+        "<section class=\"{}\">\n<h2>{} <span class=\"backlink-count\">{}</span></h2>\n",
+        class, heading, reference_count
+    );
+
+    // Embeds inside the context blocks are checked against this page like its own content
+    render_context
+        .transclusion_stack
+        .borrow_mut()
+        .push(note.ID.clone());
+    for (source_id, contexts) in groups {
+        let source = &notes_map[source_id];
+        let title = if source.Properties.title.is_empty() {
+            &source.ID
+        } else {
+            &source.Properties.title
+        };
+        html.push_str(&format!(
+            "<div class=\"backlink-group\">\n<h3><a href=\"{}\">{}</a></h3>\n",
+            render_context.urls.note_url(source_id),
+            escape_html(title)
+        ));
+
+        for context_id in contexts {
+            if let Some(block) = find_block_by_id(context_id, &source.Children) {
+                html.push_str(&format!(
+                    "<div class=\"backlink-context\">{}</div>\n",
+                    render_blocks(
+                        std::slice::from_ref(block),
+                        notes_map,
+                        id_to_path,
+                        margin_info_tracker,
+                        render_context,
+                        true,
+                    )
+                ));
+            }
+        }
+        html.push_str("</div>\n");
+    }
+    render_context.transclusion_stack.borrow_mut().pop();
+
+    html.push_str("</section>");
+    html
+}
+
+// The "Linked references" section for the {{backlinks}} template variable: every paragraph,
+// heading or table that references the note or one of its blocks, grouped by source note
+fn generate_backlinks_html(
+    note: &Note,
+    notes_map: &HashMap<String, Note>,
+    id_to_path: &HashMap<String, PathBuf>,
+    margin_info_tracker: &MarginInfoTracker,
+    render_context: &RenderContext,
+) -> String {
+    let contexts = linked_reference_contexts(note, margin_info_tracker);
+
+    render_reference_section(
+        "Linked references",
+        "backlinks",
+        group_reference_contexts(note, notes_map, contexts.into_iter()),
+        note,
+        notes_map,
+        id_to_path,
+        margin_info_tracker,
+        render_context,
+    )
+}
+
+// The "Unlinked mentions" section for the {{unlinked_mentions}} template variable: blocks that
+// mention the note's title or aliases as plain text, unless they already reference the note
+fn generate_unlinked_mentions_html(
+    note: &Note,
+    notes_map: &HashMap<String, Note>,
+    id_to_path: &HashMap<String, PathBuf>,
+    margin_info_tracker: &MarginInfoTracker,
+    render_context: &RenderContext,
+) -> String {
+    let linked: HashSet<_> = linked_reference_contexts(note, margin_info_tracker)
+        .into_iter()
+        .collect();
+
+    let mentions = margin_info_tracker
+        .unlinked_mentions
+        .get(&note.ID)
+        .into_iter()
+        .flatten()
+        .filter(|context| !linked.contains(context));
+
+    render_reference_section(
+        "Unlinked mentions",
+        "backlinks unlinked-mentions",
+        group_reference_contexts(note, notes_map, mentions),
+        note,
+        notes_map,
+        id_to_path,
+        margin_info_tracker,
+        render_context,
+    )
+}
+
+// The {{local_graph}} section: the notes within the configured number of hops of `note`, drawn
+// as an SVG with the note in the middle and each hop on a ring around it. The same nodes and
+// links are inlined as JSON, or written to local-graph/<note-id>.json, for themes that draw
+// the graph themselves.
+fn generate_local_graph_html(
+    note: &Note,
+    notes_map: &HashMap<String, Note>,
+    output_dir: &Path,
+    render_context: &RenderContext,
+) -> std::io::Result<String> {
+    let Some(graph) = render_context.link_graph else {
+        return Ok(String::new());
+    };
+    let config = &render_context.config.local_graph;
+    let title_of = |id: &str| {
+        let note = &notes_map[id];
+        if note.Properties.title.is_empty() {
+            id.to_string()
+        } else {
+            note.Properties.title.clone()
+        }
+    };
+    let neighbors_of = |id: &str| {
+        graph
+            .neighbors
+            .get(id)
+            .into_iter()
+            .flatten()
+            .filter(|neighbor| notes_map.contains_key(neighbor.as_str()))
+    };
+
+    // Breadth-first from the note; each ring is sorted by title, and the second ring follows
+    // the order of the first so neighbors sit near the note that leads to them
+    let mut rings: Vec<Vec<&str>> = Vec::new();
+    let mut seen: HashSet<&str> = HashSet::from([note.ID.as_str()]);
+    let mut frontier = vec![note.ID.as_str()];
+    let mut node_count = 0;
+    for _ in 0..config.hops.clamp(1, 2) {
+        let mut ring = Vec::new();
+        for id in &frontier {
+            let mut next: Vec<&str> = neighbors_of(id)
+                .map(|neighbor| neighbor.as_str())
+                .filter(|neighbor| !seen.contains(neighbor))
+                .collect();
+            next.sort_by_cached_key(|neighbor| title_of(neighbor).to_lowercase());
+            for neighbor in next {
+                if node_count < config.max_nodes && seen.insert(neighbor) {
+                    ring.push(neighbor);
+                    node_count += 1;
                 }
             }
         }
+        if ring.is_empty() {
+            break;
+        }
+        frontier = ring.clone();
+        rings.push(ring);
+    }
+    if rings.is_empty() {
+        return Ok(String::new());
     }
 
-    if total_points > 0 {
-        let mid_section: Vec<(usize, usize)> = insertion_points
-            .iter()
-            .filter(|(pos, d)| {
-                let pos_ratio = *pos as f64 / html.len() as f64;
-                *d >= 3 && pos_ratio >= 0.2 && pos_ratio <= 0.8
-            })
-            .cloned()
-            .collect();
+    // Node positions in a 300x300 view box
+    let radii: &[f64] = if rings.len() == 1 {
+        &[90.0]
+    } else {
+        &[65.0, 125.0]
+    };
+    let mut positions: HashMap<&str, (f64, f64)> =
+        HashMap::from([(note.ID.as_str(), (150.0, 150.0))]);
+    for (ring, radius) in rings.iter().zip(radii) {
+        for (i, id) in ring.iter().enumerate() {
+            let angle = std::f64::consts::TAU * (i as f64 + 0.5) / ring.len() as f64
+                - std::f64::consts::FRAC_PI_2;
+            let x = (150.0 + radius * angle.cos()).round();
+            let y = (150.0 + radius * angle.sin()).round();
+            positions.insert(id, (x, y));
+        }
+    }
 
-        let weighted_points: Vec<(usize, usize)> = mid_section
-            .iter()
-            .flat_map(|(pos, d)| {
-                let weight = d * d; // Square the depth to increase probability for deeper points
-                std::iter::repeat((*pos, *d)).take(weight)
-            })
-            .collect();
+    let ordered: Vec<(&str, usize)> = std::iter::once((note.ID.as_str(), 0))
+        .chain(
+            rings
+                .iter()
+                .enumerate()
+                .flat_map(|(hop, ring)| ring.iter().map(move |id| (*id, hop + 1))),
+        )
+        .collect();
 
-        if !weighted_points.is_empty() {
-            let seed = html.len() + html.chars().fold(0, |acc, c| acc + c as usize);
-            let index_pos = seed % weighted_points.len();
-            let (insertion_point, _) = weighted_points[index_pos];
+    let mut links = Vec::new();
+    for (source, _) in &ordered {
+        for target in neighbors_of(source) {
+            if !positions.contains_key(target.as_str()) {
+                continue;
+            }
+            if let Some(kind) = graph.link_kind(source, target) {
+                links.push((*source, target.as_str(), kind));
+            }
+        }
+    }
 
-            let mut result = String::with_capacity(html.len() + COMMENT.len());
-            result.push_str(&html[..=insertion_point]);
-            result.push_str(COMMENT);
-            result.push_str(&html[insertion_point + 1..]);
-            return result;
+    let mut svg = String::from(
+        "<svg class=\"local-graph-svg\" viewBox=\"0 0 300 300\" role=\"img\">\n\
+         <defs><marker id=\"local-graph-arrow\" viewBox=\"0 0 10 10\" refX=\"10\" refY=\"5\" \
+         markerWidth=\"6\" markerHeight=\"6\" orient=\"auto\"><path d=\"M0,0L10,5L0,10z\"/>\
+         </marker></defs>\n",
+    );
+    svg.push_str(&format!(
+        "<title>Notes linked to {}</title>\n",
+        escape_html(&title_of(&note.ID))
+    ));
+    for (source, target, kind) in &links {
+        // Stop the line at the edge of the target's circle so the arrow stays visible
+        let (x1, y1) = positions[source];
+        let (x2, y2) = positions[target];
+        let length = ((x2 - x1).powi(2) + (y2 - y1).powi(2)).sqrt().max(1.0);
+        let gap = if *target == note.ID { 9.0 } else { 7.0 };
+        svg.push_str(&format!(
+            "<line class=\"local-graph-link {}\" x1=\"{}\" y1=\"{}\" x2=\"{:.1}\" y2=\"{:.1}\" marker-end=\"url(#local-graph-arrow)\"/>\n",
+            kind,
+            x1,
+            y1,
+            x2 - (x2 - x1) * gap / length,
+            y2 - (y2 - y1) * gap / length
+        ));
+    }
+    for (id, hop) in &ordered {
+        let (x, y) = positions[id];
+        let title = title_of(id);
+        let label = smart_truncate_excerpt(&title, usize::MAX, 24);
+        let node = format!(
+            "<title>{}</title><circle cx=\"{}\" cy=\"{}\" r=\"{}\"/><text x=\"{}\" y=\"{}\" text-anchor=\"middle\">{}</text>",
+            escape_html(&title),
+            x,
+            y,
+            if *hop == 0 { 7 } else { 5 },
+            x,
+            y + 16.0,
+            escape_html(&label)
+        );
+        if *hop == 0 {
+            svg.push_str(&format!(
+                "<g class=\"local-graph-node current\">{}</g>\n",
+                node
+            ));
+        } else {
+            svg.push_str(&format!(
+                "<a href=\"{}\" class=\"local-graph-node hop-{}\">{}</a>\n",
+                render_context.urls.note_url(id),
+                hop,
+                node
+            ));
         }
     }
+    svg.push_str("</svg>\n");
+
+    let data = json!({
+        "nodes": ordered.iter().map(|(id, hop)| {
+            let (x, y) = positions[id];
+            json!({
+                "id": id,
+                "title": title_of(id),
+                "url": render_context.urls.note_url(id),
+                "hops": hop,
+                "x": x,
+                "y": y
+            })
+        }).collect::<Vec<_>>(),
+        "links": links.iter().map(|(source, target, kind)| json!({
+            "source": source,
+            "target": target,
+            "kind": kind
+        })).collect::<Vec<_>>()
+    });
 
-    let middle_point = html.len() / 2;
-    let mut result = String::with_capacity(html.len() + COMMENT.len());
-    result.push_str(&html[..middle_point]);
-    result.push_str(COMMENT);
-    result.push_str(&html[middle_point..]);
-    result
+    let mut html = String::from("<section class=\"local-graph\"");
+    if config.json_files {
+        let data_file = format!("local-graph/{}.json", note.ID);
+        let mut file = create_page_file(&output_dir.join(&data_file))?;
+        file.write_all(data.to_string().as_bytes())?;
+        html.push_str(&format!(
+            " data-src=\"{}\">\n<h2>Local graph</h2>\n{}",
+            render_context.urls.asset_url(&data_file),
+            svg
+        ));
+    } else {
+        html.push_str(&format!(
+            ">\n<h2>Local graph</h2>\n{}<script type=\"application/json\" class=\"local-graph-data\">{}</script>\n",
+            svg,
+            data.to_string().replace("</", "<\\/")
+        ));
+    }
+    html.push_str("</section>\n");
+
+    Ok(html)
 }
 
 fn find_block_by_id<'a>(block_id: &str, blocks: &'a [Block]) -> Option<&'a Block> {
@@ -4135,6 +8680,127 @@ fn find_block_by_id<'a>(block_id: &str, blocks: &'a [Block]) -> Option<&'a Block
     None
 }
 
+// Finds the blocks a reference to `block_id` stands for: a heading together with the siblings
+// that follow it up to the next heading of the same or a higher level, any other block alone
+fn find_section_by_id<'a>(block_id: &str, blocks: &'a [Block]) -> Option<&'a [Block]> {
+    if let Some(index) = blocks.iter().position(|b| b.ID == block_id) {
+        let block = &blocks[index];
+        if block.Type != "NodeHeading" {
+            return Some(std::slice::from_ref(block));
+        }
+
+        let end = blocks[index + 1..]
+            .iter()
+            .position(|b| b.Type == "NodeHeading" && b.HeadingLevel <= block.HeadingLevel)
+            .map_or(blocks.len(), |offset| index + 1 + offset);
+        return Some(&blocks[index..end]);
+    }
+
+    blocks
+        .iter()
+        .find_map(|block| find_section_by_id(block_id, &block.Children))
+}
+
+// Plain text of the first two paragraphs of a referenced note or block, for tooltips
+fn ref_excerpt_text(section: &[Block]) -> String {
+    let mut excerpt = String::new();
+    let mut paragraph_count = 0;
+    for child in section {
+        if child.Type == "NodeParagraph" {
+            for grandchild in &child.Children {
+                if grandchild.Type == "NodeText" {
+                    excerpt.push_str(&grandchild.Data);
+                    excerpt.push(' ');
+                }
+            }
+
+            paragraph_count += 1;
+            if paragraph_count >= 2 {
+                break;
+            }
+        }
+    }
+    excerpt
+}
+
+// Resolves a block reference or embed target to the note that contains it and the blocks it
+// stands for (all of the note's blocks for a note ID, the heading section for a heading)
+fn resolve_ref_target<'a>(
+    ref_id: &str,
+    notes_map: &'a HashMap<String, Note>,
+    block_index: &HashMap<String, String>,
+) -> Option<(&'a Note, &'a [Block])> {
+    let note_id = block_index.get(ref_id)?;
+    let note = notes_map.get(note_id)?;
+    if note_id == ref_id {
+        return Some((note, &note.Children));
+    }
+    find_section_by_id(ref_id, &note.Children).map(|section| (note, section))
+}
+
+// SiYuan's default limit on the length of dynamic anchor text
+const DYNAMIC_ANCHOR_MAX_CHARS: usize = 96;
+
+// Anchor text for a block reference. Dynamic ("d") refs show the target's current note title,
+// heading text or first line of content, so renaming a note doesn't leave stale links behind.
+// Static ("s") refs keep the text typed in the editor
+fn block_ref_anchor_text(block: &Block, ref_note: &Note, section: &[Block]) -> String {
+    let typed_text = &block.TextMarkTextContent;
+
+    let dynamic_text = if block.TextMarkBlockRefSubtype != "d" {
+        String::new()
+    } else if ref_note.ID == block.TextMarkBlockRefID {
+        ref_note.Properties.title.clone()
+    } else if let Some(target) = section.first() {
+        let mut text = String::new();
+        collect_plain_text(target, &mut text);
+        let first_line = text.lines().map(str::trim).find(|line| !line.is_empty());
+        smart_truncate_excerpt(
+            first_line.unwrap_or_default(),
+            usize::MAX,
+            DYNAMIC_ANCHOR_MAX_CHARS,
+        )
+    } else {
+        String::new()
+    };
+
+    if !dynamic_text.is_empty() {
+        dynamic_text
+    } else if !typed_text.is_empty() {
+        typed_text.clone()
+    } else if !ref_note.Properties.title.is_empty() {
+        ref_note.Properties.title.clone()
+    } else {
+        block.TextMarkBlockRefID.clone()
+    }
+}
+
+// Appends the visible text of a block and its children, without any markup
+fn collect_plain_text(block: &Block, text: &mut String) {
+    match block.Type.as_str() {
+        "NodeText" => text.push_str(&block.Data),
+        "NodeTextMark" => text.push_str(&block.TextMarkTextContent),
+        _ => {
+            for child in &block.Children {
+                collect_plain_text(child, text);
+            }
+            // Keep blocks on separate lines so the first line can be picked out
+            if !block.Children.is_empty() && !text.ends_with('\n') {
+                text.push('\n');
+            }
+        }
+    }
+}
+
+// Page URL for a resolved reference target, anchored to the block when it isn't the note itself
+fn ref_target_href(urls: &SiteUrls, note: &Note, ref_id: &str) -> String {
+    if note.ID == ref_id {
+        urls.note_url(&note.ID)
+    } else {
+        format!("{}#{}", urls.note_url(&note.ID), ref_id)
+    }
+}
+
 // This function handles finding a block by ID, or an entire note by ID if the block isn't found
 fn find_content_by_id<'a>(id: &str, notes_map: &'a HashMap<String, Note>) -> Option<&'a [Block]> {
     // First, check if this is a note ID
@@ -4161,6 +8827,7 @@ fn render_block(
     notes_map: &HashMap<String, Note>,
     id_to_path: &HashMap<String, PathBuf>,
     margin_info_tracker: &MarginInfoTracker,
+    render_context: &RenderContext,
     is_in_transclusion: bool,
 ) -> String {
     render_blocks(
@@ -4168,6 +8835,7 @@ fn render_block(
         notes_map,
         id_to_path,
         margin_info_tracker,
+        render_context,
         is_in_transclusion,
     )
 }
@@ -4280,3 +8948,574 @@ fn cleanup_template_variables(html: &str) -> String {
 
     cleaned_html
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXIF_TAG_GPS_IFD: u16 = 0x8825;
+
+    // Little-endian TIFF with one IFD0 holding the given (tag, type, count, value) entries;
+    // values longer than four bytes go after the IFD
+    fn tiff(entries: &[(u16, u16, u32, &[u8])]) -> Vec<u8> {
+        let mut tiff = b"II".to_vec();
+        tiff.extend_from_slice(&42u16.to_le_bytes());
+        tiff.extend_from_slice(&8u32.to_le_bytes());
+        tiff.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+
+        let mut data_offset = 8 + 2 + entries.len() * 12 + 4;
+        let mut data_area = Vec::new();
+        for (tag, value_type, count, value) in entries {
+            tiff.extend_from_slice(&tag.to_le_bytes());
+            tiff.extend_from_slice(&value_type.to_le_bytes());
+            tiff.extend_from_slice(&count.to_le_bytes());
+            if value.len() <= 4 {
+                let mut inline = value.to_vec();
+                inline.resize(4, 0);
+                tiff.extend_from_slice(&inline);
+            } else {
+                tiff.extend_from_slice(&(data_offset as u32).to_le_bytes());
+                data_area.extend_from_slice(value);
+                data_offset += value.len();
+            }
+        }
+        tiff.extend_from_slice(&0u32.to_le_bytes());
+        tiff.extend_from_slice(&data_area);
+        tiff
+    }
+
+    // EXIF with an orientation, a copyright and a pointer to GPS data
+    fn sample_exif() -> Vec<u8> {
+        tiff(&[
+            (EXIF_TAG_ORIENTATION, 3, 1, &6u16.to_le_bytes()),
+            (EXIF_TAG_COPYRIGHT, 2, 10, b"Jane Doe\0\0"),
+            (EXIF_TAG_GPS_IFD, 4, 1, &200u32.to_le_bytes()),
+        ])
+    }
+
+    // IFD0 tags of a TIFF block that filter_exif wrote
+    fn exif_tags(tiff: &[u8]) -> Vec<u16> {
+        let count = u16::from_le_bytes([tiff[8], tiff[9]]) as usize;
+        (0..count)
+            .map(|i| u16::from_le_bytes([tiff[10 + i * 12], tiff[11 + i * 12]]))
+            .collect()
+    }
+
+    fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+        haystack
+            .windows(needle.len())
+            .any(|window| window == needle)
+    }
+
+    #[test]
+    fn filter_exif_keeps_only_requested_tags() {
+        let exif = sample_exif();
+        let filtered = filter_exif(&exif, &[EXIF_TAG_ORIENTATION]).unwrap();
+        assert_eq!(exif_tags(&filtered), vec![EXIF_TAG_ORIENTATION]);
+        assert_eq!(u16::from_le_bytes([filtered[18], filtered[19]]), 6);
+
+        // Out-of-line values are copied along with their entry
+        let filtered = filter_exif(&exif, &[EXIF_TAG_COPYRIGHT]).unwrap();
+        assert_eq!(exif_tags(&filtered), vec![EXIF_TAG_COPYRIGHT]);
+        assert!(contains(&filtered, b"Jane Doe"));
+    }
+
+    #[test]
+    fn filter_exif_drops_blocks_without_kept_tags() {
+        let exif = sample_exif();
+        assert_eq!(filter_exif(&exif, &[]), None);
+        assert_eq!(filter_exif(&exif, &[0x010F]), None);
+        assert_eq!(filter_exif(&exif[..20], &[EXIF_TAG_ORIENTATION]), None);
+        assert_eq!(
+            filter_exif(b"XX*\0\x08\0\0\0", &[EXIF_TAG_ORIENTATION]),
+            None
+        );
+    }
+
+    #[test]
+    fn filter_exif_reads_big_endian() {
+        let mut exif = b"MM\0\x2a\0\0\0\x08\0\x01".to_vec();
+        exif.extend_from_slice(&[0x01, 0x12, 0, 3, 0, 0, 0, 1, 0, 8, 0, 0]);
+        exif.extend_from_slice(&[0, 0, 0, 0]);
+        let filtered = filter_exif(&exif, &[EXIF_TAG_ORIENTATION]).unwrap();
+        assert!(filtered.starts_with(b"MM"));
+        assert_eq!(&filtered[18..20], &[0, 8]);
+    }
+
+    fn jpeg_segment(marker: u8, payload: &[u8]) -> Vec<u8> {
+        let mut segment = vec![0xFF, marker];
+        segment.extend_from_slice(&((payload.len() + 2) as u16).to_be_bytes());
+        segment.extend_from_slice(payload);
+        segment
+    }
+
+    // A phone-style JPEG: EXIF, an MPF index, a comment, then after the main image a second
+    // image with its own EXIF
+    fn sample_jpeg() -> Vec<u8> {
+        let mut exif = b"Exif\0\0".to_vec();
+        exif.extend_from_slice(&sample_exif());
+
+        let mut jpeg = vec![0xFF, 0xD8];
+        jpeg.extend(jpeg_segment(0xE1, &exif));
+        jpeg.extend(jpeg_segment(0xE2, b"MPF\0II*\0"));
+        jpeg.extend(jpeg_segment(0xE2, b"ICC_PROFILE\0\x01\x01"));
+        jpeg.extend(jpeg_segment(0xFE, b"shot on a phone"));
+        jpeg.extend(jpeg_segment(0xDB, &[0; 65]));
+        jpeg.extend(jpeg_segment(0xDA, &[1, 1, 0, 0, 63, 0]));
+        // Entropy-coded data with a stuffed 0xFF and a restart marker
+        jpeg.extend_from_slice(&[0x12, 0xFF, 0x00, 0x34, 0xFF, 0xD0, 0x56]);
+        jpeg.extend_from_slice(&[0xFF, 0xD9]);
+
+        jpeg.extend_from_slice(&[0xFF, 0xD8]);
+        jpeg.extend(jpeg_segment(0xE1, &exif));
+        jpeg.extend_from_slice(&[0xFF, 0xD9]);
+        jpeg
+    }
+
+    #[test]
+    fn strip_jpeg_removes_metadata_and_secondary_images() {
+        let stripped = strip_jpeg_metadata(&sample_jpeg(), &[]).unwrap();
+
+        assert!(stripped.starts_with(&[0xFF, 0xD8]));
+        assert!(stripped.ends_with(&[0x12, 0xFF, 0x00, 0x34, 0xFF, 0xD0, 0x56, 0xFF, 0xD9]));
+        assert!(!contains(&stripped, b"Exif"));
+        assert!(!contains(&stripped, b"MPF"));
+        assert!(!contains(&stripped, b"shot on a phone"));
+        assert!(contains(&stripped, b"ICC_PROFILE"));
+        // Only one image is left
+        assert_eq!(
+            stripped.windows(2).filter(|w| w == &[0xFF, 0xD8]).count(),
+            1
+        );
+    }
+
+    #[test]
+    fn strip_jpeg_rebuilds_kept_exif() {
+        let stripped = strip_jpeg_metadata(&sample_jpeg(), &[EXIF_TAG_ORIENTATION]).unwrap();
+        let exif_start = stripped.windows(6).position(|w| w == b"Exif\0\0").unwrap();
+        assert_eq!(
+            exif_tags(&stripped[exif_start + 6..]),
+            vec![EXIF_TAG_ORIENTATION]
+        );
+        assert_eq!(stripped.windows(6).filter(|w| w == b"Exif\0\0").count(), 1);
+    }
+
+    #[test]
+    fn strip_jpeg_rejects_other_data() {
+        assert_eq!(strip_jpeg_metadata(b"GIF89a", &[]), None);
+        // A segment running past the end of the file
+        assert_eq!(
+            strip_jpeg_metadata(&[0xFF, 0xD8, 0xFF, 0xE1, 0x10, 0x00], &[]),
+            None
+        );
+    }
+
+    fn png_chunk(chunk_type: &[u8], payload: &[u8]) -> Vec<u8> {
+        let mut chunk = Vec::new();
+        write_png_chunk(&mut chunk, chunk_type, payload);
+        chunk
+    }
+
+    fn png_chunk_types(png: &[u8]) -> Vec<String> {
+        let mut types = Vec::new();
+        let mut pos = 8;
+        while pos + 12 <= png.len() {
+            let length = u32::from_be_bytes(png[pos..pos + 4].try_into().unwrap()) as usize;
+            types.push(String::from_utf8_lossy(&png[pos + 4..pos + 8]).to_string());
+            pos += 12 + length;
+        }
+        types
+    }
+
+    fn sample_png() -> Vec<u8> {
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        png.extend(png_chunk(b"IHDR", &[0, 0, 0, 1, 0, 0, 0, 1, 8, 2, 0, 0, 0]));
+        png.extend(png_chunk(b"eXIf", &sample_exif()));
+        png.extend(png_chunk(b"tEXt", b"Comment\0taken at home"));
+        png.extend(png_chunk(b"tEXt", b"Copyright\0Jane Doe"));
+        png.extend(png_chunk(
+            b"iTXt",
+            b"XML:com.adobe.xmp\0\0\0\0\0<x:xmpmeta/>",
+        ));
+        png.extend(png_chunk(b"tIME", &[7, 233, 1, 1, 0, 0, 0]));
+        png.extend(png_chunk(b"IDAT", &[1, 2, 3]));
+        png.extend(png_chunk(b"IEND", &[]));
+        png
+    }
+
+    #[test]
+    fn strip_png_removes_metadata_chunks() {
+        let stripped = strip_png_metadata(&sample_png(), &[], false).unwrap();
+        assert_eq!(png_chunk_types(&stripped), ["IHDR", "IDAT", "IEND"]);
+    }
+
+    #[test]
+    fn strip_png_keeps_copyright_and_kept_exif() {
+        let stripped = strip_png_metadata(&sample_png(), &[EXIF_TAG_ORIENTATION], true).unwrap();
+        assert_eq!(
+            png_chunk_types(&stripped),
+            ["IHDR", "eXIf", "tEXt", "IDAT", "IEND"]
+        );
+        assert!(contains(&stripped, b"Copyright\0Jane Doe"));
+        assert!(!contains(&stripped, b"taken at home"));
+        assert!(!contains(&stripped, b"xmpmeta"));
+
+        // The rebuilt eXIf chunk gets a valid CRC
+        let exif = filter_exif(&sample_exif(), &[EXIF_TAG_ORIENTATION]).unwrap();
+        assert!(contains(&stripped, &png_chunk(b"eXIf", &exif)));
+    }
+
+    fn webp_chunk(fourcc: &[u8], payload: &[u8]) -> Vec<u8> {
+        let mut chunk = fourcc.to_vec();
+        chunk.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        chunk.extend_from_slice(payload);
+        if payload.len() % 2 == 1 {
+            chunk.push(0);
+        }
+        chunk
+    }
+
+    fn sample_webp() -> Vec<u8> {
+        let mut body = b"WEBP".to_vec();
+        // VP8X announcing EXIF (0x08) and XMP (0x04)
+        body.extend(webp_chunk(b"VP8X", &[0x0C, 0, 0, 0, 0, 0, 0, 0, 0, 0]));
+        // Odd-sized image data, padded
+        body.extend(webp_chunk(b"VP8L", &[0x2F, 0, 0, 0, 0]));
+        body.extend(webp_chunk(b"EXIF", &sample_exif()));
+        body.extend(webp_chunk(b"XMP ", b"<x:xmpmeta/>"));
+
+        let mut webp = b"RIFF".to_vec();
+        webp.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        webp.extend(body);
+        webp
+    }
+
+    #[test]
+    fn strip_webp_removes_metadata_and_clears_flags() {
+        let stripped = strip_webp_metadata(&sample_webp(), &[]).unwrap();
+
+        let mut expected = b"WEBP".to_vec();
+        expected.extend(webp_chunk(b"VP8X", &[0, 0, 0, 0, 0, 0, 0, 0, 0, 0]));
+        expected.extend(webp_chunk(b"VP8L", &[0x2F, 0, 0, 0, 0]));
+        assert_eq!(&stripped[8..], &expected[..]);
+        assert_eq!(
+            u32::from_le_bytes(stripped[4..8].try_into().unwrap()) as usize,
+            stripped.len() - 8
+        );
+    }
+
+    #[test]
+    fn strip_webp_keeps_exif_flag_for_kept_tags() {
+        let stripped = strip_webp_metadata(&sample_webp(), &[EXIF_TAG_ORIENTATION]).unwrap();
+        assert_eq!(stripped[20], 0x08);
+        assert!(contains(&stripped, b"EXIF"));
+        assert!(!contains(&stripped, b"XMP "));
+        assert_eq!(strip_webp_metadata(b"RIFF\0\0\0\0WAVE", &[]), None);
+    }
+
+    #[test]
+    fn text_stats_count_korean_and_fullwidth_latin_as_words() {
+        let stats = TextStats::from_text("안녕하세요 세계 ＡＢＣ ok");
+        assert_eq!(stats.words, 4);
+        assert_eq!(stats.cjk_characters, 0);
+
+        let stats = TextStats::from_text("日本語の text");
+        assert_eq!(stats.words, 1);
+        assert_eq!(stats.cjk_characters, 4);
+    }
+
+    #[test]
+    fn inline_tag_commas_become_spaces() {
+        assert_eq!(inline_tag_name(" a, b "), "a b");
+        assert_eq!(inline_tag_name("x/y"), "x/y");
+        assert_eq!(inline_tag_name(",,"), "");
+    }
+
+    fn graph_node(id: &str) -> GraphNode {
+        GraphNode {
+            id: id.to_string(),
+            kind: "note",
+            title: String::new(),
+            url: String::new(),
+            tags: Vec::new(),
+            created: String::new(),
+            updated: String::new(),
+            connections: 0,
+        }
+    }
+
+    // Adjacency lists of fully linked groups of nodes, numbered one group after the other
+    fn cliques(sizes: &[usize]) -> Vec<Vec<(usize, f64)>> {
+        let mut adjacency = vec![Vec::new(); sizes.iter().sum()];
+        let mut start = 0;
+        for &size in sizes {
+            let group = start..start + size;
+            for (a, links) in adjacency.iter_mut().enumerate().skip(start).take(size) {
+                links.extend(group.clone().filter(|&b| b != a).map(|b| (b, 1.0)));
+            }
+            start += size;
+        }
+        adjacency
+    }
+
+    #[test]
+    fn detect_communities_splits_disjoint_cliques() {
+        let communities = detect_communities(&cliques(&[5, 4]));
+        assert!(communities[..5].iter().all(|&c| c == communities[0]));
+        assert!(communities[5..].iter().all(|&c| c == communities[5]));
+        assert_ne!(communities[0], communities[5]);
+        // Numbered from the largest community
+        assert_eq!(communities[0], 0);
+    }
+
+    #[test]
+    fn force_layout_pulls_coincident_nodes_apart() {
+        let adjacency = cliques(&[3, 3]);
+        let links = [
+            (0, 1, 1.0),
+            (0, 2, 1.0),
+            (1, 2, 1.0),
+            (3, 4, 1.0),
+            (3, 5, 1.0),
+            (4, 5, 1.0),
+        ];
+        let positions = force_layout(&links, &adjacency, vec![(0.0, 0.0); 6], 50);
+        assert!(
+            positions
+                .iter()
+                .all(|(x, y)| x.is_finite() && y.is_finite())
+        );
+        assert!(
+            positions[1..]
+                .iter()
+                .any(|&position| position != positions[0])
+        );
+    }
+
+    #[test]
+    fn layout_graph_is_the_same_every_run() {
+        let ids: Vec<String> = (0..40).map(|i| format!("n{:02}", i)).collect();
+        let nodes: Vec<GraphNode> = ids.iter().map(|id| graph_node(id)).collect();
+        let edges: Vec<(&str, &str, &'static str, usize)> = (0..40)
+            .flat_map(|i| [(i, (i + 1) % 40), (i, (i * 7) % 40)])
+            .map(|(a, b)| (ids[a].as_str(), ids[b].as_str(), "ref", 1))
+            .collect();
+
+        let first = layout_graph(&nodes, &edges, 100);
+        let second = layout_graph(&nodes, &edges, 100);
+        assert_eq!(first.positions, second.positions);
+        assert_eq!(first.communities, second.communities);
+        assert_eq!(first.lod, second.lod);
+    }
+
+    #[test]
+    fn layout_graph_lod_tiers_follow_connections() {
+        // 600 nodes: the top 50 by links show at any zoom and the top 500 from medium zoom.
+        // The last node links to the nine before it, which puts all ten among the top 50.
+        let ids: Vec<String> = (0..600).map(|i| format!("n{:03}", i)).collect();
+        let nodes: Vec<GraphNode> = ids.iter().map(|id| graph_node(id)).collect();
+        let edges: Vec<(&str, &str, &'static str, usize)> = (590..599)
+            .map(|i| (ids[599].as_str(), ids[i].as_str(), "ref", 1))
+            .collect();
+
+        let layout = layout_graph(&nodes, &edges, 1);
+        let tier_sizes = (0..3)
+            .map(|tier| layout.lod.iter().filter(|&&lod| lod == tier).count())
+            .collect::<Vec<_>>();
+        assert_eq!(tier_sizes, [50, 450, 100]);
+        assert!(layout.lod[590..].iter().all(|&lod| lod == 0));
+        assert!(layout.lod[..40].iter().all(|&lod| lod == 0));
+        assert_eq!(layout.lod[40], 1);
+        assert_eq!(layout.lod[589], 2);
+    }
+
+    fn links(pairs: &[(&str, &str)]) -> HashMap<String, HashSet<String>> {
+        let mut adjacency: HashMap<String, HashSet<String>> = HashMap::new();
+        for (source, target) in pairs {
+            adjacency
+                .entry(source.to_string())
+                .or_default()
+                .insert(target.to_string());
+        }
+        adjacency
+    }
+
+    fn id_set(ids: &[&str]) -> HashSet<String> {
+        ids.iter().map(|id| id.to_string()).collect()
+    }
+
+    #[test]
+    fn collect_reachable_notes_follows_cycles_once() {
+        let adjacency = links(&[("a", "b"), ("b", "c"), ("c", "a"), ("c", "c"), ("d", "a")]);
+        let seeds = ["a".to_string()];
+        assert_eq!(
+            collect_reachable_notes(&seeds, &adjacency, None),
+            id_set(&["a", "b", "c"])
+        );
+        assert_eq!(
+            collect_reachable_notes(&seeds, &adjacency, Some(1)),
+            id_set(&["a", "b"])
+        );
+        assert_eq!(
+            collect_reachable_notes(&seeds, &adjacency, Some(0)),
+            id_set(&["a"])
+        );
+    }
+
+    #[test]
+    fn collect_reachable_notes_counts_hops_from_the_nearest_seed() {
+        let adjacency = links(&[("a", "b"), ("b", "c"), ("c", "d"), ("x", "c")]);
+        let seeds = ["a".to_string(), "x".to_string(), "a".to_string()];
+        assert_eq!(
+            collect_reachable_notes(&seeds, &adjacency, Some(1)),
+            id_set(&["a", "b", "x", "c"])
+        );
+    }
+
+    #[test]
+    fn percent_decode_keeps_malformed_escapes() {
+        assert_eq!(percent_decode("a%20b%2Fc"), "a b/c");
+        assert_eq!(percent_decode("%E4%B8%AD.png"), "中.png");
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%4"), "%4");
+        assert_eq!(percent_decode("%zz%+1%-1"), "%zz%+1%-1");
+        assert_eq!(percent_decode("%%41"), "%A");
+        assert_eq!(percent_decode("%中"), "%中");
+        // Bytes that don't form UTF-8 become replacement characters
+        assert_eq!(percent_decode("%FF"), "\u{FFFD}");
+    }
+
+    #[test]
+    fn slugify_collapses_separators() {
+        assert_eq!(slugify("  Hello, World! ", false), "Hello-World");
+        assert_eq!(
+            slugify("snake_case--and  more", false),
+            "snake_case-and-more"
+        );
+        assert_eq!(slugify("中文 测试", false), "中文-测试");
+        assert_eq!(slugify("中文 测试", true), "Zhong-Wen-Ce-Shi");
+        assert_eq!(slugify("?!", false), "");
+        assert_eq!(tag_slug("a/ b /", false), "a__b");
+    }
+
+    #[test]
+    fn claim_slug_numbers_collisions_ignoring_case() {
+        let mut used = HashSet::new();
+        assert_eq!(claim_slug("note", &mut used), "note");
+        assert_eq!(claim_slug("Note", &mut used), "Note-2");
+        assert_eq!(claim_slug("note-3", &mut used), "note-3");
+        // A numbered slug already taken by another page is skipped
+        assert_eq!(claim_slug("note", &mut used), "note-4");
+        assert_eq!(claim_slug("note-2", &mut used), "note-2-2");
+    }
+
+    fn site_urls(pretty: bool, root: &str) -> SiteUrls {
+        SiteUrls {
+            note_slugs: HashMap::new(),
+            tag_slugs: HashMap::new(),
+            encode_links: false,
+            pretty,
+            root: root.to_string(),
+        }
+    }
+
+    #[test]
+    fn redirect_file_stays_inside_the_output() {
+        let urls = site_urls(false, "");
+        for path in [
+            "..",
+            "../x.html",
+            "/a/../../b.html",
+            "a/..",
+            "..\\x.html",
+            "a\\..\\..\\b",
+        ] {
+            assert_eq!(redirect_file(path, &urls), None, "{}", path);
+        }
+        assert_eq!(redirect_file("", &urls), None);
+        assert_eq!(redirect_file("/", &urls), None);
+        assert_eq!(redirect_file("https://example.com/a", &urls), None);
+        // Dots inside a name are fine
+        assert_eq!(
+            redirect_file("/a..b.html", &urls).as_deref(),
+            Some("a..b.html")
+        );
+    }
+
+    #[test]
+    fn redirect_file_names_pages_like_the_site() {
+        let flat = site_urls(false, "");
+        assert_eq!(
+            redirect_file(" /old.html ", &flat).as_deref(),
+            Some("old.html")
+        );
+        assert_eq!(
+            redirect_file("/posts/old/", &flat).as_deref(),
+            Some("posts/old/index.html")
+        );
+        assert_eq!(redirect_file("old", &flat).as_deref(), Some("old.html"));
+
+        let pretty = site_urls(true, "/blog/");
+        assert_eq!(
+            redirect_file("/blog/old", &pretty).as_deref(),
+            Some("old/index.html")
+        );
+        assert_eq!(
+            redirect_file("/other/old.htm", &pretty).as_deref(),
+            Some("other/old.htm")
+        );
+    }
+
+    // Notes with the given (ID, title, aliases)
+    fn notes(entries: &[(&str, &str, &str)]) -> HashMap<String, Note> {
+        entries
+            .iter()
+            .map(|(id, title, alias)| {
+                let note = json!({"ID": id, "Properties": {"title": title, "alias": alias}});
+                (id.to_string(), serde_json::from_value(note).unwrap())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn fold_case_keeps_byte_offsets() {
+        assert_eq!(fold_case("ÜBER Straße"), "über straße");
+        // These change length when lowercased, so they stay as they are
+        for text in ["İstanbul", "ẞ", "Ⱥ", "\u{212A}elvin"] {
+            assert_eq!(fold_case(text).len(), text.len(), "{}", text);
+        }
+        assert_eq!(fold_case("İSTANBUL"), "İstanbul");
+    }
+
+    #[test]
+    fn mention_matcher_finds_names_after_length_changing_characters() {
+        let notes_map = notes(&[("a", "Über", ""), ("b", "Rust", "rust-lang, RL")]);
+        let matcher = MentionMatcher::new(&notes_map, 2, &[], &[]).unwrap();
+
+        let text = "İẞȺ über RUST, rustacean and RL";
+        let found: Vec<_> = matcher
+            .find(text)
+            .into_iter()
+            .map(|(start, end, id)| (&text[start..end], id))
+            .collect();
+        assert_eq!(found, [("über", "a"), ("RUST", "b"), ("RL", "b")]);
+    }
+
+    #[test]
+    fn mention_matcher_respects_word_boundaries_except_in_cjk() {
+        let notes_map = notes(&[("a", "Graph", ""), ("b", "图谱", "")]);
+        let matcher = MentionMatcher::new(&notes_map, 2, &[], &[]).unwrap();
+
+        assert!(matcher.find("graphs and subgraph").is_empty());
+        assert_eq!(matcher.find("a graph.").len(), 1);
+        assert_eq!(matcher.find("知识图谱很有用").len(), 1);
+    }
+
+    #[test]
+    fn mention_matcher_skips_names_shared_by_several_notes() {
+        let notes_map = notes(&[("a", "Home", ""), ("b", "home", ""), ("c", "Garden", "")]);
+        let matcher = MentionMatcher::new(&notes_map, 2, &[], &[]).unwrap();
+        let ids: Vec<_> = matcher.find("home garden").iter().map(|m| m.2).collect();
+        assert_eq!(ids, ["c"]);
+    }
+}